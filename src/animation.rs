@@ -0,0 +1,249 @@
+// The image encoders write through `std::io::Write` even without the std
+// feature, so it's taken from them for encoding into memory.
+#[cfg(feature = "image-alloc")]
+extern crate std;
+
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+
+#[cfg(any(feature = "image-std", feature = "image-alloc"))]
+use {
+    crate::error::{Error, Result},
+    gif::{Frame, Repeat},
+    png::{BitDepth, ColorType},
+    std::io::Write,
+    tiny_skia::Pixmap,
+};
+
+#[cfg(feature = "webp")]
+use image_webp::WebPEncoder;
+
+/// The kinds of file an animation can be written as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnimationFormat {
+    /// A GIF, limited to 256 colors.
+    Gif,
+    /// An animated PNG, with full color and transparency.
+    Apng,
+    /// A lossless animated WebP.
+    WebP,
+    /// A directory of numbered PNG images, one for each frame.
+    PngSequence,
+}
+
+impl AnimationFormat {
+    /// Picks the format for a file extension, if it's one animations can be
+    /// written as.
+    pub fn from_extension(extension: &str) -> Option<AnimationFormat> {
+        match extension.to_lowercase().as_str() {
+            "gif" => Some(AnimationFormat::Gif),
+            "png" | "apng" => Some(AnimationFormat::Apng),
+            "webp" => Some(AnimationFormat::WebP),
+            _ => None,
+        }
+    }
+}
+
+// Encodes the frames of an animation one at a time. Delays are in
+// milliseconds and a loop count of zero plays forever.
+#[cfg(any(feature = "image-std", feature = "image-alloc"))]
+pub(crate) enum AnimationWriter<W: Write> {
+    Gif {
+        encoder: gif::Encoder<W>,
+        delay: u32,
+    },
+    Apng(png::Writer<W>),
+    // WebP files start with their total size, so the frames are held until
+    // the end. They're compressed by then, so this takes far less memory
+    // than the pixmaps would.
+    #[cfg(feature = "webp")]
+    WebP {
+        writer: W,
+        width: u32,
+        height: u32,
+        delay: u32,
+        loops: u16,
+        frames: Vec<u8>,
+    },
+}
+
+#[cfg(any(feature = "image-std", feature = "image-alloc"))]
+impl<W: Write> AnimationWriter<W> {
+    // PNG sequences aren't a single file, so they're encoded as a GIF.
+    pub(crate) fn new(
+        writer: W,
+        format: AnimationFormat,
+        width: u32,
+        height: u32,
+        frames: usize,
+        delay: u32,
+        loops: u16,
+    ) -> Result<Self> {
+        match format {
+            AnimationFormat::Apng => Self::apng(writer, width, height, frames, delay, loops),
+            #[cfg(feature = "webp")]
+            AnimationFormat::WebP => Self::webp(writer, width, height, delay, loops),
+            #[cfg(not(feature = "webp"))]
+            AnimationFormat::WebP => Err(Error::UnsupportedFormat("webp".into(), "webp".into())),
+            AnimationFormat::Gif | AnimationFormat::PngSequence => {
+                Self::gif(writer, width, height, delay, loops)
+            }
+        }
+    }
+
+    fn gif(writer: W, width: u32, height: u32, delay: u32, loops: u16) -> Result<Self> {
+        let mut encoder =
+            gif::Encoder::new(writer, width as u16, height as u16, &[]).map_err(Error::GifError)?;
+        // GIFs count the times they repeat after the first play.
+        let repeat = match loops {
+            0 => Repeat::Infinite,
+            loops => Repeat::Finite(loops - 1),
+        };
+        encoder.set_repeat(repeat).map_err(Error::GifError)?;
+        Ok(AnimationWriter::Gif { encoder, delay })
+    }
+
+    // APNG needs to know how many frames there will be before the first.
+    fn apng(
+        writer: W,
+        width: u32,
+        height: u32,
+        frames: usize,
+        delay: u32,
+        loops: u16,
+    ) -> Result<Self> {
+        let mut encoder = png::Encoder::new(writer, width, height);
+        encoder.set_color(ColorType::Rgba);
+        encoder.set_depth(BitDepth::Eight);
+        encoder
+            .set_animated(frames as u32, loops as u32)
+            .map_err(Error::PngError)?;
+        // Delays are a fraction of a second, which can't go past 65535 / 1000.
+        let delay = match u16::try_from(delay) {
+            Ok(delay) => (delay, 1000),
+            Err(_) => ((delay / 100).min(u16::MAX as u32) as u16, 10),
+        };
+        encoder
+            .set_frame_delay(delay.0, delay.1)
+            .map_err(Error::PngError)?;
+        let writer = encoder.write_header().map_err(Error::PngError)?;
+        Ok(AnimationWriter::Apng(writer))
+    }
+
+    #[cfg(feature = "webp")]
+    fn webp(writer: W, width: u32, height: u32, delay: u32, loops: u16) -> Result<Self> {
+        Ok(AnimationWriter::WebP {
+            writer,
+            width,
+            height,
+            delay,
+            loops,
+            frames: Vec::new(),
+        })
+    }
+
+    pub(crate) fn add_frame(&mut self, pixmap: &Pixmap) -> Result<()> {
+        let mut data = rgba(pixmap);
+        match self {
+            AnimationWriter::Gif { encoder, delay } => {
+                let mut frame =
+                    Frame::from_rgba(pixmap.width() as u16, pixmap.height() as u16, &mut data);
+                // GIF delays are in hundredths of a second.
+                frame.delay = ((*delay + 5) / 10).min(u16::MAX as u32) as u16;
+                encoder.write_frame(&frame).map_err(Error::GifError)
+            }
+            AnimationWriter::Apng(writer) => {
+                writer.write_image_data(&data).map_err(Error::PngError)
+            }
+            #[cfg(feature = "webp")]
+            AnimationWriter::WebP {
+                width,
+                height,
+                delay,
+                frames,
+                ..
+            } => {
+                let mut image = Vec::new();
+                WebPEncoder::new(&mut image)
+                    .encode(&data, *width, *height, image_webp::ColorType::Rgba8)
+                    .map_err(Error::WebPError)?;
+
+                let mut frame = Vec::new();
+                // The frame covers the whole canvas from the top left.
+                frame.extend_from_slice(&[0; 6]);
+                frame.extend_from_slice(&(*width - 1).to_le_bytes()[..3]);
+                frame.extend_from_slice(&(*height - 1).to_le_bytes()[..3]);
+                frame.extend_from_slice(&(*delay).min(0xffffff).to_le_bytes()[..3]);
+                // Replace the last frame instead of blending over it, so
+                // transparent parts stay transparent.
+                frame.push(0b10);
+                // Skip the RIFF header of the still image to get its VP8L chunk.
+                frame.extend_from_slice(&image[12..]);
+                write_chunk(frames, b"ANMF", &frame);
+                Ok(())
+            }
+        }
+    }
+
+    pub(crate) fn finish(self) -> Result<()> {
+        match self {
+            AnimationWriter::Gif { encoder, .. } => {
+                encoder.into_inner().map_err(Error::GifError)?;
+                Ok(())
+            }
+            AnimationWriter::Apng(writer) => writer.finish().map_err(Error::PngError),
+            #[cfg(feature = "webp")]
+            AnimationWriter::WebP {
+                mut writer,
+                width,
+                height,
+                loops,
+                frames,
+                ..
+            } => {
+                let mut header = Vec::new();
+                let mut canvas = vec![0b10010, 0, 0, 0];
+                canvas.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+                canvas.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+                write_chunk(&mut header, b"VP8X", &canvas);
+                // A transparent background, then the loop count.
+                let mut animation = vec![0; 4];
+                animation.extend_from_slice(&loops.to_le_bytes());
+                write_chunk(&mut header, b"ANIM", &animation);
+
+                let size = 4 + header.len() + frames.len();
+                writer
+                    .write_all(b"RIFF")
+                    .and_then(|_| writer.write_all(&(size as u32).to_le_bytes()))
+                    .and_then(|_| writer.write_all(b"WEBP"))
+                    .and_then(|_| writer.write_all(&header))
+                    .and_then(|_| writer.write_all(&frames))
+                    .map_err(Error::FileError)
+            }
+        }
+    }
+}
+
+// Pixmaps store their colors premultiplied by alpha, which image formats
+// don't expect.
+#[cfg(any(feature = "image-std", feature = "image-alloc"))]
+fn rgba(pixmap: &Pixmap) -> Vec<u8> {
+    pixmap
+        .pixels()
+        .iter()
+        .flat_map(|pixel| {
+            let color = pixel.demultiply();
+            [color.red(), color.green(), color.blue(), color.alpha()]
+        })
+        .collect()
+}
+
+#[cfg(feature = "webp")]
+fn write_chunk(buf: &mut Vec<u8>, name: &[u8; 4], data: &[u8]) {
+    buf.extend_from_slice(name);
+    buf.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    buf.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        buf.push(0);
+    }
+}
@@ -0,0 +1,867 @@
+#[cfg(feature = "alloc")]
+use alloc::{
+    boxed::Box,
+    collections::BTreeMap,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+
+#[cfg(feature = "std")]
+use std::collections::BTreeMap;
+
+use crate::error::Error;
+use crate::functions::{builtin_param_count, BUILTIN_FUNCTIONS, HIGHER_ORDER_FUNCTIONS};
+use crate::interpreter::{NativeFunction, ValueKind};
+use crate::parser::{BinaryOperator, Definition, Literal, Pattern, Token, Tree, UnaryOperator};
+
+use hashbrown::HashMap;
+
+// Names bound by `let`, patterns or parameters, along with whatever is known
+// about their values. Mirrors the frames the interpreter pushes, so a name is
+// only visible where it would be at runtime.
+#[derive(Debug, Default)]
+struct Scope {
+    names: HashMap<String, ValueKind>,
+    is_let: bool,
+}
+
+struct Checker<'t, 'a> {
+    // Top-level definitions and how many parameters they take.
+    globals: &'t HashMap<&'a str, usize>,
+    // What each top-level definition evaluates to, as far as is known.
+    returns: &'t HashMap<&'a str, ValueKind>,
+    natives: &'t HashMap<&'t str, usize>,
+    definition: &'t Definition<'a>,
+    scopes: Vec<Scope>,
+    errors: Vec<Error>,
+}
+
+fn literal_kind(literal: &Literal) -> ValueKind {
+    match literal {
+        Literal::Integer(_) => ValueKind::Integer,
+        Literal::Float(_) => ValueKind::Float,
+        Literal::Complex(_) => ValueKind::Complex,
+        Literal::Boolean(_) => ValueKind::Boolean,
+        Literal::Hex(_) => ValueKind::Hex,
+        Literal::Char(_) => ValueKind::Char,
+        Literal::String(_) => ValueKind::String,
+        Literal::Shape(_) => ValueKind::Shape,
+        _ => ValueKind::Enum,
+    }
+}
+
+// Whether two kinds could belong to the same value, treating anything unknown
+// as a match.
+fn compatible(a: &ValueKind, b: &ValueKind) -> bool {
+    match (a, b) {
+        (ValueKind::Unknown, _) | (_, ValueKind::Unknown) => true,
+        (ValueKind::List(a), ValueKind::List(b)) => compatible(a, b),
+        (ValueKind::Tuple(a), ValueKind::Tuple(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| compatible(a, b))
+        }
+        (ValueKind::Record(a), ValueKind::Record(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .zip(b)
+                    .all(|((a_name, a), (b_name, b))| a_name == b_name && compatible(a, b))
+        }
+        (a, b) => a == b,
+    }
+}
+
+fn is_number(kind: &ValueKind) -> bool {
+    matches!(kind, ValueKind::Integer | ValueKind::Float)
+}
+
+// Whether matching a value of `kind` against the pattern could be attempted
+// without a type error. Patterns of the right type that simply don't match,
+// like tuples of a different size, are fine.
+fn pattern_fits(kind: &ValueKind, pattern: &Pattern) -> bool {
+    match (kind, pattern) {
+        (ValueKind::Unknown, _) | (_, Pattern::Wildcard | Pattern::Bind(_)) => true,
+        (kind, Pattern::Matches(literals)) => literals.iter().all(|literal| {
+            let literal = literal_kind(literal);
+            (is_number(kind) && is_number(&literal)) || compatible(kind, &literal)
+        }),
+        (kind, Pattern::Range(start, end, _)) => match (literal_kind(start), literal_kind(end)) {
+            (ValueKind::Char, ValueKind::Char) => *kind == ValueKind::Char,
+            (start, end) => is_number(kind) && is_number(&start) && is_number(&end),
+        },
+        (ValueKind::Tuple(kinds), Pattern::Tuple(patterns)) => {
+            kinds.len() != patterns.len()
+                || kinds
+                    .iter()
+                    .zip(patterns)
+                    .all(|(kind, pattern)| pattern_fits(kind, pattern))
+        }
+        // The rest of a list can only be bound to a name, which always fits.
+        (ValueKind::List(kind), Pattern::List(patterns, _)) => {
+            patterns.iter().all(|pattern| pattern_fits(kind, pattern))
+        }
+        (ValueKind::String, Pattern::List(patterns, _)) => patterns
+            .iter()
+            .all(|pattern| pattern_fits(&ValueKind::Char, pattern)),
+        (ValueKind::Record(fields), Pattern::Record(patterns)) => {
+            patterns
+                .iter()
+                .all(|(name, pattern)| match fields.get(name) {
+                    Some(kind) => pattern_fits(kind, pattern),
+                    None => true,
+                })
+        }
+        _ => false,
+    }
+}
+
+// Collects the names a pattern binds, with whatever can be told about their
+// values from the kind being matched.
+fn bind_kinds(kind: &ValueKind, pattern: &Pattern, names: &mut HashMap<String, ValueKind>) {
+    match pattern {
+        Pattern::Bind(name) => {
+            names.insert(name.clone(), kind.clone());
+        }
+        Pattern::Tuple(patterns) => {
+            for (i, pattern) in patterns.iter().enumerate() {
+                let kind = match kind {
+                    ValueKind::Tuple(kinds) => kinds.get(i).cloned(),
+                    _ => None,
+                };
+                bind_kinds(&kind.unwrap_or(ValueKind::Unknown), pattern, names);
+            }
+        }
+        Pattern::List(patterns, rest) => {
+            let elem = match kind {
+                ValueKind::List(kind) => (**kind).clone(),
+                ValueKind::String => ValueKind::Char,
+                _ => ValueKind::Unknown,
+            };
+            for pattern in patterns {
+                bind_kinds(&elem, pattern, names);
+            }
+            if let Some(rest) = rest {
+                bind_kinds(kind, rest, names);
+            }
+        }
+        Pattern::Record(patterns) => {
+            for (name, pattern) in patterns {
+                let kind = match kind {
+                    ValueKind::Record(fields) => fields.get(name).cloned(),
+                    _ => None,
+                };
+                bind_kinds(&kind.unwrap_or(ValueKind::Unknown), pattern, names);
+            }
+        }
+        Pattern::Matches(_) | Pattern::Range(..) | Pattern::Wildcard => (),
+    }
+}
+
+// Builtins that can give back a function, and so may be passed more arguments
+// than they take.
+fn returns_function(name: &str) -> bool {
+    HIGHER_ORDER_FUNCTIONS.contains(&name) || matches!(name, "|>" | "pipe")
+}
+
+const NUMBER: &[ValueKind] = &[ValueKind::Integer, ValueKind::Float];
+const NUMERIC: &[ValueKind] = &[ValueKind::Integer, ValueKind::Float, ValueKind::Complex];
+const SHAPE: &[ValueKind] = &[ValueKind::Shape];
+const TRANSFORMABLE: &[ValueKind] = &[ValueKind::Shape, ValueKind::Gradient];
+const ANY: &[ValueKind] = &[];
+
+// The kinds each argument of a builtin can be, for the builtins that always
+// take the same kinds. An empty list lets anything through.
+fn signature(name: &str) -> Option<&'static [&'static [ValueKind]]> {
+    let signature: &[&[ValueKind]] = match name {
+        "+" | "add" | "-" | "sub" | "*" | "mul" | "/" | "div" | "%" | "mod" | "**" | "pow" => {
+            &[NUMERIC, NUMERIC]
+        }
+        "&" | "bitand" | "|" | "bitor" | "^" | "bitxor" | "<<" | "bitleft" | ">>" | "bitright" => {
+            &[&[ValueKind::Integer], &[ValueKind::Integer]]
+        }
+        "&&" | "and" | "||" | "or" => &[&[ValueKind::Boolean], &[ValueKind::Boolean]],
+        "!" | "not" => &[&[ValueKind::Boolean]],
+        "neg" | "sin" | "cos" | "tan" | "asin" | "acos" | "atan" | "sinh" | "cosh" | "tanh"
+        | "asinh" | "acosh" | "atanh" | "ln" | "log10" | "abs" | "sqrt" | "cbrt" => &[NUMERIC],
+        "~" | "bitnot" | "floor" | "ceil" | "fact" | "fact2" | "deg_to_rad" | "rad_to_deg" => {
+            &[NUMBER]
+        }
+        "atan2" | "min" | "max" => &[NUMBER, NUMBER],
+        "real" | "imag" => &[&[ValueKind::Complex]],
+        "t" | "translate" | "s" | "scale" | "k" | "skew" => &[NUMBER, NUMBER, TRANSFORMABLE],
+        "tx" | "translatex" | "ty" | "translatey" | "tt" | "translateb" | "r" | "rotate" | "sx"
+        | "scalex" | "sy" | "scaley" | "ss" | "scaleb" | "kx" | "skewx" | "ky" | "skewy" | "kk"
+        | "skewb" | "f" | "flip" => &[NUMBER, TRANSFORMABLE],
+        "ra" | "rotate_at" => &[ANY, ANY, ANY, TRANSFORMABLE],
+        "fh" | "fliph" | "fv" | "flipv" | "fd" | "flipd" => &[TRANSFORMABLE],
+        "z" | "zindex" | "zshift" | "h" | "hue" | "sat" | "saturation" | "l" | "lightness"
+        | "a" | "alpha" | "hshift" | "satshift" | "lshift" | "ashift" => &[NUMBER, SHAPE],
+        "hsl" => &[ANY, ANY, ANY, SHAPE],
+        "hsla" => &[ANY, ANY, ANY, ANY, SHAPE],
+        "hex" => &[&[ValueKind::Hex], SHAPE],
+        "g" | "gradient" => &[&[ValueKind::Gradient], SHAPE],
+        ":" | "compose" | "mask" => &[SHAPE, SHAPE],
+        "blend" | "line_cap" | "line_join" => &[&[ValueKind::Enum], SHAPE],
+        "anti_alias" => &[&[ValueKind::Boolean], SHAPE],
+        "stroke" | "miter_limit" => &[ANY, SHAPE],
+        "solid" | "fill" | "winding" | "even_odd" | "no_dash" => &[SHAPE],
+        _ => return None,
+    };
+    Some(signature)
+}
+
+// Whether a builtin could be given arguments of these kinds, treating anything
+// unknown as a match. Only as many arguments as are given are checked, so
+// partial applications can be too.
+fn arguments_fit(name: &str, args: &[ValueKind]) -> bool {
+    let fits = |kinds: &[ValueKind], kind: &ValueKind| {
+        *kind == ValueKind::Unknown || kinds.is_empty() || kinds.contains(kind)
+    };
+
+    match name {
+        // Numbers compare with numbers and chars with chars.
+        "<" | "lt" | "<=" | "lte" | ">" | "gt" | ">=" | "gte" => {
+            let number = args.iter().all(|kind| fits(NUMBER, kind));
+            let char = args.iter().all(|kind| fits(&[ValueKind::Char], kind));
+            number || char
+        }
+        _ => match signature(name) {
+            Some(signature) => args
+                .iter()
+                .zip(signature)
+                .all(|(kind, kinds)| fits(kinds, kind)),
+            None => true,
+        },
+    }
+}
+
+fn pop(operands: &mut Vec<ValueKind>) -> ValueKind {
+    operands.pop().unwrap_or(ValueKind::Unknown)
+}
+
+fn pop_n(operands: &mut Vec<ValueKind>, n: usize) -> Vec<ValueKind> {
+    let mut values = (0..n).map(|_| pop(operands)).collect::<Vec<_>>();
+    values.reverse();
+    values
+}
+
+impl Checker<'_, '_> {
+    fn error(&mut self, error: Error, index: usize) {
+        let span = self.definition.spans.get(index).copied();
+        self.errors
+            .push(error.located(span, Some(self.definition.name)));
+    }
+
+    fn lookup(&self, name: &str) -> Option<&ValueKind> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.names.get(name))
+    }
+
+    // Inserts into the innermost `let` frame, starting one if needed.
+    fn bind_let(&mut self, names: HashMap<String, ValueKind>) {
+        match self.scopes.last_mut() {
+            Some(scope) if scope.is_let => scope.names.extend(names),
+            _ => self.scopes.push(Scope {
+                names,
+                is_let: true,
+            }),
+        }
+    }
+
+    // Checks the body of a local function or lambda, returning what it
+    // evaluates to.
+    fn walk_function(&mut self, params: &[&str], start: usize, end: usize) -> ValueKind {
+        let names = params
+            .iter()
+            .map(|param| ((*param).into(), ValueKind::Unknown))
+            .collect();
+        let depth = self.scopes.len();
+        self.scopes.push(Scope {
+            names,
+            is_let: false,
+        });
+        let kind = self.walk(start, end);
+        self.scopes.truncate(depth);
+        kind
+    }
+
+    // Applies something of `kind` to `argc` arguments.
+    fn apply(&mut self, kind: ValueKind, argc: usize, index: usize) -> ValueKind {
+        match kind {
+            ValueKind::Function(arity) if argc < arity => ValueKind::Function(arity - argc),
+            ValueKind::Function(_) | ValueKind::Unknown => ValueKind::Unknown,
+            kind if argc == 0 => kind,
+            _ => {
+                self.error(Error::NotCallable, index);
+                ValueKind::Unknown
+            }
+        }
+    }
+
+    fn call(&mut self, name: &str, args: &[ValueKind], index: usize) -> ValueKind {
+        let argc = args.len();
+        if BUILTIN_FUNCTIONS.contains(&name) {
+            let arity = builtin_param_count(name);
+            if argc > arity && !returns_function(name) {
+                self.error(Error::TooManyArguments(name.into(), arity), index);
+            } else if !arguments_fit(name, args) {
+                self.error(Error::InvalidArgument(name.into()), index);
+            }
+            return match argc < arity {
+                true => ValueKind::Function(arity - argc),
+                false => ValueKind::Unknown,
+            };
+        }
+
+        if let Some(kind) = self.lookup(name).cloned() {
+            return self.apply(kind, argc, index);
+        }
+
+        if let Some(&arity) = self.globals.get(name) {
+            // Extra arguments go to whatever the definition gives back.
+            let kind = self
+                .returns
+                .get(name)
+                .cloned()
+                .unwrap_or(ValueKind::Unknown);
+            return match kind {
+                _ if argc < arity => ValueKind::Function(arity - argc),
+                kind if argc == arity => kind,
+                ValueKind::Function(_) | ValueKind::Unknown => {
+                    self.apply(kind, argc - arity, index)
+                }
+                _ => {
+                    self.error(Error::TooManyArguments(name.into(), arity), index);
+                    ValueKind::Unknown
+                }
+            };
+        }
+
+        if let Some(&arity) = self.natives.get(name) {
+            return match argc < arity {
+                true => ValueKind::Function(arity - argc),
+                false => ValueKind::Unknown,
+            };
+        }
+
+        // `record.field` reads a field of whatever `record` evaluates to.
+        if let Some((head, fields)) = name.split_once('.') {
+            let head = if BUILTIN_FUNCTIONS.contains(&head) {
+                Some(ValueKind::Unknown)
+            } else if let Some(kind) = self.lookup(head) {
+                Some(kind.clone())
+            } else if self.globals.contains_key(head) || self.natives.contains_key(head) {
+                Some(ValueKind::Unknown)
+            } else {
+                None
+            };
+
+            if let Some(mut kind) = head {
+                for field in fields.split('.') {
+                    kind = match kind {
+                        ValueKind::Record(mut record) => match record.remove(field) {
+                            Some(kind) => kind,
+                            None => {
+                                self.error(Error::UnknownField(field.into()), index);
+                                ValueKind::Unknown
+                            }
+                        },
+                        ValueKind::Unknown => ValueKind::Unknown,
+                        _ => {
+                            self.error(Error::NotARecord, index);
+                            ValueKind::Unknown
+                        }
+                    };
+                }
+                return self.apply(kind, argc, index);
+            }
+        }
+
+        self.error(Error::UnknownFunction(name.into()), index);
+        ValueKind::Unknown
+    }
+
+    // Steps through the tokens from `index` to `end` the way the interpreter
+    // would, keeping track of the kinds of values instead of the values.
+    fn walk(&mut self, mut index: usize, end: usize) -> ValueKind {
+        let block = &self.definition.block;
+        let mut operands = Vec::new();
+        // Branches that jump ahead to the same place, and how many operands
+        // were left when they did.
+        let mut joins: HashMap<usize, Vec<(usize, ValueKind)>> = HashMap::new();
+        // Names bound by match arms, brought into scope where each arm starts.
+        let mut arms: HashMap<usize, HashMap<String, ValueKind>> = HashMap::new();
+
+        let join = |operands: &mut Vec<ValueKind>, branches: Vec<(usize, ValueKind)>| {
+            let depth = branches[0].0;
+            let mut kind = match operands.len() > depth {
+                true => operands.pop(),
+                false => None,
+            };
+            for (_, branch) in branches {
+                kind = match kind {
+                    Some(kind) if kind == branch => Some(kind),
+                    Some(_) => Some(ValueKind::Unknown),
+                    None => Some(branch),
+                };
+            }
+            operands.push(kind.unwrap());
+        };
+
+        while index < end {
+            if let Some(branches) = joins.remove(&index) {
+                join(&mut operands, branches);
+            }
+            if let Some(names) = arms.remove(&index) {
+                self.scopes.push(Scope {
+                    names,
+                    is_let: false,
+                });
+            }
+
+            match &block[index] {
+                Token::Literal(literal) => operands.push(literal_kind(literal)),
+                Token::List(size) => {
+                    let elems = pop_n(&mut operands, *size);
+                    let mut kind = ValueKind::Unknown;
+                    for elem in elems {
+                        if !compatible(&kind, &elem) {
+                            self.error(Error::InvalidList, index);
+                            kind = ValueKind::Unknown;
+                            break;
+                        }
+                        if kind == ValueKind::Unknown {
+                            kind = elem;
+                        }
+                    }
+                    operands.push(match kind {
+                        ValueKind::Char => ValueKind::String,
+                        kind => ValueKind::List(Box::new(kind)),
+                    });
+                }
+                Token::Tuple(size) => {
+                    let elems = pop_n(&mut operands, *size);
+                    operands.push(ValueKind::Tuple(elems));
+                }
+                Token::Record(names) => {
+                    let values = (0..names.len()).map(|_| pop(&mut operands));
+                    let record = names
+                        .iter()
+                        .rev()
+                        .map(|name| name.to_string())
+                        .zip(values)
+                        .collect::<BTreeMap<_, _>>();
+                    operands.push(ValueKind::Record(record));
+                }
+                Token::Update(names) => {
+                    pop_n(&mut operands, names.len());
+                    let record = pop(&mut operands);
+                    match &record {
+                        ValueKind::Record(fields) => {
+                            for name in names {
+                                if !fields.contains_key(*name) {
+                                    self.error(Error::UnknownField((*name).into()), index);
+                                }
+                            }
+                        }
+                        ValueKind::Unknown => (),
+                        _ => self.error(Error::NotARecord, index),
+                    }
+                    operands.push(record);
+                }
+                Token::UnaryOperator(op) => {
+                    let a = pop(&mut operands);
+                    if matches!(a, ValueKind::Function(_)) || !arguments_fit(op.as_str(), &[a]) {
+                        self.error(Error::InvalidArgument(op.as_str().into()), index);
+                    }
+                    operands.push(match op {
+                        UnaryOperator::Not => ValueKind::Boolean,
+                        _ => ValueKind::Unknown,
+                    });
+                }
+                Token::BinaryOperator(op) => {
+                    let b = pop(&mut operands);
+                    let a = pop(&mut operands);
+                    let kind = match op {
+                        BinaryOperator::FunctionComposition => match b {
+                            ValueKind::Function(argc) => ValueKind::Function(argc),
+                            _ => ValueKind::Unknown,
+                        },
+                        BinaryOperator::Pipe => match b {
+                            ValueKind::Function(argc) if argc > 1 => ValueKind::Function(argc - 1),
+                            ValueKind::Function(_) | ValueKind::Unknown => ValueKind::Unknown,
+                            _ => {
+                                self.error(Error::InvalidArgument(op.as_str().into()), index);
+                                ValueKind::Unknown
+                            }
+                        },
+                        _ => {
+                            if matches!(a, ValueKind::Function(_))
+                                || matches!(b, ValueKind::Function(_))
+                                || !arguments_fit(op.as_str(), &[a, b])
+                            {
+                                self.error(Error::InvalidArgument(op.as_str().into()), index);
+                            }
+
+                            match op {
+                                BinaryOperator::EqualTo
+                                | BinaryOperator::NotEqualTo
+                                | BinaryOperator::LessThan
+                                | BinaryOperator::LessThanOrEqualTo
+                                | BinaryOperator::GreaterThan
+                                | BinaryOperator::GreaterThanOrEqualTo
+                                | BinaryOperator::And
+                                | BinaryOperator::Or => ValueKind::Boolean,
+                                BinaryOperator::Composition => ValueKind::Shape,
+                                _ => ValueKind::Unknown,
+                            }
+                        }
+                    };
+                    operands.push(kind);
+                }
+                Token::Call(name, argc) => {
+                    let args = pop_n(&mut operands, *argc);
+                    let kind = self.call(name, &args, index);
+                    operands.push(kind);
+                }
+                Token::Jump(skip) => {
+                    let kind = pop(&mut operands);
+                    joins
+                        .entry(index + skip + 1)
+                        .or_default()
+                        .push((operands.len(), kind));
+                }
+                Token::Pop => {
+                    if self.scopes.len() > 1 {
+                        self.scopes.pop();
+                    }
+                }
+                Token::Return(_) => break,
+                Token::Let(name, params, skip) => {
+                    let arity = match params.len() {
+                        0 => ValueKind::Unknown,
+                        n => ValueKind::Function(n),
+                    };
+                    self.bind_let([((*name).into(), arity)].into_iter().collect());
+
+                    let kind = self.walk_function(params, index + 1, index + skip);
+                    if params.is_empty() {
+                        self.bind_let([((*name).into(), kind)].into_iter().collect());
+                    }
+                    index += skip;
+                }
+                Token::Destructure(pattern) => {
+                    let kind = pop(&mut operands);
+                    if !pattern_fits(&kind, pattern) {
+                        self.error(Error::InvalidMatch, index);
+                    }
+                    let mut names = HashMap::new();
+                    bind_kinds(&kind, pattern, &mut names);
+                    self.bind_let(names);
+                }
+                Token::Lambda(params, skip) => {
+                    self.walk_function(params, index + 1, index + skip);
+                    operands.push(ValueKind::Function(params.len()));
+                    index += skip;
+                }
+                Token::If(_) => {
+                    let condition = pop(&mut operands);
+                    if !compatible(&condition, &ValueKind::Boolean) {
+                        self.error(Error::InvalidCondition, index);
+                    }
+                }
+                Token::Match(patterns) => {
+                    let kind = pop(&mut operands);
+                    let mut start = index + 1;
+                    for (pattern, has_guard, skip) in patterns {
+                        if *has_guard {
+                            let condition = pop(&mut operands);
+                            if !compatible(&condition, &ValueKind::Boolean) {
+                                self.error(Error::InvalidCondition, index);
+                            }
+                        }
+
+                        if !pattern_fits(&kind, pattern) {
+                            self.error(Error::InvalidMatch, index);
+                        }
+                        if pattern.binds() {
+                            let mut names = HashMap::new();
+                            bind_kinds(&kind, pattern, &mut names);
+                            arms.insert(start, names);
+                        }
+                        start += skip;
+                    }
+                }
+                Token::ForStart(pattern) => {
+                    let elem = match pop(&mut operands) {
+                        ValueKind::List(kind) => *kind,
+                        ValueKind::Integer | ValueKind::Float => ValueKind::Integer,
+                        ValueKind::Unknown => ValueKind::Unknown,
+                        _ => {
+                            self.error(Error::NotIterable, index);
+                            ValueKind::Unknown
+                        }
+                    };
+                    if !pattern_fits(&elem, pattern) {
+                        self.error(Error::InvalidMatch, index);
+                    }
+                    let mut names = HashMap::new();
+                    bind_kinds(&elem, pattern, &mut names);
+                    self.scopes.push(Scope {
+                        names,
+                        is_let: false,
+                    });
+                }
+                Token::LoopStart => {
+                    let count = pop(&mut operands);
+                    if !compatible(&count, &ValueKind::Integer) && count != ValueKind::Float {
+                        self.error(Error::NotIterable, index);
+                    }
+                }
+                Token::ForEnd | Token::LoopEnd => {
+                    let kind = pop(&mut operands);
+                    operands.push(ValueKind::List(Box::new(kind)));
+                }
+            }
+
+            index += 1;
+        }
+
+        if let Some(branches) = joins.remove(&index) {
+            join(&mut operands, branches);
+        }
+
+        pop(&mut operands)
+    }
+}
+
+/// Looks through every definition for mistakes that would otherwise only show
+/// up once the program runs: calls to names that aren't defined anywhere in
+/// scope, functions given more arguments than they take, operators and
+/// builtins given the wrong kinds of value, and type errors in whatever can be
+/// worked out without running anything. All problems found are
+/// returned, each located at the expression responsible. Native functions the
+/// program will be run with count as defined.
+pub fn check_tree(tree: &Tree, natives: &[NativeFunction]) -> Vec<Error> {
+    let mut errors = Vec::new();
+    let mut globals: HashMap<&str, usize> = HashMap::new();
+    let natives: HashMap<&str, usize> = natives
+        .iter()
+        .map(|native| (native.name.as_str(), native.arity))
+        .collect();
+
+    for definition in tree {
+        match globals.get(definition.name) {
+            Some(&arity) if arity != definition.params.len() => {
+                errors.push(
+                    Error::InvalidDefinition(definition.name.into())
+                        .located(Some(definition.span), Some(definition.name)),
+                );
+            }
+            Some(_) => (),
+            None => {
+                globals.insert(definition.name, definition.params.len());
+            }
+        }
+    }
+
+    // A first pass works out what each definition gives back, so calls to it
+    // can be checked in the second. Definitions that can give back different
+    // kinds of value, like weighted alternatives, aren't known.
+    let mut returns: HashMap<&str, ValueKind> = HashMap::new();
+    for definition in tree {
+        let (kind, _) = check_definition(definition, &globals, &HashMap::new(), &natives);
+        let kind = match returns.get(definition.name) {
+            Some(other) if *other != kind => ValueKind::Unknown,
+            _ => kind,
+        };
+        returns.insert(definition.name, kind);
+    }
+
+    for definition in tree {
+        let (_, found) = check_definition(definition, &globals, &returns, &natives);
+        errors.extend(found);
+    }
+
+    errors
+}
+
+fn check_definition<'a>(
+    definition: &Definition<'a>,
+    globals: &HashMap<&'a str, usize>,
+    returns: &HashMap<&'a str, ValueKind>,
+    natives: &HashMap<&str, usize>,
+) -> (ValueKind, Vec<Error>) {
+    let mut checker = Checker {
+        globals,
+        returns,
+        natives,
+        definition,
+        scopes: vec![Scope {
+            names: definition
+                .params
+                .iter()
+                .map(|param| ((*param).into(), ValueKind::Unknown))
+                .collect(),
+            is_let: false,
+        }],
+        errors: Vec::new(),
+    };
+    let kind = checker.walk(0, definition.block.len());
+    (kind, checker.errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn check_source(code: &str) -> Vec<Error> {
+        check_tree(&parse(code).unwrap(), &[])
+    }
+
+    #[test]
+    fn test_unknown_names() {
+        let errors = check_source(
+            "
+start = tx 1 squre : shape 2
+
+shape n = if n > 1 -> SQUARE else -> circel
+            ",
+        );
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(errors[0].inner(), Error::UnknownFunction(name) if name == "squre"));
+        assert_eq!(errors[0].definition(), Some("start"));
+        assert!(matches!(errors[1].inner(), Error::UnknownFunction(name) if name == "circel"));
+        assert_eq!(errors[1].definition(), Some("shape"));
+
+        // Names are only visible where they'd be bound at runtime
+        let errors = check_source(
+            "
+model =
+    let offset = 2
+        (x, y) = (1, 2)
+        add_offset n = n + offset
+    -> (add_offset x, for (i, j) in [(x, y)] -> i * j, map (\\v -> v + y) [1, 2], state.frame)
+
+state = { frame = 0 }
+
+step p = match p
+    (0, rest) -> rest
+    [h, ..t] -> h
+    _ -> i
+            ",
+        );
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0].inner(), Error::UnknownFunction(name) if name == "i"));
+        assert_eq!(errors[0].definition(), Some("step"));
+    }
+
+    #[test]
+    fn test_arity() {
+        // Builtins can be partially applied, but not given extra arguments
+        // unless they can return a function.
+        let errors = check_source(
+            "
+start = tx 1 2 SQUARE : collect (map (tx 1) [SQUARE]) : tx (pipe 1 add 2) SQUARE
+            ",
+        );
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0].inner(), Error::TooManyArguments(name, 2) if name == "tx"));
+
+        // Values that still need arguments can't be used as operands
+        let errors = check_source("start = ss 2 : SQUARE\n\nsize = (tx 1) + 2");
+        assert_eq!(errors.len(), 2);
+        assert!(errors
+            .iter()
+            .all(|error| matches!(error.inner(), Error::InvalidArgument(_))));
+
+        // Nor can values that aren't functions be called
+        let errors = check_source("start = let n = 5 -> n SQUARE");
+        assert!(matches!(errors[0].inner(), Error::NotCallable));
+
+        // Definitions can only be given extra arguments if they give back a
+        // function
+        let errors = check_source(
+            "
+start = tx (size 2) SQUARE : tx (adder 1 2) SQUARE
+
+size = 5
+
+adder n = \\x -> x + n
+            ",
+        );
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(errors[0].inner(), Error::TooManyArguments(name, 0) if name == "size"));
+
+        // Composition takes as many arguments as the function on the right
+        let errors = check_source(
+            "start = SQUARE\n\nplus x y = x + y\n\nn = let neg_sum = neg . plus -> neg_sum 1 2",
+        );
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn test_types() {
+        let errors = check_source(
+            "
+start =
+    let state = { frame = 0 }
+    -> if 1 -> { state | time = 1 } else -> for i in true -> [i, 2.5]
+
+pick n = match n * 2
+    \"a\" -> state.fram
+    _ -> loop SQUARE -> n
+            ",
+        );
+        let kinds = errors
+            .iter()
+            .map(|error| error.inner().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                Error::InvalidCondition.to_string(),
+                Error::UnknownField("time".into()).to_string(),
+                Error::NotIterable.to_string(),
+                Error::UnknownFunction("state.fram".into()).to_string(),
+                Error::NotIterable.to_string(),
+            ]
+        );
+
+        // Operators and builtins given the wrong kinds of value
+        let errors = check_source(
+            "
+start = SQUARE
+
+bad = (1 + \"a\", 'a' < 2, !3, rotate \"a\" SQUARE, tx 1 5)
+
+good = (1 + 2.5, 'a' < 'b', r 45 (g (linear_grad 0 0 1 1) SQUARE), map (ss 2) [SQUARE])
+            ",
+        );
+        let names = errors
+            .iter()
+            .map(|error| match error.inner() {
+                Error::InvalidArgument(name) => name.as_str(),
+                _ => "",
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec!["+", "<", "!", "rotate", "tx"]);
+
+        // Programs without mistakes pass
+        assert!(check_source(
+            "
+start = collect (for i in 0..10 -> match i
+    0 -> SQUARE
+    n if i > 5 -> tx n CIRCLE
+    _ -> let k2 = i * 2 -> tx k2 (fib i))
+
+fib n = match n
+    0,1 -> n
+    _ -> fib (n - 1) + fib (n - 2)
+            "
+        )
+        .is_empty());
+    }
+}
@@ -1,6 +1,7 @@
 use nom::branch::alt;
 use nom::bytes::complete::tag;
 use nom::combinator::value;
+use nom::error::ParseError;
 use nom::{IResult, Parser};
 
 pub const ALICEBLUE: [u8; 3] = [240, 248, 255];
@@ -152,7 +153,7 @@ pub const WHITESMOKE: [u8; 3] = [245, 245, 245];
 pub const YELLOW: [u8; 3] = [255, 255, 0];
 pub const YELLOWGREEN: [u8; 3] = [154, 205, 50];
 
-pub fn color(input: &str) -> IResult<&str, [u8; 3]> {
+pub fn color<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, [u8; 3], E> {
     alt((
         alt((
             value(ALICEBLUE, tag("ALICEBLUE")),
@@ -1,9 +1,26 @@
 #[cfg(feature = "alloc")]
 use alloc::{
+    boxed::Box,
     format,
     string::{String, ToString},
 };
 
+use core::fmt;
+
+use crate::parser::strip_comments;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+}
+
 #[derive(Debug)]
 pub enum Error {
     ParseError,
@@ -12,38 +29,189 @@ pub enum Error {
     InvalidRange,
     InvalidStart,
     InvalidView,
+    InvalidAnimation,
     MissingSeed,
     UnknownFunction(String),
     InvalidArgument(String),
+    TooManyArguments(String, usize),
     InvalidDefinition(String),
     InvalidCondition,
     InvalidMatch,
     MatchNotFound,
     NotIterable,
+    NotCallable,
+    NotARecord,
+    UnknownField(String),
     NegativeNumber,
     OutOfBounds,
     NotFound,
     MaxDepthReached,
+    MaxTailCallsReached,
+    OutOfFuel,
+    Timeout,
+    ListTooLong(usize),
+    TooManyShapes(usize),
     NoIO,
+    ImportNotFound(String),
+    ImportCycle(String),
+    ImportConflict(String),
+    FileAccessDenied(String),
+    ImageError(String, String),
+    FontError(String, String),
     PngError(png::EncodingError),
+    GifError(gif::EncodingError),
+    #[cfg(feature = "webp")]
+    WebPError(image_webp::EncodingError),
+    UnsupportedFormat(String, String),
+    UnsupportedDataUri,
     #[cfg(feature = "std")]
     FileError(std::io::Error),
+    Located {
+        error: Box<Error>,
+        span: Option<Span>,
+        definition: Option<String>,
+    },
 }
 
-impl ToString for Error {
-    fn to_string(&self) -> String {
+impl Error {
+    /// Attaches a source location to the error. Errors that already carry a
+    /// location keep it, so the innermost expression is the one reported.
+    pub fn located(self, span: Option<Span>, definition: Option<&str>) -> Error {
+        match self {
+            Error::Located { .. } => self,
+            error => Error::Located {
+                error: Box::new(error),
+                span,
+                definition: definition.map(Into::into),
+            },
+        }
+    }
+
+    /// The underlying error, without any location information.
+    pub fn inner(&self) -> &Error {
         match self {
+            Error::Located { error, .. } => error.inner(),
+            error => error,
+        }
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Error::Located { span, .. } => *span,
+            _ => None,
+        }
+    }
+
+    pub fn definition(&self) -> Option<&str> {
+        match self {
+            Error::Located { definition, .. } => definition.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Moves the error's span forward, for sources that are parsed on their
+    /// own but located as part of a larger program.
+    pub(crate) fn offset(self, by: usize) -> Error {
+        match self {
+            Error::Located {
+                error,
+                span,
+                definition,
+            } => Error::Located {
+                error,
+                span: span.map(|span| Span::new(span.start + by, span.end + by)),
+                definition,
+            },
+            error => error,
+        }
+    }
+
+    /// Renders the error as a rustc-style diagnostic, with a snippet of the
+    /// offending line and a caret under the expression that failed.
+    pub fn diagnostic(&self, file_name: &str, source: &str) -> String {
+        self.diagnostic_at(self.span(), file_name, source)
+    }
+
+    pub(crate) fn diagnostic_at(
+        &self,
+        span: Option<Span>,
+        file_name: &str,
+        source: &str,
+    ) -> String {
+        let message = format!("error: {}", self);
+        let span = match span {
+            Some(span) => span,
+            None => {
+                return match self.definition() {
+                    Some(name) => format!("{}\n  = note: in definition `{}`", message, name),
+                    None => message,
+                }
+            }
+        };
+
+        // Spans point into the source as it was parsed, with comments removed.
+        // Comments only ever trail the code on a line, so line and column
+        // numbers still match the original file.
+        let stripped = strip_comments(source);
+        let start = span.start.min(stripped.len());
+        let line_start = stripped[..start].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let line_end = stripped[start..]
+            .find('\n')
+            .map(|i| start + i)
+            .unwrap_or(stripped.len());
+        let line_number = stripped[..start].matches('\n').count() + 1;
+        let column = stripped[line_start..start].chars().count();
+        let text = source.lines().nth(line_number - 1).unwrap_or("");
+
+        let end = span.end.clamp(start, line_end);
+        let width = stripped[start..end].trim_end().chars().count().max(1);
+
+        let gutter = " ".repeat(line_number.to_string().len());
+        let mut output = format!(
+            "{}\n{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}{}",
+            message,
+            gutter,
+            file_name,
+            line_number,
+            column + 1,
+            gutter,
+            line_number,
+            text,
+            gutter,
+            " ".repeat(column),
+            "^".repeat(width),
+        );
+        if let Some(name) = self.definition() {
+            output.push_str(&format!("\n{} = note: in definition `{}`", gutter, name));
+        }
+        output
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let message: String = match self {
             Error::ParseError => "Could not parse file.".into(),
             Error::NotDigit(name) => format!("Value passed to `{}` was not a digit.", name),
             Error::InvalidList => "Type mismatch in list.".into(),
             Error::InvalidRange => "Invalid range.".into(),
             Error::InvalidStart => "The `start` function must return a shape.".into(),
             Error::InvalidView => "The `view` function must return a shape.".into(),
+            Error::InvalidAnimation => {
+                "The `animation` definition must be a record of `fps`, `delay`, `loops` and `ping_pong`."
+                    .into()
+            }
             Error::MissingSeed => "Seed required for rng.".into(),
             Error::UnknownFunction(name) => format!("Unknown function `{}`.", name),
             Error::InvalidArgument(name) => {
                 format!("Invalid argument passed to `{}` function.", name)
             }
+            Error::TooManyArguments(name, count) => {
+                format!(
+                    "Too many arguments passed to `{}` function, which takes {}.",
+                    name, count
+                )
+            }
             Error::InvalidDefinition(name) => {
                 format!("Incorrect parameters in `{}` function.", name)
             }
@@ -51,15 +219,46 @@ impl ToString for Error {
             Error::InvalidMatch => "Incorrect type comparison in match statement.".into(),
             Error::MatchNotFound => "Not all possibilities covered in match statement".into(),
             Error::NotIterable => "Value is not iterable.".into(),
+            Error::NotCallable => "Value is not a function.".into(),
+            Error::NotARecord => "Value is not a record.".into(),
+            Error::UnknownField(name) => format!("Record has no field `{}`.", name),
             Error::NegativeNumber => "Number cannot be negative.".into(),
             Error::OutOfBounds => "Index out of bounds.".into(),
             Error::NotFound => "Value not found.".into(),
             Error::MaxDepthReached => "Max call stack depth reached.".into(),
+            Error::MaxTailCallsReached => "Max tail calls in a row reached.".into(),
+            Error::OutOfFuel => "Ran out of fuel.".into(),
+            Error::Timeout => "Ran past the time limit.".into(),
+            Error::ListTooLong(max) => format!("List is longer than the limit of {}.", max),
+            Error::TooManyShapes(max) => {
+                format!("Shape is made of more than the limit of {} shapes.", max)
+            }
             Error::NoIO => "Cannot use IO functions without the io feature enabled.".into(),
+            Error::ImportNotFound(path) => format!("Could not find imported file `{}`.", path),
+            Error::ImportCycle(path) => format!("Import cycle detected: {}.", path),
+            Error::ImportConflict(name) => format!(
+                "`{}` is defined in more than one file. Import one of them with `as` to tell them apart.",
+                name
+            ),
+            Error::FileAccessDenied(path) => format!("Not allowed to read file `{}`.", path),
+            Error::ImageError(path, e) => format!("Could not load image `{}`: {}.", path, e),
+            Error::FontError(path, e) => format!("Could not load font `{}`: {}.", path, e),
             Error::PngError(e) => e.to_string(),
+            Error::GifError(e) => e.to_string(),
+            #[cfg(feature = "webp")]
+            Error::WebPError(e) => e.to_string(),
+            Error::UnsupportedFormat(extension, feature) => format!(
+                "Cannot write `.{}` files without the {} feature enabled.",
+                extension, feature
+            ),
+            Error::UnsupportedDataUri => {
+                "A PNG sequence can't be written as a data URI, only GIF, APNG or WebP.".into()
+            }
             #[cfg(feature = "std")]
             Error::FileError(e) => e.to_string(),
-        }
+            Error::Located { error, .. } => error.to_string(),
+        };
+        f.write_str(&message)
     }
 }
 
@@ -1,5 +1,9 @@
+#[cfg(feature = "alloc")]
+use alloc::vec;
+
 use crate::builtin_function;
 use crate::error::{Error, Result};
+use crate::functions::Callback;
 use crate::interpreter::{Data, Value};
 
 use rand_chacha::ChaCha8Rng;
@@ -7,10 +11,30 @@ use rand_chacha::ChaCha8Rng;
 builtin_function!(pipe => {
     [arg, Value::Function(name, argc, pre_args)] => {
         let mut pre_args = pre_args.clone();
-        pre_args.push(arg.clone());
-        pre_args.reverse();
+        pre_args.insert(0, arg.clone());
         Value::Function(name.clone(), argc - 1, pre_args)
     },
+    [arg, Value::Closure(closure, argc, pre_args)] => {
+        let mut pre_args = pre_args.clone();
+        pre_args.insert(0, arg.clone());
+        Value::Closure(closure.clone(), argc - 1, pre_args)
+    },
+});
+
+builtin_function!(compose_fn call => {
+    [f, g, args @ ..] => |call: &mut Callback| {
+        // Until `g` has every argument it takes, the composition waits for
+        // the rest.
+        match g {
+            Value::Function(_, argc, _) | Value::Closure(_, argc, _) if *argc > args.len() => {
+                let g = call(g, args.to_vec())?;
+                return Ok(Value::Function(".".into(), argc - args.len(), vec![g, f.clone()]));
+            }
+            _ => (),
+        }
+        let value = call(g, args.to_vec())?;
+        call(f, vec![value])
+    },
 });
 
 #[cfg(test)]
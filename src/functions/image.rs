@@ -20,21 +20,38 @@ use tiny_skia::FilterQuality;
 #[cfg(not(feature = "io"))]
 type Interpolation = ();
 
-builtin_function!(import_image => {
-    [Value::String(path)] => {
-        Value::Shape(Rc::new(RefCell::new(Shape::image(ImagePath::File(path.clone())))))
+// Catches files that can't be read when the shape is made, rather than
+// waiting until it's rendered.
+#[cfg(all(feature = "std", feature = "io"))]
+fn check_file(path: &str, error: fn(String, String) -> Error) -> Result<()> {
+    match std::fs::metadata(path) {
+        Ok(metadata) if metadata.is_file() => Ok(()),
+        Ok(_) => Err(error(path.into(), "not a file".into())),
+        Err(e) => Err(error(path.into(), e.to_string())),
+    }
+}
+
+builtin_function!(import_image data => {
+    [Value::String(path)] => |data: &Data| {
+        let path = data.file_path(path)?;
+        #[cfg(all(feature = "std", feature = "io"))]
+        check_file(&path, Error::ImageError)?;
+        Ok(Value::Shape(Rc::new(RefCell::new(Shape::image(ImagePath::File(path))))))
     }
 });
 
-builtin_function!(text => {
-    [Value::String(text), Value::String(font), size] => {
+builtin_function!(text data => {
+    [Value::String(text), Value::String(font), size] => |data: &Data| {
         let size = match size {
             Value::Integer(size) => *size as f32,
             Value::Float(size)   => *size,
             _ => return Err(Error::InvalidArgument("text".into())),
         };
 
-        Value::Shape(Rc::new(RefCell::new(Shape::text(font.clone(), text.clone(), size))))
+        let font = data.file_path(font)?;
+        #[cfg(all(feature = "std", feature = "io"))]
+        check_file(&font, Error::FontError)?;
+        Ok(Value::Shape(Rc::new(RefCell::new(Shape::text(font, text.clone(), size)))))
     }
 });
 
@@ -846,18 +863,40 @@ mod tests {
             panic!("Expected Shape value");
         }
 
-        // Test text creation
+        // Test text creation, where only the font file's existence is
+        // checked up front
         let text_result = text(
             &mut rng,
             &data,
             &[
                 Value::String("Hello".into()),
-                Value::String("Arial".into()),
+                Value::String("test.png".into()),
                 Value::Integer(24),
             ],
         )
         .unwrap();
         assert!(matches!(text_result, Value::Shape(_)));
+
+        // Missing files are caught before rendering
+        #[cfg(all(feature = "std", feature = "io"))]
+        {
+            let image_result =
+                import_image(&mut rng, &data, &[Value::String("missing.png".into())]);
+            assert!(
+                matches!(image_result, Err(Error::ImageError(path, _)) if path == "missing.png")
+            );
+
+            let text_result = text(
+                &mut rng,
+                &data,
+                &[
+                    Value::String("Hello".into()),
+                    Value::String("Arial".into()),
+                    Value::Integer(24),
+                ],
+            );
+            assert!(matches!(text_result, Err(Error::FontError(path, _)) if path == "Arial"));
+        }
     }
 
     #[test]
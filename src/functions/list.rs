@@ -7,50 +7,56 @@ use alloc::{
 
 use crate::builtin_function;
 use crate::error::{Error, Result};
+use crate::functions::Callback;
 use crate::interpreter::{Data, Value};
 
+use core::cmp::Ordering;
 use itertools::Itertools;
 use rand_chacha::ChaCha8Rng;
 
-builtin_function!(range => {
-    [Value::Integer(from), Value::Integer(to)] => {
+builtin_function!(range data => {
+    [Value::Integer(from), Value::Integer(to)] => |data: &Data| {
         if from >= to {
             return Err(Error::InvalidRange);
         }
-        Value::List((*from..*to).map(Value::Integer).collect())
+        data.check_length((*to as i64 - *from as i64) as usize)?;
+        Ok(Value::List((*from..*to).map(Value::Integer).collect()))
     },
-    [Value::Float(from), Value::Float(to)] => {
+    [Value::Float(from), Value::Float(to)] => |data: &Data| {
         if from >= to {
             return Err(Error::InvalidRange);
         }
-        Value::List((*from as i32..*to as i32).map(|i| Value::Float(i as f32)).collect())
+        data.check_length((*to as i64 - *from as i64) as usize)?;
+        Ok(Value::List((*from as i32..*to as i32).map(|i| Value::Float(i as f32)).collect()))
     },
-    [Value::Char(from), Value::Char(to)] => {
+    [Value::Char(from), Value::Char(to)] => |_data: &Data| {
         if from >= to {
             return Err(Error::InvalidRange);
         }
-        Value::String((*from..*to).collect())
+        Ok(Value::String((*from..*to).collect()))
     },
 });
 
-builtin_function!(rangei => {
-    [Value::Integer(from), Value::Integer(to)] => {
+builtin_function!(rangei data => {
+    [Value::Integer(from), Value::Integer(to)] => |data: &Data| {
         if from > to {
             return Err(Error::InvalidRange);
         }
-        Value::List((*from..=*to).map(Value::Integer).collect())
+        data.check_length((*to as i64 - *from as i64 + 1) as usize)?;
+        Ok(Value::List((*from..=*to).map(Value::Integer).collect()))
     },
-    [Value::Float(from), Value::Float(to)] => {
+    [Value::Float(from), Value::Float(to)] => |data: &Data| {
         if from > to {
             return Err(Error::InvalidRange);
         }
-        Value::List((*from as i32..=*to as i32).map(|i| Value::Float(i as f32)).collect())
+        data.check_length((*to as i64 - *from as i64 + 1) as usize)?;
+        Ok(Value::List((*from as i32..=*to as i32).map(|i| Value::Float(i as f32)).collect()))
     },
-    [Value::Char(from), Value::Char(to)] => {
+    [Value::Char(from), Value::Char(to)] => |_data: &Data| {
         if from > to {
             return Err(Error::InvalidRange);
         }
-        Value::String((*from..=*to).collect())
+        Ok(Value::String((*from..=*to).collect()))
     },
 });
 
@@ -724,6 +730,190 @@ builtin_function!(intersperse => {
     [Value::Char(c), Value::String(s)] => Value::String(s.chars().intersperse(*c).collect()),
 });
 
+fn typed_list(list: Vec<Value>) -> Result<Value> {
+    let list = Value::List(list);
+    list.kind()?;
+    Ok(list)
+}
+
+fn predicate(call: &mut Callback, name: &str, function: &Value, value: &Value) -> Result<bool> {
+    match call(function, vec![value.clone()])? {
+        Value::Boolean(b) => Ok(b),
+        _ => Err(Error::InvalidArgument(name.into())),
+    }
+}
+
+fn compare_keys(a: &Value, b: &Value) -> Result<Ordering> {
+    match (a, b) {
+        (Value::Integer(a), Value::Integer(b)) => Ok(a.cmp(b)),
+        (Value::Float(a), Value::Float(b)) => Ok(a.partial_cmp(b).unwrap_or(Ordering::Equal)),
+        (Value::Boolean(a), Value::Boolean(b)) => Ok(a.cmp(b)),
+        (Value::Char(a), Value::Char(b)) => Ok(a.cmp(b)),
+        (Value::String(a), Value::String(b)) => Ok(a.cmp(b)),
+        _ => Err(Error::InvalidArgument("sort_by".into())),
+    }
+}
+
+builtin_function!(map call => {
+    [f, Value::List(list)] => |call: &mut Callback| {
+        let list = list
+            .iter()
+            .map(|value| call(f, vec![value.clone()]))
+            .collect::<Result<Vec<_>>>()?;
+        typed_list(list)
+    },
+});
+
+builtin_function!(filter call => {
+    [f, Value::List(list)] => |call: &mut Callback| {
+        let mut filtered = Vec::new();
+        for value in list {
+            if predicate(call, "filter", f, value)? {
+                filtered.push(value.clone());
+            }
+        }
+        Ok(Value::List(filtered))
+    },
+});
+
+builtin_function!(foldl call => {
+    [f, init, Value::List(list)] => |call: &mut Callback| {
+        let mut acc = init.clone();
+        for value in list {
+            acc = call(f, vec![acc, value.clone()])?;
+        }
+        Ok(acc)
+    },
+});
+
+builtin_function!(foldr call => {
+    [f, init, Value::List(list)] => |call: &mut Callback| {
+        let mut acc = init.clone();
+        for value in list.iter().rev() {
+            acc = call(f, vec![value.clone(), acc])?;
+        }
+        Ok(acc)
+    },
+});
+
+builtin_function!(scan call => {
+    [f, init, Value::List(list)] => |call: &mut Callback| {
+        let mut acc = init.clone();
+        let mut scanned = Vec::with_capacity(list.len() + 1);
+        scanned.push(acc.clone());
+        for value in list {
+            acc = call(f, vec![acc, value.clone()])?;
+            scanned.push(acc.clone());
+        }
+        typed_list(scanned)
+    },
+});
+
+builtin_function!(zip_with call => {
+    [f, Value::List(a), Value::List(b)] => |call: &mut Callback| {
+        let list = a
+            .iter()
+            .zip(b)
+            .map(|(a, b)| call(f, vec![a.clone(), b.clone()]))
+            .collect::<Result<Vec<_>>>()?;
+        typed_list(list)
+    },
+});
+
+builtin_function!(any call => {
+    [f, Value::List(list)] => |call: &mut Callback| {
+        for value in list {
+            if predicate(call, "any", f, value)? {
+                return Ok(Value::Boolean(true));
+            }
+        }
+        Ok(Value::Boolean(false))
+    },
+});
+
+builtin_function!(all call => {
+    [f, Value::List(list)] => |call: &mut Callback| {
+        for value in list {
+            if !predicate(call, "all", f, value)? {
+                return Ok(Value::Boolean(false));
+            }
+        }
+        Ok(Value::Boolean(true))
+    },
+});
+
+// Sorts by the key each element maps to, keeping equal keys in order.
+builtin_function!(sort_by call => {
+    [f, Value::List(list)] => |call: &mut Callback| {
+        let keys = list
+            .iter()
+            .map(|value| call(f, vec![value.clone()]))
+            .collect::<Result<Vec<_>>>()?;
+        if let Some(first) = keys.first() {
+            for key in &keys {
+                compare_keys(first, key)?;
+            }
+        }
+
+        let sorted = keys
+            .iter()
+            .zip(list)
+            .sorted_by(|(a, _), (b, _)| compare_keys(a, b).unwrap())
+            .map(|(_, value)| value.clone())
+            .collect();
+        Ok(Value::List(sorted))
+    },
+});
+
+// Groups together runs of consecutive elements that map to the same key.
+builtin_function!(group_by call => {
+    [f, Value::List(list)] => |call: &mut Callback| {
+        let mut groups: Vec<(Value, Vec<Value>)> = Vec::new();
+        for value in list {
+            let key = call(f, vec![value.clone()])?;
+            match groups.last_mut() {
+                Some((last, group)) if *last == key => group.push(value.clone()),
+                _ => groups.push((key, vec![value.clone()])),
+            }
+        }
+        Ok(Value::List(
+            groups.into_iter().map(|(_, group)| Value::List(group)).collect(),
+        ))
+    },
+});
+
+builtin_function!(flat_map call => {
+    [f, Value::List(list)] => |call: &mut Callback| {
+        let mut flattened = Vec::new();
+        for value in list {
+            match call(f, vec![value.clone()])? {
+                Value::List(list) => flattened.extend(list),
+                _ => return Err(Error::InvalidArgument("flat_map".into())),
+            }
+        }
+        typed_list(flattened)
+    },
+});
+
+// Repeatedly applies a function, starting from the given value.
+builtin_function!(iterate call => {
+    [f, Value::Integer(n), init] => |call: &mut Callback| {
+        if *n < 0 {
+            return Err(Error::InvalidArgument("iterate".into()));
+        }
+
+        let mut list = Vec::with_capacity(*n as usize);
+        let mut value = init.clone();
+        for i in 0..*n {
+            if i > 0 {
+                value = call(f, vec![value])?;
+            }
+            list.push(value.clone());
+        }
+        typed_list(list)
+    },
+});
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1635,4 +1825,94 @@ mod tests {
             Some(Value::String("a,b,c".into()))
         );
     }
+
+    // Calls partially applied builtins, standing in for the interpreter.
+    fn call_builtin(function: &Value, args: Vec<Value>) -> Result<Value> {
+        let mut rng = ChaCha8Rng::from_seed([0; 32]);
+        match function {
+            Value::Function(name, _, pre_args) => {
+                let args: Vec<Value> = pre_args.iter().rev().cloned().chain(args).collect();
+                crate::functions::handle_builtin(name, &mut rng, &Data::default(), &args)
+            }
+            _ => Err(Error::NotCallable),
+        }
+    }
+
+    fn ints(list: &[i32]) -> Value {
+        Value::List(list.iter().map(|n| Value::Integer(*n)).collect())
+    }
+
+    #[test]
+    fn test_higher_order() {
+        let call = &mut call_builtin;
+        let greater_than_two = Value::Function("lt".into(), 1, vec![Value::Integer(2)]);
+
+        assert_eq!(
+            filter(call, &[greater_than_two.clone(), ints(&[1, 3, 2, 4])]).ok(),
+            Some(ints(&[3, 4]))
+        );
+        assert_eq!(
+            any(call, &[greater_than_two.clone(), ints(&[1, 3])]).ok(),
+            Some(Value::Boolean(true))
+        );
+        assert_eq!(
+            all(call, &[greater_than_two, ints(&[1, 3])]).ok(),
+            Some(Value::Boolean(false))
+        );
+
+        // Folds
+        let sub = Value::Function("sub".into(), 2, vec![]);
+        assert_eq!(
+            foldl(call, &[sub.clone(), Value::Integer(10), ints(&[1, 2, 3])]).ok(),
+            Some(Value::Integer(4))
+        );
+        assert_eq!(
+            foldr(call, &[sub.clone(), Value::Integer(0), ints(&[1, 2, 3])]).ok(),
+            Some(Value::Integer(2))
+        );
+        assert_eq!(
+            scan(call, &[sub, Value::Integer(10), ints(&[1, 2])]).ok(),
+            Some(ints(&[10, 9, 7]))
+        );
+
+        assert_eq!(
+            zip_with(
+                call,
+                &[
+                    Value::Function("mul".into(), 2, vec![]),
+                    ints(&[1, 2, 3]),
+                    ints(&[4, 5])
+                ]
+            )
+            .ok(),
+            Some(ints(&[4, 10]))
+        );
+        assert_eq!(
+            sort_by(
+                call,
+                &[Value::Function("neg".into(), 1, vec![]), ints(&[2, 3, 1])]
+            )
+            .ok(),
+            Some(ints(&[3, 2, 1]))
+        );
+        assert_eq!(
+            iterate(
+                call,
+                &[
+                    Value::Function("mul".into(), 1, vec![Value::Integer(3)]),
+                    Value::Integer(4),
+                    Value::Integer(1)
+                ]
+            )
+            .ok(),
+            Some(ints(&[1, 3, 9, 27]))
+        );
+
+        // Predicates must return booleans
+        assert!(filter(
+            call,
+            &[Value::Function("neg".into(), 1, vec![]), ints(&[1])]
+        )
+        .is_err());
+    }
 }
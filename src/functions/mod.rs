@@ -2,7 +2,7 @@
 use std::rc::Rc;
 
 #[cfg(feature = "alloc")]
-use alloc::rc::Rc;
+use alloc::{rc::Rc, vec::Vec};
 
 use crate::error::{Error, Result};
 use crate::interpreter::{Data, Value};
@@ -24,18 +24,32 @@ mod shape;
 mod system;
 mod transform;
 
+/// Calls a function value from within a higher-order builtin, running it to
+/// completion.
+pub type Callback<'a> = dyn FnMut(&Value, Vec<Value>) -> Result<Value> + 'a;
+
 macro_rules! define_builtins {
     (
+        higher_order {
+            $(
+                $ho_name:literal => {$ho_func:path, $ho_param_count:literal}
+            ),* $(,)?
+        }
         $(
             $name:literal => {$func:path, $param_count:literal}
         ),* $(,)?
     ) => {
         // Generate the static BUILTIN_FUNCTIONS array
         pub static BUILTIN_FUNCTIONS: &[&str] = &[
-            "map",
+            $($ho_name,)*
             $($name),*
         ];
 
+        // Builtins that take functions as arguments and call back into them
+        pub static HIGHER_ORDER_FUNCTIONS: &[&str] = &[
+            $($ho_name),*
+        ];
+
         // Generate the handle_builtin function with match statements
         pub fn handle_builtin(name: &str, rng: &mut ChaCha8Rng, data: &Data, args: &[Value]) -> Result<Value> {
             match name {
@@ -46,10 +60,22 @@ macro_rules! define_builtins {
             }
         }
 
+        // Generate the handle_higher_order function with match statements
+        pub fn handle_higher_order(name: &str, call: &mut Callback, args: &[Value]) -> Result<Value> {
+            match name {
+                $(
+                    $ho_name => $ho_func(call, args),
+                )*
+                _ => Err(Error::UnknownFunction(name.into())),
+            }
+        }
+
         // Generate the builtin_param_count function with match statements
         pub fn builtin_param_count(name: &str) -> usize {
             match name {
-                "map" => 2,
+                $(
+                    $ho_name => $ho_param_count,
+                )*
                 $(
                     $name => $param_count,
                 )*
@@ -60,6 +86,22 @@ macro_rules! define_builtins {
 }
 
 define_builtins! {
+    higher_order {
+        "map" => {list::map, 2},
+        "filter" => {list::filter, 2},
+        "foldl" => {list::foldl, 3},
+        "foldr" => {list::foldr, 3},
+        "scan" => {list::scan, 3},
+        "zip_with" => {list::zip_with, 3},
+        "any" => {list::any, 2},
+        "all" => {list::all, 2},
+        "sort_by" => {list::sort_by, 2},
+        "group_by" => {list::group_by, 2},
+        "flat_map" => {list::flat_map, 2},
+        "iterate" => {list::iterate, 3},
+        "." => {func::compose_fn, 3},
+        "compose_fn" => {func::compose_fn, 3},
+    }
     "width" => {system::width, 0},
     "height" => {system::height, 0},
     "neg" => {math::neg, 1},
@@ -200,8 +242,6 @@ define_builtins! {
     "string" => {character::string, 1},
     "|>" => {func::pipe, 2},
     "pipe" => {func::pipe, 2},
-    // "." => compose_fn 2,
-    // "compose_fn" => compose_fn 2,
     "rand" => {rand::rand, 0},
     "randi" => {rand::randi, 0},
     "rand_range" => {rand::rand_range, 2},
@@ -376,6 +416,18 @@ define_builtins! {
     "pixel_sort" => {image::pixel_sort, 3},
 }
 
+// Builtins that draw from the random number generator
+pub static RANDOM_FUNCTIONS: &[&str] = &[
+    "rand",
+    "randi",
+    "rand_range",
+    "randi_range",
+    "rand_rangei",
+    "randi_rangei",
+    "shuffle",
+    "choose",
+];
+
 #[macro_export]
 macro_rules! builtin_function {
     ($name:ident => {
@@ -410,6 +462,23 @@ macro_rules! builtin_function {
         }
     };
 
+    ($name:ident call => {
+        $(
+            $pattern:pat => $body:expr
+        ),* $(,)?
+    }) => {
+        pub fn $name(call: &mut Callback, args: &[Value]) -> Result<Value> {
+            match args {
+                $(
+                    $pattern => $body(call),
+                )*
+                _ => Err(Error::InvalidArgument(
+                    stringify!($name).into(),
+                )),
+            }
+        }
+    };
+
     ($name:ident data => {
         $(
             $pattern:pat => $body:expr
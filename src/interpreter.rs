@@ -1,8 +1,20 @@
 #[cfg(feature = "std")]
-use std::rc::Rc;
+use std::{
+    collections::BTreeMap,
+    rc::Rc,
+    time::{Duration, Instant},
+};
 
 #[cfg(feature = "alloc")]
-use alloc::{boxed::Box, rc::Rc, string::String, vec, vec::Vec};
+use alloc::{
+    boxed::Box,
+    collections::BTreeMap,
+    format,
+    rc::Rc,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
 
 #[cfg(feature = "io")]
 use {image::imageops::FilterType, imageproc::distance_transform::Norm};
@@ -10,14 +22,21 @@ use {image::imageops::FilterType, imageproc::distance_transform::Norm};
 #[cfg(not(feature = "io"))]
 use crate::parser::{FilterType, Norm};
 
-use crate::error::{Error, Result};
-use crate::functions::{builtin_param_count, handle_builtin, BUILTIN_FUNCTIONS};
+use crate::error::{Error, Result, Span};
+use crate::functions::{
+    builtin_param_count, handle_builtin, handle_higher_order, BUILTIN_FUNCTIONS,
+    HIGHER_ORDER_FUNCTIONS, RANDOM_FUNCTIONS,
+};
+use crate::module::Program;
 use crate::out::Config;
+#[cfg(feature = "std")]
+use crate::out::Sandbox;
 use crate::parser::*;
-use crate::shape::{Gradient, Shape};
+use crate::shape::{BasicShape, Gradient, Shape};
 
 use core::cell::RefCell;
-use hashbrown::HashMap;
+use hashbrown::{HashMap, HashSet};
+use itertools::Itertools;
 use noise::Perlin;
 use num::Complex;
 use rand::distr::{weighted::WeightedIndex, Distribution};
@@ -39,9 +58,12 @@ pub enum ValueKind {
     Enum,
     Function(usize),
     List(Box<ValueKind>),
+    Tuple(Vec<ValueKind>),
+    Record(BTreeMap<String, ValueKind>),
     Unknown,
 }
 
+/// A value as scripts see it, passed to and returned from native functions.
 #[derive(Debug, Clone)]
 pub enum Value {
     Integer(i32),
@@ -63,8 +85,13 @@ pub enum Value {
     Norm(Norm),
     SortMode(SortMode),
     SortDirection(SortDirection),
+    // Partially applied functions keep the arguments given so far, most recent
+    // first, along with how many are still needed.
     Function(String, usize, Vec<Value>),
+    Closure(Rc<Closure>, usize, Vec<Value>),
     List(Vec<Value>),
+    Tuple(Vec<Value>),
+    Record(BTreeMap<String, Value>),
 }
 
 impl Value {
@@ -89,7 +116,9 @@ impl Value {
             | Self::Norm(_)
             | Self::SortMode(_)
             | Self::SortDirection(_) => Ok(ValueKind::Enum),
-            Self::Function(_, argc, _) => Ok(ValueKind::Function(*argc)),
+            Self::Function(_, argc, _) | Self::Closure(_, argc, _) => {
+                Ok(ValueKind::Function(*argc))
+            }
             Self::List(list) => {
                 let kind = list
                     .get(0)
@@ -105,6 +134,16 @@ impl Value {
                     Err(Error::InvalidList)
                 }
             }
+            Self::Tuple(values) => values
+                .iter()
+                .map(Self::kind)
+                .collect::<Result<_>>()
+                .map(ValueKind::Tuple),
+            Self::Record(fields) => fields
+                .iter()
+                .map(|(name, value)| Ok((name.clone(), value.kind()?)))
+                .collect::<Result<_>>()
+                .map(ValueKind::Record),
         }
     }
 }
@@ -137,17 +176,142 @@ impl PartialEq for Value {
             (Value::Function(a_name, a_argc, a_args), Value::Function(b_name, b_argc, b_args)) => {
                 a_name == b_name && a_argc == b_argc && a_args == b_args
             }
+            (
+                Value::Closure(a_closure, a_argc, a_args),
+                Value::Closure(b_closure, b_argc, b_args),
+            ) => Rc::ptr_eq(a_closure, b_closure) && a_argc == b_argc && a_args == b_args,
             (Value::List(a), Value::List(b)) => a == b,
+            (Value::Tuple(a), Value::Tuple(b)) => a == b,
+            (Value::Record(a), Value::Record(b)) => a == b,
             _ => false,
         }
     }
 }
 
+// Values are written the way they would be in source code where possible, so
+// they can be pasted back into a program.
+impl core::fmt::Display for Value {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        let join = |values: &mut dyn Iterator<Item = &Value>| {
+            values.map(|value| value.to_string()).join(", ")
+        };
+
+        match self {
+            Value::Integer(n) => write!(f, "{}", n),
+            Value::Float(n) if n.is_finite() && n.fract() == 0.0 => write!(f, "{:.1}", n),
+            Value::Float(n) => write!(f, "{}", n),
+            Value::Complex(n) => write!(f, "{}", n),
+            Value::Boolean(b) => write!(f, "{}", b),
+            Value::Hex([r, g, b]) => write!(f, "0x{:02x}{:02x}{:02x}", r, g, b),
+            Value::Char(c) => write!(f, "'{}'", c),
+            Value::String(s) => write!(f, "\"{}\"", s),
+            Value::Gradient(_) => write!(f, "<gradient>"),
+            Value::Shape(_) => write!(f, "<shape>"),
+            Value::BlendMode(bm) => write!(f, "{}", Literal::BlendMode(*bm).to_string()),
+            Value::LineCap(lc) => write!(f, "{}", Literal::LineCap(*lc).to_string()),
+            Value::LineJoin(lj) => write!(f, "{}", Literal::LineJoin(*lj).to_string()),
+            Value::SpreadMode(sm) => write!(f, "{}", Literal::SpreadMode(*sm).to_string()),
+            Value::FilterQuality(fq) => {
+                write!(f, "{}", Literal::FilterQuality(*fq).to_string())
+            }
+            Value::FilterType(ft) => write!(f, "{}", Literal::FilterType(*ft).to_string()),
+            Value::ThresholdType(tt) => {
+                write!(f, "{}", Literal::ThresholdType(*tt).to_string())
+            }
+            Value::Norm(norm) => write!(f, "{}", Literal::Norm(*norm).to_string()),
+            Value::SortMode(sm) => write!(f, "{}", Literal::SortMode(*sm).to_string()),
+            Value::SortDirection(sd) => {
+                write!(f, "{}", Literal::SortDirection(*sd).to_string())
+            }
+            Value::Function(name, _, args) if args.is_empty() => write!(f, "{}", name),
+            Value::Function(name, _, args) => {
+                let args = args
+                    .iter()
+                    .rev()
+                    .map(|arg| match arg.to_string() {
+                        arg if arg.contains(' ') || arg.starts_with('-') => format!(" ({})", arg),
+                        arg => format!(" {}", arg),
+                    })
+                    .join("");
+                write!(f, "({}{})", name, args)
+            }
+            Value::Closure(..) => write!(f, "<lambda>"),
+            Value::List(list) => write!(f, "[{}]", join(&mut list.iter())),
+            Value::Tuple(tuple) => write!(f, "({})", join(&mut tuple.iter())),
+            Value::Record(record) => {
+                let fields = record
+                    .iter()
+                    .map(|(name, value)| format!("{} = {}", name, value))
+                    .join(", ");
+                write!(f, "{{ {} }}", fields)
+            }
+        }
+    }
+}
+
+/// A hashable stand-in for an argument to a cached definition. Values that
+/// can't be compared this way, like shapes and functions, aren't cached.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CacheKey {
+    Integer(i32),
+    Float(u32),
+    Complex(u32, u32),
+    Boolean(bool),
+    Hex([u8; 3]),
+    Char(char),
+    String(String),
+    List(Vec<CacheKey>),
+    Tuple(Vec<CacheKey>),
+    Record(Vec<(String, CacheKey)>),
+}
+
+impl CacheKey {
+    fn new(value: &Value) -> Option<CacheKey> {
+        match value {
+            Value::Integer(n) => Some(CacheKey::Integer(*n)),
+            Value::Float(n) => Some(CacheKey::Float(n.to_bits())),
+            Value::Complex(n) => Some(CacheKey::Complex(n.re.to_bits(), n.im.to_bits())),
+            Value::Boolean(b) => Some(CacheKey::Boolean(*b)),
+            Value::Hex(hex) => Some(CacheKey::Hex(*hex)),
+            Value::Char(c) => Some(CacheKey::Char(*c)),
+            Value::String(s) => Some(CacheKey::String(s.clone())),
+            Value::List(list) => list
+                .iter()
+                .map(CacheKey::new)
+                .collect::<Option<_>>()
+                .map(CacheKey::List),
+            Value::Tuple(values) => values
+                .iter()
+                .map(CacheKey::new)
+                .collect::<Option<_>>()
+                .map(CacheKey::Tuple),
+            Value::Record(fields) => fields
+                .iter()
+                .map(|(name, value)| Some((name.clone(), CacheKey::new(value)?)))
+                .collect::<Option<_>>()
+                .map(CacheKey::Record),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Data {
     pub dimensions: (u32, u32),
     pub max_depth: usize,
+    pub max_tail_calls: usize,
+    pub fuel: Option<u64>,
+    #[cfg(feature = "std")]
+    pub timeout: Option<Duration>,
+    pub max_list_length: Option<usize>,
+    pub max_shapes: Option<usize>,
+    #[cfg(feature = "std")]
+    pub sandbox: Sandbox,
+    pub natives: HashMap<String, NativeFunction>,
     pub perlin: Perlin,
+    // Results of cached definitions by name and arguments, kept for as long
+    // as the environment.
+    pub cache: RefCell<HashMap<String, HashMap<Vec<CacheKey>, Value>>>,
 }
 
 impl Default for Data {
@@ -155,7 +319,49 @@ impl Default for Data {
         Data {
             dimensions: (400, 400),
             max_depth: 1500,
+            max_tail_calls: 100_000,
+            fuel: None,
+            #[cfg(feature = "std")]
+            timeout: None,
+            max_list_length: None,
+            max_shapes: None,
+            #[cfg(feature = "std")]
+            sandbox: Sandbox::default(),
+            natives: HashMap::new(),
             perlin: Perlin::new(0),
+            cache: RefCell::default(),
+        }
+    }
+}
+
+impl Data {
+    /// Fails if a list of the given length would be longer than allowed.
+    pub fn check_length(&self, length: usize) -> Result<()> {
+        match self.max_list_length {
+            Some(max) if length > max => Err(Error::ListTooLong(max)),
+            _ => Ok(()),
+        }
+    }
+
+    /// The path to read a file from, if scripts are allowed to read it.
+    pub fn file_path(&self, path: &str) -> Result<String> {
+        #[cfg(feature = "std")]
+        return Ok(self.sandbox.check(path)?.to_string_lossy().into());
+        #[cfg(feature = "alloc")]
+        Ok(path.into())
+    }
+
+    fn check_value(&self, value: &Value) -> Result<()> {
+        match value {
+            Value::List(list) => self.check_length(list.len()),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_shape(&self, shape: &Shape) -> Result<()> {
+        match self.max_shapes {
+            Some(max) if shape.count(max) > max => Err(Error::TooManyShapes(max)),
+            _ => Ok(()),
         }
     }
 }
@@ -163,9 +369,9 @@ impl Default for Data {
 type Frame = HashMap<String, Function>;
 
 #[derive(Debug)]
-struct ForStack<'a> {
+struct ForStack {
     start: usize,
-    var: &'a str,
+    pattern: Pattern,
     items: Vec<Value>,
     values: Vec<Value>,
 }
@@ -177,26 +383,30 @@ struct LoopStack {
     values: Vec<Value>,
 }
 
-#[derive(Debug, Clone)]
-enum HigherOrder {
-    Map(usize, usize, Vec<Value>),
-}
-
 #[derive(Debug)]
-struct Stack<'a> {
+struct Stack {
     frames: Vec<Frame>,
     operands: Vec<Value>,
     scope: usize,
     calls: Vec<usize>,
     scopes: Vec<usize>,
     lets: Vec<bool>,
-    fors: Vec<ForStack<'a>>,
+    fors: Vec<ForStack>,
     loops: Vec<LoopStack>,
-    higher_order: Option<HigherOrder>,
+    pending: Vec<(usize, Pending)>,
+    // Tail calls made in a row, tagged with the number of calls at that
+    // point.
+    tail_calls: Vec<(usize, usize)>,
+    steps: u64,
+    #[cfg(feature = "std")]
+    deadline: Option<Instant>,
 }
 
-impl Stack<'_> {
-    pub fn new(frame: Frame) -> Self {
+impl Stack {
+    pub fn new(frame: Frame, data: &Data) -> Self {
+        // Only timeouts need the data, which alloc builds don't have.
+        #[cfg(feature = "alloc")]
+        let _ = data;
         Self {
             frames: vec![frame],
             operands: Vec::new(),
@@ -206,27 +416,112 @@ impl Stack<'_> {
             lets: vec![false],
             fors: Vec::new(),
             loops: Vec::new(),
-            higher_order: None,
+            pending: Vec::new(),
+            tail_calls: Vec::new(),
+            steps: 0,
+            #[cfg(feature = "std")]
+            deadline: data.timeout.map(|timeout| Instant::now() + timeout),
         }
     }
 
+    // Inner bindings shadow outer ones, and any of them shadow top-level
+    // definitions.
+    fn lookup(&self, name: &str) -> Option<&Function> {
+        self.owner(name).map(|i| &self.frames[i][name])
+    }
+
+    // Index of the frame a name is found in.
+    fn owner(&self, name: &str) -> Option<usize> {
+        (self.scope.max(1)..self.frames.len())
+            .rev()
+            .find(|i| self.frames[*i].contains_key(name))
+            .or_else(|| self.frames[0].contains_key(name).then_some(0))
+    }
+
     pub fn get_function(&self, name: &str) -> Option<Function> {
-        match self.frames[0].get(name) {
-            Some(function) => Some(function.clone()),
-            None => self.frames[self.scope..]
-                .iter()
-                .rev()
-                .find_map(|functions| functions.get(name))
-                .cloned(),
+        self.lookup(name).cloned()
+    }
+
+    fn arity(&self, data: &Data, name: &str) -> Option<usize> {
+        if BUILTIN_FUNCTIONS.contains(&name) {
+            Some(builtin_param_count(name))
+        } else {
+            match self.lookup(name) {
+                Some(function) => Some(function.params.len()),
+                None => data.natives.get(name).map(|native| native.arity),
+            }
+        }
+    }
+
+    // Everything visible from the current scope besides top-level definitions,
+    // flattened so that inner bindings shadow outer ones.
+    fn capture(&self) -> Frame {
+        let mut captured = Frame::new();
+        for functions in &self.frames[self.scope.max(1)..] {
+            captured.extend(functions.iter().map(|(k, v)| (k.clone(), v.clone())));
         }
+        captured
+    }
+
+    fn mark(&self) -> Mark {
+        Mark {
+            scope: self.scope,
+            frames: self.frames.len(),
+            operands: self.operands.len(),
+            calls: self.calls.len(),
+            scopes: self.scopes.len(),
+            lets: self.lets.len(),
+            fors: self.fors.len(),
+            loops: self.loops.len(),
+            pending: self.pending.len(),
+            tail_calls: self.tail_calls.len(),
+        }
+    }
+
+    // Puts the stack back the way it was at `mark`, however far a call got.
+    fn reset(&mut self, mark: Mark) {
+        self.scope = mark.scope;
+        self.frames.truncate(mark.frames);
+        self.operands.truncate(mark.operands);
+        self.calls.truncate(mark.calls);
+        self.scopes.truncate(mark.scopes);
+        self.lets.truncate(mark.lets);
+        self.fors.truncate(mark.fors);
+        self.loops.truncate(mark.loops);
+        self.pending.truncate(mark.pending);
+        self.tail_calls.truncate(mark.tail_calls);
     }
 }
 
+// How far the stack had got before calling back into user code.
+#[derive(Debug)]
+struct Mark {
+    scope: usize,
+    frames: usize,
+    operands: usize,
+    calls: usize,
+    scopes: usize,
+    lets: usize,
+    fors: usize,
+    loops: usize,
+    pending: usize,
+    tail_calls: usize,
+}
+
+// Work left for when the function in a frame returns, tagged with the number
+// of frames at that point.
+#[derive(Debug)]
+enum Pending {
+    // A shape to compose with the result of a tail call
+    Compose(Value),
+    // Where to keep the result of a cached definition
+    Cache(String, Vec<CacheKey>),
+}
+
 #[derive(Debug, Clone)]
 enum FunctionBlock {
     Value(Value),
     Start(usize),
-    HigherOrder,
 }
 
 #[derive(Debug, Clone)]
@@ -234,9 +529,69 @@ struct Function {
     params: Vec<String>,
     weighted: bool,
     base: bool,
+    cached: bool,
     blocks: Vec<(FunctionBlock, f32)>,
 }
 
+impl Function {
+    fn value(value: Value) -> Function {
+        Function {
+            params: vec![],
+            weighted: false,
+            base: false,
+            cached: false,
+            blocks: vec![(FunctionBlock::Value(value), 0.0)],
+        }
+    }
+}
+
+type NativeFn = dyn Fn(&[Value]) -> Result<Value>;
+
+/// A function provided by the application running a script, callable by name
+/// like any builtin. Definitions in the script itself take precedence.
+#[derive(Clone)]
+pub struct NativeFunction {
+    pub name: String,
+    pub arity: usize,
+    function: Rc<NativeFn>,
+}
+
+impl NativeFunction {
+    pub fn new<S, F>(name: S, arity: usize, function: F) -> NativeFunction
+    where
+        S: Into<String>,
+        F: Fn(&[Value]) -> Result<Value> + 'static,
+    {
+        NativeFunction {
+            name: name.into(),
+            arity,
+            function: Rc::new(function),
+        }
+    }
+
+    pub fn call(&self, args: &[Value]) -> Result<Value> {
+        (self.function)(args)
+    }
+}
+
+impl core::fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_struct("NativeFunction")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish_non_exhaustive()
+    }
+}
+
+/// An anonymous function along with the bindings that were in scope where it
+/// was created.
+#[derive(Debug)]
+pub struct Closure {
+    params: Vec<String>,
+    start: usize,
+    captured: Frame,
+}
+
 fn reduce_literal(literal: &Literal) -> Result<Value> {
     match literal {
         Literal::Integer(n) => Ok(Value::Integer(*n)),
@@ -269,19 +624,19 @@ fn reduce_literal(literal: &Literal) -> Result<Value> {
     }
 }
 
-fn reduce_call(
+fn reduce_call<'a>(
     stack: &mut Stack,
     rng: &mut ChaCha8Rng,
     data: &Data,
+    code: &Code<'a>,
     name: &str,
     mut args: Vec<Value>,
 ) -> Result<FunctionBlock> {
     if BUILTIN_FUNCTIONS.contains(&name) {
         let param_count = builtin_param_count(name);
-        if args.len() > param_count {
-            stack.operands.extend(args.drain(param_count..));
-        } else if args.len() < param_count {
+        if args.len() < param_count {
             let argc = param_count - args.len();
+            args.reverse();
             return Ok(FunctionBlock::Value(Value::Function(
                 name.into(),
                 argc,
@@ -289,20 +644,39 @@ fn reduce_call(
             )));
         }
 
-        match name {
-            "map" => return Ok(FunctionBlock::HigherOrder),
-            _ => {
-                let value = handle_builtin(name, rng, data, &args)?;
-                Ok(FunctionBlock::Value(value))
-            }
-        }
+        let value = if HIGHER_ORDER_FUNCTIONS.contains(&name) {
+            let mut call = |function: &Value, args: Vec<Value>| {
+                call_value(stack, rng, data, code, function, args)
+            };
+            handle_higher_order(name, &mut call, &args)?
+        } else {
+            handle_builtin(name, rng, data, &args)?
+        };
+        Ok(FunctionBlock::Value(value))
     } else {
         match stack.get_function(name) {
             Some(function) => {
-                if args.len() > function.params.len() {
-                    stack.operands.extend(args.drain(function.params.len()..));
-                } else if args.len() < function.params.len() {
+                if args.len() < function.params.len() {
                     let argc = function.params.len() - args.len();
+                    args.reverse();
+
+                    // Local definitions take their bindings along with them,
+                    // since they can't be looked up by name once out of scope.
+                    if let (Some(1..), FunctionBlock::Start(start)) =
+                        (stack.owner(name), &function.blocks[0].0)
+                    {
+                        let closure = Closure {
+                            params: function.params.clone(),
+                            start: *start,
+                            captured: stack.capture(),
+                        };
+                        return Ok(FunctionBlock::Value(Value::Closure(
+                            Rc::new(closure),
+                            argc,
+                            args,
+                        )));
+                    }
+
                     return Ok(FunctionBlock::Value(Value::Function(
                         name.into(),
                         argc,
@@ -310,6 +684,21 @@ fn reduce_call(
                     )));
                 }
 
+                let key = if function.cached {
+                    args.iter().map(CacheKey::new).collect::<Option<Vec<_>>>()
+                } else {
+                    None
+                };
+                if let Some(value) = key.as_ref().and_then(|key| {
+                    let cache = data.cache.borrow();
+                    cache
+                        .get(name)
+                        .and_then(|values| values.get(key))
+                        .map(detach)
+                }) {
+                    return Ok(FunctionBlock::Value(value));
+                }
+
                 let (_i, block) = if function.weighted {
                     let index_weights = function.blocks.iter().enumerate();
                     let dist =
@@ -328,20 +717,9 @@ fn reduce_call(
                     FunctionBlock::Start(start) => {
                         let functions = function
                             .params
-                            .clone()
                             .iter()
                             .zip(args)
-                            .map(|(param, arg)| {
-                                (
-                                    param.into(),
-                                    Function {
-                                        params: vec![],
-                                        weighted: false,
-                                        base: false,
-                                        blocks: vec![(FunctionBlock::Value(arg), 0.0)],
-                                    },
-                                )
-                            })
+                            .map(|(param, arg)| (param.clone(), Function::value(arg)))
                             .collect();
 
                         if function.base {
@@ -351,14 +729,273 @@ fn reduce_call(
                         stack.frames.push(functions);
                         stack.lets.push(false);
 
+                        if let Some(key) = key {
+                            let pending = Pending::Cache(name.into(), key);
+                            stack.pending.push((stack.frames.len(), pending));
+                        }
+
                         Ok(FunctionBlock::Start(*start))
                     }
-                    _ => unreachable!(),
                 }
             }
-            None => Err(Error::UnknownFunction(name.into())),
+            None if data.natives.contains_key(name) => {
+                let native = &data.natives[name];
+                if args.len() < native.arity {
+                    let argc = native.arity - args.len();
+                    args.reverse();
+                    return Ok(FunctionBlock::Value(Value::Function(
+                        name.into(),
+                        argc,
+                        args,
+                    )));
+                }
+                Ok(FunctionBlock::Value(native.call(&args)?))
+            }
+            // `record.field` reads a field of whatever `record` evaluates to.
+            None => match name.split_once('.') {
+                Some((head, fields))
+                    if stack.lookup(head).is_some()
+                        || BUILTIN_FUNCTIONS.contains(&head)
+                        || data.natives.contains_key(head) =>
+                {
+                    let mut value = call_name(stack, rng, data, code, head, Vec::new())?;
+                    for field in fields.split('.') {
+                        value = match value {
+                            Value::Record(mut record) => record
+                                .remove(field)
+                                .ok_or_else(|| Error::UnknownField(field.into()))?,
+                            _ => return Err(Error::NotARecord),
+                        };
+                    }
+
+                    if !args.is_empty() {
+                        value = call_value(stack, rng, data, code, &value, args)?;
+                    }
+                    Ok(FunctionBlock::Value(value))
+                }
+                _ => Err(Error::UnknownFunction(name.into())),
+            },
+        }
+    }
+}
+
+// Copies the shapes in a value, since builtins change a shape in place when
+// nothing else seems to be holding onto it.
+fn detach(value: &Value) -> Value {
+    match value {
+        Value::Shape(shape) => Value::Shape(Rc::new(RefCell::new(shape.borrow().clone()))),
+        Value::List(list) => Value::List(list.iter().map(detach).collect()),
+        Value::Tuple(values) => Value::Tuple(values.iter().map(detach).collect()),
+        Value::Record(fields) => Value::Record(
+            fields
+                .iter()
+                .map(|(name, value)| (name.clone(), detach(value)))
+                .collect(),
+        ),
+        value => value.clone(),
+    }
+}
+
+fn reduce_closure(stack: &mut Stack, closure: &Closure, args: Vec<Value>) -> FunctionBlock {
+    let mut functions = closure.captured.clone();
+    for (param, arg) in closure.params.iter().zip(args) {
+        functions.insert(param.clone(), Function::value(arg));
+    }
+
+    stack.scope = stack.frames.len();
+    stack.frames.push(functions);
+    stack.lets.push(false);
+
+    FunctionBlock::Start(closure.start)
+}
+
+// Applies a function value to at most as many arguments as it still needs.
+fn apply<'a>(
+    stack: &mut Stack,
+    rng: &mut ChaCha8Rng,
+    data: &Data,
+    code: &Code<'a>,
+    function: &Value,
+    mut args: Vec<Value>,
+) -> Result<FunctionBlock> {
+    match function {
+        Value::Function(_, argc, pre_args) | Value::Closure(_, argc, pre_args)
+            if args.len() < *argc =>
+        {
+            let argc = argc - args.len();
+            args.reverse();
+            args.extend(pre_args.iter().cloned());
+            match function {
+                Value::Function(name, _, _) => Ok(FunctionBlock::Value(Value::Function(
+                    name.clone(),
+                    argc,
+                    args,
+                ))),
+                Value::Closure(closure, _, _) => Ok(FunctionBlock::Value(Value::Closure(
+                    closure.clone(),
+                    argc,
+                    args,
+                ))),
+                _ => unreachable!(),
+            }
+        }
+        Value::Function(name, _, pre_args) => {
+            // Local definitions are captured as closures, so a function
+            // passed around by name is a top-level, builtin or native one.
+            // The caller's bindings are hidden so that a parameter or let of
+            // the same name can't take its place.
+            stack.scope = stack.frames.len();
+            let args = pre_args.iter().rev().cloned().chain(args).collect();
+            reduce_call(stack, rng, data, code, name, args)
+        }
+        Value::Closure(closure, _, pre_args) => {
+            let args = pre_args.iter().rev().cloned().chain(args).collect();
+            Ok(reduce_closure(stack, closure, args))
+        }
+        _ => Err(Error::NotCallable),
+    }
+}
+
+/// Calls a function value and runs it to completion, for builtins that call
+/// back into user code. Any arguments beyond what the function takes are
+/// applied to its result.
+fn call_value<'a>(
+    stack: &mut Stack,
+    rng: &mut ChaCha8Rng,
+    data: &Data,
+    code: &Code<'a>,
+    function: &Value,
+    mut args: Vec<Value>,
+) -> Result<Value> {
+    let extra = match function {
+        Value::Function(_, argc, _) | Value::Closure(_, argc, _) if args.len() > *argc => {
+            args.split_off(*argc)
+        }
+        _ => Vec::new(),
+    };
+
+    let mark = stack.mark();
+    let value = apply(stack, rng, data, code, function, args)
+        .and_then(|function_block| finish_call(stack, rng, data, code, function_block));
+    stack.reset(mark);
+    let value = value?;
+
+    if extra.is_empty() {
+        Ok(value)
+    } else {
+        call_value(stack, rng, data, code, &value, extra)
+    }
+}
+
+// Calls a definition by name from the current scope and runs it to
+// completion.
+fn call_name<'a>(
+    stack: &mut Stack,
+    rng: &mut ChaCha8Rng,
+    data: &Data,
+    code: &Code<'a>,
+    name: &str,
+    args: Vec<Value>,
+) -> Result<Value> {
+    let mark = stack.mark();
+    let value = reduce_call(stack, rng, data, code, name, args)
+        .and_then(|function_block| finish_call(stack, rng, data, code, function_block));
+    stack.reset(mark);
+    value
+}
+
+// Runs a call that's been made until it returns. Callers put the stack back
+// afterwards.
+fn finish_call<'a>(
+    stack: &mut Stack,
+    rng: &mut ChaCha8Rng,
+    data: &Data,
+    code: &Code<'a>,
+    function_block: FunctionBlock,
+) -> Result<Value> {
+    match function_block {
+        FunctionBlock::Value(value) => Ok(value),
+        FunctionBlock::Start(start) => {
+            // Callbacks run in a loop of their own, but count towards the max
+            // depth along with the calls they're made from.
+            stack.calls.push(start);
+            start_block(stack, rng, data, code, start)
+        }
+    }
+}
+
+// Matches a value against a pattern, adding any names it binds to `frame`.
+fn bind_pattern(a: &Value, pattern: &Pattern, frame: &mut Frame) -> Result<bool> {
+    match pattern {
+        Pattern::Matches(matches) => {
+            for b in matches {
+                if pattern_match(a, b)? {
+                    return Ok(true);
+                }
+            }
+            Ok(false)
+        }
+        Pattern::Range(start, end, inclusive) => range_match(a, start, end, *inclusive),
+        Pattern::Wildcard => Ok(true),
+        Pattern::Bind(name) => {
+            frame.insert(name.clone(), Function::value(a.clone()));
+            Ok(true)
+        }
+        Pattern::Tuple(patterns) => match a {
+            Value::Tuple(values) if values.len() == patterns.len() => {
+                bind_all(values, patterns, frame)
+            }
+            Value::Tuple(_) => Ok(false),
+            _ => Err(Error::InvalidMatch),
+        },
+        Pattern::List(patterns, rest) => {
+            let values = match a {
+                Value::List(list) => list.clone(),
+                Value::String(s) => s.chars().map(Value::Char).collect(),
+                _ => return Err(Error::InvalidMatch),
+            };
+
+            let fits = match rest {
+                Some(_) => values.len() >= patterns.len(),
+                None => values.len() == patterns.len(),
+            };
+            if !fits || !bind_all(&values[..patterns.len()], patterns, frame)? {
+                return Ok(false);
+            }
+
+            match rest {
+                Some(rest) => {
+                    let value = match a {
+                        Value::String(s) => Value::String(s.chars().skip(patterns.len()).collect()),
+                        _ => Value::List(values[patterns.len()..].to_vec()),
+                    };
+                    bind_pattern(&value, rest, frame)
+                }
+                None => Ok(true),
+            }
+        }
+        Pattern::Record(fields) => match a {
+            Value::Record(record) => {
+                for (name, pattern) in fields {
+                    match record.get(name) {
+                        Some(value) if bind_pattern(value, pattern, frame)? => (),
+                        _ => return Ok(false),
+                    }
+                }
+                Ok(true)
+            }
+            _ => Err(Error::InvalidMatch),
+        },
+    }
+}
+
+fn bind_all(values: &[Value], patterns: &[Pattern], frame: &mut Frame) -> Result<bool> {
+    for (value, pattern) in values.iter().zip(patterns) {
+        if !bind_pattern(value, pattern, frame)? {
+            return Ok(false);
         }
     }
+    Ok(true)
 }
 
 fn pattern_match(a: &Value, b: &Literal) -> Result<bool> {
@@ -368,80 +1005,176 @@ fn pattern_match(a: &Value, b: &Literal) -> Result<bool> {
         (Value::Integer(a), Literal::Float(b)) | (Value::Float(b), Literal::Integer(a)) => {
             Ok(*a as f32 == *b)
         }
+        (Value::Complex(a), Literal::Complex(b)) => Ok(a == b),
         (Value::Boolean(a), Literal::Boolean(b)) => Ok(a == b),
         (Value::Hex(a), Literal::Hex(b)) => Ok(a == b),
-        (Value::Shape(_a), Literal::Shape(_b)) => todo!(),
+        (Value::Char(a), Literal::Char(b)) => Ok(a == b),
+        (Value::String(a), Literal::String(b)) => Ok(a == b),
+        // Shapes match the literal they were made from, however they've been
+        // transformed or styled since.
+        (Value::Shape(a), Literal::Shape(b)) => {
+            let kind = match &*a.borrow() {
+                Shape::Basic(BasicShape::Square { .. }, ..) => Some(ShapeKind::Square),
+                Shape::Basic(BasicShape::Circle { .. }, ..) => Some(ShapeKind::Circle),
+                Shape::Basic(BasicShape::Triangle { .. }, ..) => Some(ShapeKind::Triangle),
+                Shape::Basic(BasicShape::Fill { .. }, ..) => Some(ShapeKind::Fill),
+                Shape::Basic(BasicShape::Empty, ..) => Some(ShapeKind::Empty),
+                _ => None,
+            };
+            Ok(kind.as_ref() == Some(b))
+        }
+        (Value::BlendMode(a), Literal::BlendMode(b)) => Ok(a == b),
+        (Value::LineCap(a), Literal::LineCap(b)) => Ok(a == b),
+        (Value::LineJoin(a), Literal::LineJoin(b)) => Ok(a == b),
+        (Value::SpreadMode(a), Literal::SpreadMode(b)) => Ok(a == b),
+        (Value::FilterQuality(a), Literal::FilterQuality(b)) => Ok(a == b),
+        (Value::FilterType(a), Literal::FilterType(b)) => Ok(a == b),
+        (Value::ThresholdType(a), Literal::ThresholdType(b)) => Ok(a == b),
+        (Value::Norm(a), Literal::Norm(b)) => Ok(a == b),
+        (Value::SortMode(a), Literal::SortMode(b)) => Ok(a == b),
+        (Value::SortDirection(a), Literal::SortDirection(b)) => Ok(a == b),
         _ => return Err(Error::InvalidMatch),
     }
 }
 
+fn range_match(a: &Value, start: &Literal, end: &Literal, inclusive: bool) -> Result<bool> {
+    let number = |literal: &Literal| match literal {
+        Literal::Integer(n) => Some(*n as f32),
+        Literal::Float(n) => Some(*n),
+        _ => None,
+    };
+
+    match (a, start, end) {
+        (Value::Char(c), Literal::Char(start), Literal::Char(end)) => Ok(if inclusive {
+            (start..=end).contains(&c)
+        } else {
+            (start..end).contains(&c)
+        }),
+        (Value::Integer(_) | Value::Float(_), _, _) => {
+            let n = match a {
+                Value::Integer(n) => *n as f32,
+                Value::Float(n) => *n,
+                _ => unreachable!(),
+            };
+            match (number(start), number(end)) {
+                (Some(start), Some(end)) if inclusive => Ok(start <= n && n <= end),
+                (Some(start), Some(end)) => Ok(start <= n && n < end),
+                _ => Err(Error::InvalidMatch),
+            }
+        }
+        _ => Err(Error::InvalidMatch),
+    }
+}
+
 #[derive(Debug)]
 enum Operand {
     Value(Option<Value>),
     Function,
 }
 
-fn next_operand(
+fn next_operand<'a>(
     stack: &mut Stack,
     rng: &mut ChaCha8Rng,
     data: &Data,
+    code: &Code<'a>,
     index: &mut usize,
 ) -> Result<Operand> {
     match stack.operands.pop() {
-        Some(Value::Function(name, argc, pre_args)) => {
-            let mut args = Vec::with_capacity(pre_args.len() + argc);
-            args.extend(pre_args);
-
-            for _ in 0..argc {
-                let arg = match next_operand(stack, rng, data, index)? {
-                    Operand::Value(value) => value.unwrap(),
-                    Operand::Function => {
-                        stack.operands.extend(args);
-                        return Ok(Operand::Function);
-                    }
-                };
-                args.push(arg);
-            }
-            args.reverse();
-
-            let function_block = reduce_call(stack, rng, data, &name, args)?;
-            match function_block {
-                FunctionBlock::Value(value) => Ok(Operand::Value(Some(value))),
+        // Functions that have been given every argument, such as the result of
+        // piping into a partial application, are called once they're used.
+        Some(function @ (Value::Function(_, 0, _) | Value::Closure(_, 0, _))) => {
+            stack.scopes.push(stack.scope);
+            match apply(stack, rng, data, code, &function, Vec::new())? {
+                FunctionBlock::Value(value) => {
+                    stack.scope = stack.scopes.pop().unwrap();
+                    Ok(Operand::Value(Some(value)))
+                }
                 FunctionBlock::Start(start) => {
                     stack.calls.push(*index);
                     *index = start;
                     Ok(Operand::Function)
                 }
-                FunctionBlock::HigherOrder => todo!(),
             }
         }
         value => Ok(Operand::Value(value)),
     }
 }
 
+// Follows what's left to run once a call returns. If it's only leaving lets
+// and match arms, composing and returning, the call is in tail position.
+// Returns how many frames are left and how many compositions are made.
+fn tail_call(block: &[Token], mut index: usize) -> Option<(usize, usize)> {
+    let mut pops = 0;
+    let mut compositions = 0;
+    loop {
+        match block.get(index)? {
+            Token::Jump(skip) => index += skip + 1,
+            Token::Pop => {
+                pops += 1;
+                index += 1;
+            }
+            Token::BinaryOperator(BinaryOperator::Composition) => {
+                compositions += 1;
+                index += 1;
+            }
+            Token::Return(_) => return Some((pops, compositions)),
+            _ => return None,
+        }
+    }
+}
+
 fn start_block<'a>(
-    stack: &mut Stack<'a>,
+    stack: &mut Stack,
     rng: &mut ChaCha8Rng,
     data: &Data,
-    block: &[Token<'a>],
+    code: &Code<'a>,
     start: usize,
 ) -> Result<Value> {
     let mut index = start;
+    run_block(stack, rng, data, code, &mut index).map_err(|e| code.locate(e, index))
+}
+
+// Runs until the function that starts at `index` returns. Calls made along the
+// way are handled in the same loop rather than by recursing.
+fn run_block<'a>(
+    stack: &mut Stack,
+    rng: &mut ChaCha8Rng,
+    data: &Data,
+    code: &Code<'a>,
+    index: &mut usize,
+) -> Result<Value> {
+    let block = &code.tokens;
+    let depth = stack.calls.len();
 
-    'a: while index < block.len() {
+    'a: while *index < block.len() {
         if stack.calls.len() > data.max_depth {
             return Err(Error::MaxDepthReached);
         }
 
-        match &block[index] {
+        stack.steps += 1;
+        if data.fuel.is_some_and(|fuel| stack.steps > fuel) {
+            return Err(Error::OutOfFuel);
+        }
+        // Reading the clock is slow next to a step, so it's only done every
+        // so often.
+        #[cfg(feature = "std")]
+        if stack.steps.is_multiple_of(1024)
+            && stack
+                .deadline
+                .is_some_and(|deadline| Instant::now() > deadline)
+        {
+            return Err(Error::Timeout);
+        }
+
+        match &block[*index] {
             Token::Literal(literal) => {
                 stack.operands.push(reduce_literal(literal)?);
-                index += 1;
+                *index += 1;
             }
             Token::List(size) => {
                 let mut elems = Vec::with_capacity(*size);
                 for _ in 0..*size {
-                    let elem = match next_operand(stack, rng, data, &mut index)? {
+                    let elem = match next_operand(stack, rng, data, code, index)? {
                         Operand::Value(value) => value.unwrap(),
                         Operand::Function => continue 'a,
                     };
@@ -469,28 +1202,86 @@ fn start_block<'a>(
                 };
 
                 stack.operands.push(list);
-                index += 1;
+                *index += 1;
             }
-            Token::UnaryOperator(op) => {
-                let arg = match next_operand(stack, rng, data, &mut index)? {
-                    Operand::Value(value) => value.unwrap(),
-                    Operand::Function => continue 'a,
-                };
+            Token::Tuple(size) => {
+                let mut elems = Vec::with_capacity(*size);
+                for _ in 0..*size {
+                    let elem = match next_operand(stack, rng, data, code, index)? {
+                        Operand::Value(value) => value.unwrap(),
+                        Operand::Function => continue 'a,
+                    };
+                    elems.push(elem);
+                }
+                elems.reverse();
 
-                let value = handle_builtin(op.as_str(), rng, data, &[arg])?;
-                stack.operands.push(value);
-                index += 1;
+                stack.operands.push(Value::Tuple(elems));
+                *index += 1;
             }
-            Token::BinaryOperator(op) => {
-                let b = match op {
-                    BinaryOperator::Pipe => stack.operands.pop().unwrap(),
-                    _ => match next_operand(stack, rng, data, &mut index)? {
+            Token::Record(names) => {
+                let mut values = Vec::with_capacity(names.len());
+                for _ in 0..names.len() {
+                    let value = match next_operand(stack, rng, data, code, index)? {
                         Operand::Value(value) => value.unwrap(),
                         Operand::Function => continue 'a,
-                    },
+                    };
+                    values.push(value);
+                }
+
+                let record = names
+                    .iter()
+                    .rev()
+                    .map(|name| (*name).into())
+                    .zip(values)
+                    .collect();
+                stack.operands.push(Value::Record(record));
+                *index += 1;
+            }
+            Token::Update(names) => {
+                let mut values = Vec::with_capacity(names.len());
+                for _ in 0..names.len() {
+                    let value = match next_operand(stack, rng, data, code, index)? {
+                        Operand::Value(value) => value.unwrap(),
+                        Operand::Function => continue 'a,
+                    };
+                    values.push(value);
+                }
+
+                let mut record = match next_operand(stack, rng, data, code, index)? {
+                    Operand::Value(Some(Value::Record(record))) => record,
+                    Operand::Value(_) => return Err(Error::NotARecord),
+                    Operand::Function => continue 'a,
+                };
+                for (name, value) in names.iter().rev().zip(values) {
+                    match record.get_mut(*name) {
+                        Some(field) => *field = value,
+                        None => return Err(Error::UnknownField((*name).into())),
+                    }
+                }
+
+                stack.operands.push(Value::Record(record));
+                *index += 1;
+            }
+            Token::UnaryOperator(op) => {
+                let arg = match next_operand(stack, rng, data, code, index)? {
+                    Operand::Value(value) => value.unwrap(),
+                    Operand::Function => continue 'a,
+                };
+
+                let value = handle_builtin(op.as_str(), rng, data, &[arg])?;
+                stack.operands.push(value);
+                *index += 1;
+            }
+            Token::BinaryOperator(op) => {
+                let b = match op {
+                    BinaryOperator::Pipe => stack.operands.pop().unwrap(),
+                    _ => match next_operand(stack, rng, data, code, index)? {
+                        Operand::Value(value) => value.unwrap(),
+                        Operand::Function => continue 'a,
+                    },
                 };
 
-                let a = match next_operand(stack, rng, data, &mut index)? {
+                let a = match next_operand(stack, rng, data, code, index)? {
                     Operand::Value(value) => value.unwrap(),
                     Operand::Function => {
                         stack.operands.push(b);
@@ -498,136 +1289,182 @@ fn start_block<'a>(
                     }
                 };
 
-                let value = handle_builtin(op.as_str(), rng, data, &[a, b])?;
+                let value = match op {
+                    // Composition calls back into user code, so it's left
+                    // waiting for the arguments of the function on the right
+                    // like a partial application.
+                    BinaryOperator::FunctionComposition => {
+                        let argc = match b {
+                            Value::Function(_, argc, _) | Value::Closure(_, argc, _) => argc,
+                            _ => 1,
+                        };
+                        Value::Function(op.as_str().into(), argc, vec![b, a])
+                    }
+                    _ => handle_builtin(op.as_str(), rng, data, &[a, b])?,
+                };
+                data.check_value(&value)?;
                 stack.operands.push(value);
-                index += 1;
+                *index += 1;
             }
             Token::Call(name, argc) => {
                 let mut args = Vec::with_capacity(*argc);
                 for _ in 0..*argc {
-                    // let arg = match next_operand(stack, rng, data, &mut index)? {
-                    //     Operand::Value(value) => value.unwrap(),
-                    //     Operand::Function => {
-                    //         stack.operands.extend(args);
-                    //         continue 'a;
-                    //     }
-                    // };
-                    // args.push(arg);
                     args.push(stack.operands.pop().unwrap());
                 }
                 args.reverse();
 
-                stack.scopes.push(stack.scope);
-                let function_block = reduce_call(stack, rng, data, name, args.clone())?;
+                let tail = tail_call(block, *index + 1);
+                let mut owner = stack.owner(name);
+                let frames = stack.frames.len();
+
+                // Arguments beyond what the function takes are applied to
+                // whatever it returns.
+                let function_block = match stack.arity(data, name) {
+                    Some(arity) if args.len() > arity => {
+                        let extra = args.split_off(arity);
+                        let value = call_name(stack, rng, data, code, name, args)?;
+                        owner = None;
+                        stack.scopes.push(stack.scope);
+                        match value {
+                            Value::Function(_, argc, _) | Value::Closure(_, argc, _)
+                                if extra.len() <= argc =>
+                            {
+                                apply(stack, rng, data, code, &value, extra)?
+                            }
+                            _ => FunctionBlock::Value(call_value(
+                                stack, rng, data, code, &value, extra,
+                            )?),
+                        }
+                    }
+                    _ => {
+                        stack.scopes.push(stack.scope);
+                        reduce_call(stack, rng, data, code, name, args)?
+                    }
+                };
+
                 match function_block {
                     FunctionBlock::Value(value) => {
+                        data.check_value(&value)?;
                         stack.operands.push(value);
                         stack.scopes.pop().unwrap();
-                        index += 1;
+                        *index += 1;
                     }
                     FunctionBlock::Start(start) => {
-                        stack.calls.push(index + 1);
-                        index = start;
-                    }
-                    FunctionBlock::HigherOrder => match *name {
-                        "map" => match (&args[0], &args[1]) {
-                            (Value::Function(name, _argc, pre_args), Value::List(list)) => {
-                                index += 1;
-
-                                let mut frames = Vec::new();
-                                let mut lets = Vec::new();
-                                let mut values = Vec::new();
-                                for value in list.clone() {
-                                    let mut args = Vec::with_capacity(pre_args.len() + 1);
-                                    args.extend(pre_args.clone());
-                                    args.push(value);
-                                    args.reverse();
-
-                                    let function_block =
-                                        reduce_call(stack, rng, data, &name, args)?;
-                                    match function_block {
-                                        FunctionBlock::Value(value) => values.push(value),
-                                        FunctionBlock::Start(start) => {
-                                            frames.push(stack.frames.pop().unwrap());
-                                            lets.push(false);
-                                            stack.calls.push(index);
-                                            index = start;
-                                        }
-                                        FunctionBlock::HigherOrder => todo!(),
-                                    }
+                        if let Some((pops, compositions)) = tail {
+                            // The frame of the function making the call and
+                            // those of any lets or match arms it's inside.
+                            let base = frames - pops - 1;
+                            let scoped = stack.scope == frames;
+                            let reusable = (scoped || owner.is_some_and(|owner| owner < base))
+                                && stack.operands.len() >= compositions
+                                && stack.operands[stack.operands.len() - compositions..]
+                                    .iter()
+                                    .all(|value| {
+                                        !matches!(
+                                            value,
+                                            Value::Function(_, 0, _) | Value::Closure(_, 0, _)
+                                        )
+                                    });
+
+                            // Nothing is left to do once the call returns, so
+                            // it takes the place of the function making it
+                            // rather than nesting inside.
+                            if reusable {
+                                let level = stack.calls.len();
+                                match stack.tail_calls.last_mut() {
+                                    Some((tag, count)) if *tag == level => *count += 1,
+                                    _ => stack.tail_calls.push((level, 1)),
+                                }
+                                if stack.tail_calls.last().unwrap().1 > data.max_tail_calls {
+                                    return Err(Error::MaxTailCallsReached);
+                                }
+
+                                // Caching the callee's result comes before
+                                // composing it with what the caller had.
+                                let cache =
+                                    stack.pending.pop_if(|(tag, _)| *tag == stack.frames.len());
+
+                                stack.scopes.pop().unwrap();
+                                let frame = stack.frames.pop().unwrap();
+                                stack.frames.truncate(base);
+                                stack.frames.push(frame);
+                                stack.lets.truncate(base);
+                                stack.lets.push(false);
+                                if scoped {
+                                    stack.scope = base;
                                 }
 
-                                if frames.len() == 0 {
-                                    let list = Value::List(values);
-                                    list.kind()?;
-                                    stack.operands.push(list);
-                                } else {
-                                    stack.higher_order = Some(HigherOrder::Map(
-                                        index,
-                                        frames.len() - values.len(),
-                                        values,
-                                    ));
-                                    frames.reverse();
-                                    stack.frames.extend(frames);
-                                    stack.lets.extend(lets);
+                                let shapes = stack
+                                    .operands
+                                    .split_off(stack.operands.len() - compositions);
+                                stack.pending.extend(
+                                    shapes
+                                        .into_iter()
+                                        .map(|shape| (base + 1, Pending::Compose(shape))),
+                                );
+                                if let Some((_, cache)) = cache {
+                                    stack.pending.push((base + 1, cache));
                                 }
+
+                                *index = start;
+                                continue;
                             }
-                            _ => return Err(Error::UnknownFunction("map".into())),
-                        },
-                        _ => unreachable!(),
-                    },
+                        }
+
+                        stack.calls.push(*index + 1);
+                        *index = start;
+                    }
                 }
             }
-            Token::Jump(skip) => index += skip + 1,
+            Token::Jump(skip) => *index += skip + 1,
             Token::Pop => {
                 stack.frames.pop().unwrap();
                 stack.lets.pop().unwrap();
-                index += 1;
+                *index += 1;
             }
-            Token::Return(start) => {
-                let value = match next_operand(stack, rng, data, &mut index)? {
-                    Operand::Value(value) => value,
+            Token::Return(_) => {
+                let value = match next_operand(stack, rng, data, code, index)? {
+                    Operand::Value(value) => value.unwrap(),
                     Operand::Function => continue 'a,
                 };
 
-                match stack.calls.pop() {
-                    Some(last_index) => {
-                        match &mut stack.higher_order {
-                            Some(higher_order) => match higher_order {
-                                HigherOrder::Map(other_start, count, values)
-                                    if *start == Some(*other_start) =>
-                                {
-                                    *count -= 1;
-                                    values.push(value.unwrap());
-
-                                    if *count == 0 {
-                                        let list = Value::List(values.clone());
-                                        list.kind()?;
-                                        stack.operands.push(list);
-                                        stack.higher_order = None;
-                                    }
-                                }
-                                _ => (),
-                            },
-                            _ => {
-                                stack.operands.push(value.unwrap());
-                            }
+                let mut value = value;
+                while let Some((_, pending)) =
+                    stack.pending.pop_if(|(tag, _)| *tag == stack.frames.len())
+                {
+                    match pending {
+                        Pending::Compose(shape) => {
+                            let op = BinaryOperator::Composition.as_str();
+                            value = handle_builtin(op, rng, data, &[shape, value])?;
+                        }
+                        Pending::Cache(name, key) => {
+                            let mut cache = data.cache.borrow_mut();
+                            cache.entry(name).or_default().insert(key, detach(&value));
                         }
-
-                        stack.frames.pop().unwrap();
-                        stack.lets.pop().unwrap();
-                        stack.scope = stack.scopes.pop().unwrap();
-                        index = last_index;
                     }
-                    None => return Ok(value.unwrap()),
                 }
+
+                stack
+                    .tail_calls
+                    .pop_if(|(tag, _)| *tag == stack.calls.len());
+
+                if stack.calls.len() == depth {
+                    return Ok(value);
+                }
+
+                stack.operands.push(value);
+                stack.frames.pop().unwrap();
+                stack.lets.pop().unwrap();
+                stack.scope = stack.scopes.pop().unwrap();
+                *index = stack.calls.pop().unwrap();
             }
             Token::Let(name, params, skip) => {
                 let func = Function {
                     params: params.iter().map(|s| (*s).into()).collect(),
                     weighted: false,
                     base: false,
-                    blocks: vec![(FunctionBlock::Start(index + 1), 0.0)],
+                    cached: false,
+                    blocks: vec![(FunctionBlock::Start(*index + 1), 0.0)],
                 };
                 if *stack.lets.last().unwrap() {
                     stack
@@ -639,10 +1476,39 @@ fn start_block<'a>(
                     stack.frames.push([((*name).into(), func)].into());
                     stack.lets.push(true);
                 }
-                index += skip + 1;
+                *index += skip + 1;
+            }
+            Token::Destructure(pattern) => {
+                let value = match next_operand(stack, rng, data, code, index)? {
+                    Operand::Value(value) => value.unwrap(),
+                    Operand::Function => continue 'a,
+                };
+
+                let mut frame = Frame::new();
+                if !bind_pattern(&value, pattern, &mut frame)? {
+                    return Err(Error::MatchNotFound);
+                }
+                if *stack.lets.last().unwrap() {
+                    stack.frames.last_mut().unwrap().extend(frame);
+                } else {
+                    stack.frames.push(frame);
+                    stack.lets.push(true);
+                }
+                *index += 1;
+            }
+            Token::Lambda(params, skip) => {
+                let closure = Closure {
+                    params: params.iter().map(|s| (*s).into()).collect(),
+                    start: *index + 1,
+                    captured: stack.capture(),
+                };
+                stack
+                    .operands
+                    .push(Value::Closure(Rc::new(closure), params.len(), Vec::new()));
+                *index += skip + 1;
             }
             Token::If(skip) => {
-                let condition = match next_operand(stack, rng, data, &mut index)? {
+                let condition = match next_operand(stack, rng, data, code, index)? {
                     Operand::Value(value) => value.unwrap(),
                     Operand::Function => continue 'a,
                 };
@@ -652,13 +1518,13 @@ fn start_block<'a>(
                 };
 
                 if is_true {
-                    index += 1;
+                    *index += 1;
                 } else {
-                    index += skip + 1;
+                    *index += skip + 1;
                 }
             }
             Token::Match(patterns) => {
-                let a = match next_operand(stack, rng, data, &mut index)? {
+                let a = match next_operand(stack, rng, data, code, index)? {
                     Operand::Value(value) => value.unwrap(),
                     Operand::Function => continue 'a,
                 };
@@ -666,7 +1532,7 @@ fn start_block<'a>(
                 let mut found = false;
                 'b: for (pattern, has_guard, skip) in patterns {
                     let is_guard = if *has_guard {
-                        let condition = match next_operand(stack, rng, data, &mut index)? {
+                        let condition = match next_operand(stack, rng, data, code, index)? {
                             Operand::Value(value) => value.unwrap(),
                             Operand::Function => continue 'a,
                         };
@@ -678,35 +1544,26 @@ fn start_block<'a>(
                         true
                     };
 
-                    if is_guard {
-                        match pattern {
-                            Pattern::Matches(matches) => {
-                                for b in matches {
-                                    if pattern_match(&a, b)? {
-                                        found = true;
-                                        break 'b;
-                                    }
-                                }
-                                index += skip;
-                            }
-                            Pattern::Wildcard => {
-                                found = true;
-                                break 'b;
-                            }
+                    let mut frame = Frame::new();
+                    if is_guard && bind_pattern(&a, pattern, &mut frame)? {
+                        if pattern.binds() {
+                            stack.frames.push(frame);
+                            stack.lets.push(false);
                         }
-                    } else {
-                        index += skip;
+                        found = true;
+                        break 'b;
                     }
+                    *index += skip;
                 }
 
                 if !found {
                     return Err(Error::MatchNotFound);
                 }
 
-                index += 1;
+                *index += 1;
             }
-            Token::ForStart(var) => {
-                let iter = match next_operand(stack, rng, data, &mut index)? {
+            Token::ForStart(pattern) => {
+                let iter = match next_operand(stack, rng, data, code, index)? {
                     Operand::Value(value) => value.unwrap(),
                     Operand::Function => continue 'a,
                 };
@@ -729,30 +1586,23 @@ fn start_block<'a>(
                 items.reverse();
 
                 let len = items.len();
-                stack.frames.push(
-                    [(
-                        (*var).into(),
-                        Function {
-                            params: vec![],
-                            weighted: false,
-                            base: false,
-                            blocks: vec![(FunctionBlock::Value(items.pop().unwrap()), 0.0)],
-                        },
-                    )]
-                    .into(),
-                );
+                let mut frame = Frame::new();
+                if !bind_pattern(&items.pop().unwrap(), pattern, &mut frame)? {
+                    return Err(Error::MatchNotFound);
+                }
+                stack.frames.push(frame);
                 stack.lets.push(false);
 
                 stack.fors.push(ForStack {
-                    start: index + 1,
-                    var: *var,
+                    start: *index + 1,
+                    pattern: pattern.clone(),
                     items,
                     values: Vec::with_capacity(len),
                 });
-                index += 1;
+                *index += 1;
             }
             Token::ForEnd => {
-                let value = match next_operand(stack, rng, data, &mut index)? {
+                let value = match next_operand(stack, rng, data, code, index)? {
                     Operand::Value(value) => value,
                     Operand::Function => continue 'a,
                 };
@@ -760,30 +1610,21 @@ fn start_block<'a>(
                     for_stack.values.push(value.unwrap());
 
                     if for_stack.items.len() > 0 {
-                        stack.frames.push(
-                            [(
-                                for_stack.var.into(),
-                                Function {
-                                    params: vec![],
-                                    weighted: false,
-                                    base: false,
-                                    blocks: vec![(
-                                        FunctionBlock::Value(for_stack.items.pop().unwrap()),
-                                        0.0,
-                                    )],
-                                },
-                            )]
-                            .into(),
-                        );
+                        let item = for_stack.items.pop().unwrap();
+                        let mut frame = Frame::new();
+                        if !bind_pattern(&item, &for_stack.pattern, &mut frame)? {
+                            return Err(Error::MatchNotFound);
+                        }
+                        stack.frames.push(frame);
                         stack.lets.push(false);
-                        index = for_stack.start; // Jump back to ForStart
+                        *index = for_stack.start; // Jump back to ForStart
                     } else {
                         let list = Value::List(for_stack.values.to_vec());
                         list.kind()?;
                         stack.operands.push(list);
 
                         stack.fors.pop().unwrap(); // Exit for loop
-                        index += 1;
+                        *index += 1;
                     }
                 } else {
                     if let Some(value) = value {
@@ -792,7 +1633,7 @@ fn start_block<'a>(
                 }
             }
             Token::LoopStart => {
-                let count = match next_operand(stack, rng, data, &mut index)? {
+                let count = match next_operand(stack, rng, data, code, index)? {
                     Operand::Value(value) => value.unwrap(),
                     Operand::Function => continue 'a,
                 };
@@ -804,16 +1645,17 @@ fn start_block<'a>(
                 if count < 0 {
                     return Err(Error::NotIterable);
                 }
+                data.check_length(count as usize)?;
 
                 stack.loops.push(LoopStack {
-                    start: index + 1,
+                    start: *index + 1,
                     remaining: count as usize,
                     values: Vec::with_capacity(count as usize),
                 });
-                index += 1;
+                *index += 1;
             }
             Token::LoopEnd => {
-                let value = match next_operand(stack, rng, data, &mut index)? {
+                let value = match next_operand(stack, rng, data, code, index)? {
                     Operand::Value(value) => value,
                     Operand::Function => continue 'a,
                 };
@@ -822,14 +1664,14 @@ fn start_block<'a>(
                     loop_stack.values.push(value.unwrap());
 
                     if loop_stack.remaining > 0 {
-                        index = loop_stack.start; // Jump back to LoopStart
+                        *index = loop_stack.start; // Jump back to LoopStart
                     } else {
                         let list = Value::List(loop_stack.values.to_vec());
                         list.kind()?;
                         stack.operands.push(list);
 
                         stack.loops.pop().unwrap(); // Exit loop
-                        index += 1;
+                        *index += 1;
                     }
                 } else {
                     if let Some(value) = value {
@@ -843,12 +1685,87 @@ fn start_block<'a>(
     Ok(stack.operands.pop().unwrap())
 }
 
+/// The flattened tokens of every definition, along with where each token came
+/// from in the source.
+#[derive(Debug, Clone, Default)]
+pub struct Code<'a> {
+    pub tokens: Vec<Token<'a>>,
+    pub spans: Vec<Span>,
+    pub definitions: Vec<(usize, String)>,
+}
+
+impl Code<'_> {
+    fn locate(&self, error: Error, index: usize) -> Error {
+        let span = self.spans.get(index).copied();
+        let definition = match self
+            .definitions
+            .binary_search_by_key(&index, |(start, _)| *start)
+        {
+            Ok(i) => Some(self.definitions[i].1.as_str()),
+            Err(0) => None,
+            Err(i) => Some(self.definitions[i - 1].1.as_str()),
+        };
+        // Expressions evaluated on their own are recorded without a name.
+        let definition = definition.filter(|name| !name.is_empty());
+        error.located(span, definition)
+    }
+}
+
+/// A loaded program, ready for its `start`, `model`, `update` and `view`
+/// functions to be run.
 #[derive(Debug)]
 pub struct Env<'a> {
-    pub rng: ChaCha8Rng,
-    pub data: Data,
-    pub functions: HashMap<String, Function>,
-    pub block: Vec<Token<'a>>,
+    rng: ChaCha8Rng,
+    data: Data,
+    functions: HashMap<String, Function>,
+    block: Code<'a>,
+}
+
+impl<'a> Env<'a> {
+    pub fn new(program: &'a Program, config: Config) -> Result<Env<'a>> {
+        load_env(program.tree()?, config)
+    }
+
+    /// Calls a definition of the program by name. Giving fewer arguments than
+    /// it takes returns a partially applied function.
+    pub fn call(&mut self, name: &str, args: Vec<Value>) -> Result<Value> {
+        let mut stack = Stack::new(self.functions.clone(), &self.data);
+        match reduce_call(
+            &mut stack,
+            &mut self.rng,
+            &self.data,
+            &self.block,
+            name,
+            args,
+        )? {
+            FunctionBlock::Start(start) => {
+                start_block(&mut stack, &mut self.rng, &self.data, &self.block, start)
+            }
+            FunctionBlock::Value(value) => Ok(value),
+        }
+    }
+
+    /// Evaluates an expression that can use any definition of the program.
+    /// Errors in the expression itself are located within it, without a
+    /// definition.
+    pub fn eval(&mut self, expression: &str) -> Result<Value> {
+        let (tokens, spans) = parse_expression(expression)?;
+
+        let mut block = self.block.clone();
+        let start = block.tokens.len();
+        block.tokens.extend(tokens);
+        block.tokens.push(Token::Return(Some(start)));
+        block.spans.extend(spans);
+        block.spans.push(Span::new(0, expression.len()));
+        block.definitions.push((start, String::new()));
+
+        // Run as if calling a definition without parameters.
+        let mut stack = Stack::new(self.functions.clone(), &self.data);
+        stack.scope = stack.frames.len();
+        stack.frames.push(HashMap::new());
+        stack.lets.push(false);
+        start_block(&mut stack, &mut self.rng, &self.data, &block, start)
+    }
 }
 
 pub fn load_env(tree: Tree, config: Config) -> Result<Env> {
@@ -869,20 +1786,46 @@ pub fn load_env(tree: Tree, config: Config) -> Result<Env> {
     let data = Data {
         dimensions: config.dimensions,
         max_depth: config.max_depth,
+        max_tail_calls: config.max_tail_calls,
+        fuel: config.fuel,
+        #[cfg(feature = "std")]
+        timeout: config.timeout,
+        max_list_length: config.max_list_length,
+        max_shapes: config.max_shapes,
+        #[cfg(feature = "std")]
+        sandbox: config.sandbox,
+        natives: config
+            .functions
+            .into_iter()
+            .map(|native| (native.name.clone(), native))
+            .collect(),
         perlin,
+        cache: RefCell::default(),
     };
 
     let mut functions: HashMap<String, Function> = HashMap::new();
-    let mut block = Vec::new();
+    let mut calls: HashMap<String, Vec<&str>> = HashMap::new();
+    let mut block = Code::default();
     for definition in tree {
-        let start = block.len();
-        block.extend(definition.block);
-        block.push(Token::Return(Some(start)));
+        calls.entry(definition.name.into()).or_default().extend(
+            definition.block.iter().filter_map(|token| match token {
+                Token::Call(name, _) => Some(*name),
+                _ => None,
+            }),
+        );
+
+        let start = block.tokens.len();
+        block.tokens.extend(definition.block);
+        block.tokens.push(Token::Return(Some(start)));
+        block.spans.extend(definition.spans);
+        block.spans.push(definition.span);
+        block.definitions.push((start, definition.name.into()));
 
         match functions.get_mut(definition.name) {
             Some(function) => {
                 if definition.params != function.params {
-                    return Err(Error::InvalidDefinition(definition.name.into()));
+                    return Err(Error::InvalidDefinition(definition.name.into())
+                        .located(Some(definition.span), Some(definition.name)));
                 }
 
                 function.weighted = true;
@@ -897,6 +1840,7 @@ pub fn load_env(tree: Tree, config: Config) -> Result<Env> {
                         params: definition.params.iter().map(|s| (*s).into()).collect(),
                         weighted: false,
                         base: true,
+                        cached: definition.memo,
                         blocks: vec![(FunctionBlock::Start(start), definition.weight)],
                     },
                 );
@@ -904,6 +1848,37 @@ pub fn load_env(tree: Tree, config: Config) -> Result<Env> {
         }
     }
 
+    // Definitions without parameters, or marked with `@memo`, are only run
+    // once for a given set of arguments. That is, unless they use randomness
+    // by being weighted or calling something random along the way. Native
+    // functions could be doing anything, so they count as random.
+    let mut random: HashSet<&str> = calls
+        .keys()
+        .filter(|name| functions[name.as_str()].weighted)
+        .map(String::as_str)
+        .collect();
+    loop {
+        let count = random.len();
+        for (name, calls) in &calls {
+            if calls.iter().any(|call| {
+                let head = call.split('.').next().unwrap();
+                RANDOM_FUNCTIONS.contains(call)
+                    || random.contains(call)
+                    || random.contains(head)
+                    || (!functions.contains_key(*call) && data.natives.contains_key(*call))
+            }) {
+                random.insert(name);
+            }
+        }
+        if random.len() == count {
+            break;
+        }
+    }
+    for (name, function) in functions.iter_mut() {
+        function.cached =
+            (function.cached || function.params.is_empty()) && !random.contains(name.as_str());
+    }
+
     Ok(Env {
         rng,
         data,
@@ -912,53 +1887,119 @@ pub fn load_env(tree: Tree, config: Config) -> Result<Env> {
     })
 }
 
+/// Runs the program's `model` function, giving the starting state of an
+/// animation.
 pub fn exec_model(env: &mut Env) -> Result<Option<Value>> {
-    let mut stack = Stack::new(env.functions.clone());
-    match reduce_call(&mut stack, &mut env.rng, &env.data, "model", Vec::new()) {
+    let mut stack = Stack::new(env.functions.clone(), &env.data);
+    match reduce_call(
+        &mut stack,
+        &mut env.rng,
+        &env.data,
+        &env.block,
+        "model",
+        Vec::new(),
+    ) {
         Ok(FunctionBlock::Start(start)) => {
             let model = start_block(&mut stack, &mut env.rng, &env.data, &env.block, start)?;
             Ok(Some(model))
         }
+        Ok(FunctionBlock::Value(model)) => Ok(Some(model)),
+        _ => Ok(None),
+    }
+}
+
+/// Evaluates the program's `animation` definition, giving the playback
+/// settings it overrides.
+pub fn exec_animation(env: &mut Env) -> Result<Option<Value>> {
+    let mut stack = Stack::new(env.functions.clone(), &env.data);
+    match reduce_call(
+        &mut stack,
+        &mut env.rng,
+        &env.data,
+        &env.block,
+        "animation",
+        Vec::new(),
+    ) {
+        Ok(FunctionBlock::Start(start)) => {
+            let value = start_block(&mut stack, &mut env.rng, &env.data, &env.block, start)?;
+            Ok(Some(value))
+        }
+        Ok(FunctionBlock::Value(value)) => Ok(Some(value)),
         _ => Ok(None),
     }
 }
 
+/// Runs the program's `update` function, giving the state after `model`.
 pub fn exec_update(env: &mut Env, model: Value) -> Result<Option<Value>> {
-    let mut stack = Stack::new(env.functions.clone());
+    let mut stack = Stack::new(env.functions.clone(), &env.data);
     let args = vec![model];
-    match reduce_call(&mut stack, &mut env.rng, &env.data, "update", args) {
+    match reduce_call(
+        &mut stack,
+        &mut env.rng,
+        &env.data,
+        &env.block,
+        "update",
+        args,
+    ) {
         Ok(FunctionBlock::Start(start)) => {
             let model = start_block(&mut stack, &mut env.rng, &env.data, &env.block, start)?;
             Ok(Some(model))
         }
+        Ok(FunctionBlock::Value(model)) => Ok(Some(model)),
         _ => Ok(None),
     }
 }
 
+/// Runs the program's `start` function, giving the shape it draws.
 pub fn exec_start(env: &mut Env) -> Result<Option<Rc<RefCell<Shape>>>> {
-    let mut stack = Stack::new(env.functions.clone());
-    match reduce_call(&mut stack, &mut env.rng, &env.data, "start", Vec::new()) {
+    let mut stack = Stack::new(env.functions.clone(), &env.data);
+    let value = match reduce_call(
+        &mut stack,
+        &mut env.rng,
+        &env.data,
+        &env.block,
+        "start",
+        Vec::new(),
+    ) {
         Ok(FunctionBlock::Start(start)) => {
-            match start_block(&mut stack, &mut env.rng, &env.data, &env.block, start)? {
-                Value::Shape(shape) => Ok(Some(shape)),
-                _ => Err(Error::InvalidStart),
-            }
+            start_block(&mut stack, &mut env.rng, &env.data, &env.block, start)?
         }
-        _ => Ok(None),
+        Ok(FunctionBlock::Value(value)) => value,
+        _ => return Ok(None),
+    };
+    match value {
+        Value::Shape(shape) => {
+            env.data.check_shape(&shape.borrow())?;
+            Ok(Some(shape))
+        }
+        _ => Err(Error::InvalidStart),
     }
 }
 
+/// Runs the program's `view` function, giving the shape drawn for `model`.
 pub fn exec_view(env: &mut Env, model: Value) -> Result<Option<Rc<RefCell<Shape>>>> {
-    let mut stack = Stack::new(env.functions.clone());
+    let mut stack = Stack::new(env.functions.clone(), &env.data);
     let args = vec![model];
-    match reduce_call(&mut stack, &mut env.rng, &env.data, "view", args) {
+    let value = match reduce_call(
+        &mut stack,
+        &mut env.rng,
+        &env.data,
+        &env.block,
+        "view",
+        args,
+    ) {
         Ok(FunctionBlock::Start(start)) => {
-            match start_block(&mut stack, &mut env.rng, &env.data, &env.block, start)? {
-                Value::Shape(shape) => Ok(Some(shape)),
-                _ => Err(Error::InvalidView),
-            }
+            start_block(&mut stack, &mut env.rng, &env.data, &env.block, start)?
         }
-        _ => Ok(None),
+        Ok(FunctionBlock::Value(value)) => value,
+        _ => return Ok(None),
+    };
+    match value {
+        Value::Shape(shape) => {
+            env.data.check_shape(&shape.borrow())?;
+            Ok(Some(shape))
+        }
+        _ => Err(Error::InvalidView),
     }
 }
 
@@ -1022,6 +2063,40 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_value_display() {
+        let mut record = BTreeMap::new();
+        record.insert("x".into(), Value::Float(1.0));
+        record.insert("y".into(), Value::Char('a'));
+        let value = Value::List(vec![
+            Value::Tuple(vec![
+                Value::Integer(-3),
+                Value::Float(0.25),
+                Value::Complex(Complex::new(1.0, 2.0)),
+                Value::Boolean(true),
+            ]),
+            Value::Tuple(vec![
+                Value::Hex([255, 0, 10]),
+                Value::String("hi".into()),
+                Value::BlendMode(BlendMode::Multiply),
+                Value::Record(record),
+            ]),
+        ]);
+        assert_eq!(
+            value.to_string(),
+            "[(-3, 0.25, 1+2i, true), (0xff000a, \"hi\", BLEND_MULTIPLY, { x = 1.0, y = 'a' })]"
+        );
+        assert_eq!(
+            Value::Function("add".into(), 1, vec![Value::Integer(-1), Value::Integer(2)])
+                .to_string(),
+            "(add 2 (-1))"
+        );
+        assert_eq!(
+            Value::Shape(Rc::new(RefCell::new(Shape::empty()))).to_string(),
+            "<shape>"
+        );
+    }
+
     #[test]
     fn test_reduce_literal() {
         assert_eq!(
@@ -1170,6 +2245,45 @@ start =
         let res = exec_start(&mut env);
         assert_eq!(res.unwrap(), Some(Rc::new(RefCell::new(Shape::circle()))));
 
+        // Matching on chars, strings, ranges, shapes and enums
+        let mut env = load_env(
+            parse(
+                "
+model = (rule 'F', rule 'q', bucket 7, kind (tx 1 CIRCLE), blending BLEND_SCREEN)
+
+rule symbol = match symbol
+    'F' -> \"FF\"
+    'a'..='z' -> \"lower\"
+    _ -> \"\"
+
+bucket n = match n
+    0..5 -> \"low\"
+    5..=10 -> \"high\"
+
+kind shape = match shape
+    SQUARE -> 0
+    CIRCLE,TRIANGLE -> 1
+
+blending mode = match mode
+    BLEND_MULTIPLY -> false
+    BLEND_SCREEN -> true
+                ",
+            )
+            .unwrap(),
+            test_config(),
+        )
+        .unwrap();
+        assert_eq!(
+            exec_model(&mut env).unwrap(),
+            Some(Value::Tuple(vec![
+                Value::String("FF".into()),
+                Value::String("lower".into()),
+                Value::String("high".into()),
+                Value::Integer(1),
+                Value::Boolean(true),
+            ]))
+        );
+
         // For loop
         let mut env = load_env(
             parse(
@@ -1248,6 +2362,874 @@ double_scale shape = ss 2 shape
         );
     }
 
+    #[test]
+    fn test_tail_calls() {
+        // Recursion in tail position runs well past the depth limit
+        let mut env = load_env(
+            parse(
+                "
+start = ss (count 5000 0) SQUARE
+count n acc =
+    if n == 0 -> acc
+    else ->
+        let step = acc + 1 ->
+            match n
+                1 -> count 0 step
+                _ -> count (n - 1) step
+                ",
+            )
+            .unwrap(),
+            test_config(),
+        )
+        .unwrap();
+        let res = exec_start(&mut env);
+        let mut env = load_env(parse("start = ss 5000 SQUARE").unwrap(), test_config()).unwrap();
+        let expected = exec_start(&mut env);
+        assert_eq!(res.unwrap(), expected.unwrap());
+
+        // Composing with the result of a tail call
+        let mut env = load_env(
+            parse(
+                "
+start = row 3000
+row n = if n == 0 -> SQUARE else -> CIRCLE : row (n - 1)
+                ",
+            )
+            .unwrap(),
+            test_config(),
+        )
+        .unwrap();
+        let res = exec_start(&mut env);
+        assert!(res.is_ok());
+
+        // Tail calls have a limit of their own, for recursion that never ends
+        let config = Config {
+            max_tail_calls: 1000,
+            ..test_config()
+        };
+        let mut env = load_env(
+            parse(
+                "
+start = ss (forever 0) SQUARE
+forever n = forever (n + 1)
+                ",
+            )
+            .unwrap(),
+            config.clone(),
+        )
+        .unwrap();
+        let res = exec_start(&mut env);
+        assert!(matches!(
+            res.unwrap_err().inner(),
+            Error::MaxTailCallsReached
+        ));
+
+        // Which only counts the tail calls made in a row
+        let mut env = load_env(
+            parse(
+                "
+start = ss (count 600 0 + count 600 0) SQUARE
+count n acc = if n == 0 -> acc else -> count (n - 1) (acc + 1)
+                ",
+            )
+            .unwrap(),
+            config,
+        )
+        .unwrap();
+        let res = exec_start(&mut env);
+        assert!(res.is_ok());
+
+        // Calls that aren't in tail position still count towards the limit
+        let mut env = load_env(
+            parse(
+                "
+start = ss (depth 5000) SQUARE
+depth n = if n == 0 -> 0 else -> 1 + depth (n - 1)
+                ",
+            )
+            .unwrap(),
+            test_config(),
+        )
+        .unwrap();
+        let res = exec_start(&mut env);
+        assert!(matches!(res.unwrap_err().inner(), Error::MaxDepthReached));
+
+        // As do functions called back by builtins
+        let mut env = load_env(
+            parse(
+                "
+start = ss (loopy 1) SQUARE
+loopy x = map loopy [x]
+                ",
+            )
+            .unwrap(),
+            Config {
+                max_depth: 20,
+                ..test_config()
+            },
+        )
+        .unwrap();
+        let res = exec_start(&mut env);
+        assert!(matches!(res.unwrap_err().inner(), Error::MaxDepthReached));
+
+        // Which can nest as deeply as the max depth allows
+        let source = "
+start = ss (nested 35) SQUARE
+nested n = if n == 0 -> 1 else -> head (map (\\x -> nested (n - 1)) [n])
+        ";
+        let mut env = load_env(parse(source).unwrap(), test_config()).unwrap();
+        assert!(exec_start(&mut env).is_ok());
+
+        let mut env = load_env(
+            parse(source).unwrap(),
+            Config {
+                max_depth: 30,
+                ..test_config()
+            },
+        )
+        .unwrap();
+        let res = exec_start(&mut env);
+        assert!(matches!(res.unwrap_err().inner(), Error::MaxDepthReached));
+    }
+
+    #[test]
+    fn test_caching() {
+        // Memoized recursion only computes each result once
+        let mut env = load_env(
+            parse(
+                "
+start = ss (fib 40) SQUARE
+fib@memo n = if n < 2 -> n else -> fib (n - 1) + fib (n - 2)
+                ",
+            )
+            .unwrap(),
+            test_config(),
+        )
+        .unwrap();
+        assert!(exec_start(&mut env).is_ok());
+        assert_eq!(env.data.cache.borrow()["fib"].len(), 41);
+        assert_eq!(
+            env.data.cache.borrow()["fib"][&vec![CacheKey::Integer(40)]],
+            Value::Integer(102334155)
+        );
+
+        // Constants are kept, unless they're random
+        let mut env = load_env(
+            parse(
+                "
+start = ss size (r angle (t offset 0 base)) : base
+size = 3
+angle = rand
+offset@2 = 1
+offset@1 = 2
+base = ss size SQUARE
+                ",
+            )
+            .unwrap(),
+            test_config(),
+        )
+        .unwrap();
+        assert!(exec_start(&mut env).is_ok());
+        {
+            let cache = env.data.cache.borrow();
+            assert!(cache.contains_key("size"));
+            assert!(cache.contains_key("base"));
+            assert!(!cache.contains_key("angle"));
+            assert!(!cache.contains_key("offset"));
+            assert!(!cache.contains_key("start"));
+        }
+
+        // Cached shapes aren't changed by what's done with them
+        let mut env = load_env(
+            parse(
+                "
+start = (ss 3 base) : base
+base = ss 2 SQUARE
+                ",
+            )
+            .unwrap(),
+            test_config(),
+        )
+        .unwrap();
+        let first = exec_start(&mut env).unwrap();
+        let second = exec_start(&mut env).unwrap();
+        assert_eq!(first, second);
+        let mut env = load_env(
+            parse("start = ss 6 SQUARE : ss 2 SQUARE").unwrap(),
+            test_config(),
+        )
+        .unwrap();
+        assert_eq!(first, exec_start(&mut env).unwrap());
+    }
+
+    #[test]
+    fn test_limits() {
+        let run = |src: &str, config: Config| {
+            let mut env = load_env(parse(src).unwrap(), config).unwrap();
+            exec_start(&mut env)
+        };
+
+        // Fuel runs out on long-running scripts
+        let src = "
+start = spin 0
+spin n = spin (n + 1)
+        ";
+        let res = run(
+            src,
+            Config {
+                fuel: Some(10000),
+                ..test_config()
+            },
+        );
+        assert!(matches!(res.unwrap_err().inner(), Error::OutOfFuel));
+
+        #[cfg(feature = "std")]
+        {
+            let res = run(
+                src,
+                Config {
+                    timeout: Some(Duration::from_millis(10)),
+                    ..test_config()
+                },
+            );
+            assert!(matches!(res.unwrap_err().inner(), Error::Timeout));
+        }
+
+        // Lists can't grow past the limit, even before they're built
+        let config = Config {
+            max_list_length: Some(1000),
+            ..test_config()
+        };
+        let res = run(
+            "start = collect (loop 1000000000 -> SQUARE)",
+            config.clone(),
+        );
+        assert!(matches!(res.unwrap_err().inner(), Error::ListTooLong(1000)));
+        let res = run(
+            "start = ss (length (range 0 2000000000)) SQUARE",
+            config.clone(),
+        );
+        assert!(matches!(res.unwrap_err().inner(), Error::ListTooLong(1000)));
+        let res = run(
+            "start = ss (length (range 0 600 ++ range 0 600)) SQUARE",
+            config.clone(),
+        );
+        assert!(matches!(res.unwrap_err().inner(), Error::ListTooLong(1000)));
+        let res = run("start = collect (loop 1000 -> SQUARE)", config);
+        assert!(res.is_ok());
+
+        // Shapes can't be made of too many others, however they're built
+        let config = Config {
+            max_shapes: Some(50),
+            ..test_config()
+        };
+        let res = run("start = collect (loop 100 -> SQUARE)", config.clone());
+        assert!(matches!(res.unwrap_err().inner(), Error::TooManyShapes(50)));
+        let res = run(
+            "
+start = double 60 SQUARE
+double n shape = if n == 0 -> shape else -> double (n - 1) (shape : shape)
+            ",
+            config.clone(),
+        );
+        assert!(matches!(res.unwrap_err().inner(), Error::TooManyShapes(50)));
+        let res = run("start = collect (loop 50 -> SQUARE)", config);
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn test_lambdas() {
+        let list = |items: &[i32]| {
+            Some(Value::List(
+                items.iter().map(|n| Value::Integer(*n)).collect(),
+            ))
+        };
+
+        // Lambda passed to a builtin
+        let mut env = load_env(
+            parse("model = map (\\x -> x * 2) [1, 2, 3]").unwrap(),
+            test_config(),
+        )
+        .unwrap();
+        assert_eq!(exec_model(&mut env).unwrap(), list(&[2, 4, 6]));
+
+        // Captured let binding
+        let mut env = load_env(
+            parse(
+                "
+model =
+    let amount = 3
+        map (\\x -> x * amount) [1, 2]
+                ",
+            )
+            .unwrap(),
+            test_config(),
+        )
+        .unwrap();
+        assert_eq!(exec_model(&mut env).unwrap(), list(&[3, 6]));
+
+        // Captured for binding
+        let mut env = load_env(
+            parse(
+                "
+model = for i in 0..3
+    apply (\\x -> x + i) 10
+
+apply fn x = fn x
+                ",
+            )
+            .unwrap(),
+            test_config(),
+        )
+        .unwrap();
+        assert_eq!(exec_model(&mut env).unwrap(), list(&[10, 11, 12]));
+
+        // Partially applied lambda passed as a parameter
+        let mut env = load_env(
+            parse(
+                "
+model =
+    let minus = \\x y -> x - y
+        apply (minus 10) 4
+
+apply fn x = fn x
+                ",
+            )
+            .unwrap(),
+            test_config(),
+        )
+        .unwrap();
+        assert_eq!(exec_model(&mut env).unwrap(), Some(Value::Integer(6)));
+
+        // Lambda returned from a function
+        let mut env = load_env(
+            parse(
+                "
+model = adder 1 2 3
+
+adder x y = \\w -> x + y + w
+                ",
+            )
+            .unwrap(),
+            test_config(),
+        )
+        .unwrap();
+        assert_eq!(exec_model(&mut env).unwrap(), Some(Value::Integer(6)));
+
+        // Named function passed to a builtin
+        let mut env = load_env(
+            parse(
+                "
+model = map (minus 10) [1, 2, 3]
+
+minus x y = x - y
+                ",
+            )
+            .unwrap(),
+            test_config(),
+        )
+        .unwrap();
+        assert_eq!(exec_model(&mut env).unwrap(), list(&[9, 8, 7]));
+    }
+
+    #[test]
+    fn test_closures() {
+        // Local definition called after its scope has ended
+        let mut env = load_env(
+            parse(
+                "
+model = apply 5 (make_adder 3)
+
+apply x fn = fn x
+
+make_adder n =
+    let adder x = x + n
+        adder
+                ",
+            )
+            .unwrap(),
+            test_config(),
+        )
+        .unwrap();
+        assert_eq!(exec_model(&mut env).unwrap(), Some(Value::Integer(8)));
+
+        // Each closure keeps the for binding it was created with
+        let mut env = load_env(
+            parse(
+                "
+model = map (\\fn -> fn 10) (for i in 0..3
+    let add_i x = x + i
+        add_i)
+                ",
+            )
+            .unwrap(),
+            test_config(),
+        )
+        .unwrap();
+        assert_eq!(
+            exec_model(&mut env).unwrap(),
+            Some(Value::List(vec![
+                Value::Integer(10),
+                Value::Integer(11),
+                Value::Integer(12)
+            ]))
+        );
+
+        // Partially applied local definition piped from elsewhere
+        let mut env = load_env(
+            parse(
+                "
+model = 4 |> (make_adder 3)
+
+make_adder n =
+    let adder x y = x + y + n
+        adder 1
+                ",
+            )
+            .unwrap(),
+            test_config(),
+        )
+        .unwrap();
+        assert_eq!(exec_model(&mut env).unwrap(), Some(Value::Integer(8)));
+
+        // Local definition sharing its name with a global
+        let mut env = load_env(
+            parse(
+                "
+model = apply 5 (make_adder 3)
+
+apply x fn = fn x
+
+adder x = 500
+
+make_adder n =
+    let adder x = x + n
+        adder
+                ",
+            )
+            .unwrap(),
+            test_config(),
+        )
+        .unwrap();
+        assert_eq!(exec_model(&mut env).unwrap(), Some(Value::Integer(8)));
+
+        // Global passed to a function with a parameter of the same name
+        let mut env = load_env(
+            parse(
+                "
+model = apply inc 5
+
+apply fn inc = fn inc
+
+inc x = x + 1
+                ",
+            )
+            .unwrap(),
+            test_config(),
+        )
+        .unwrap();
+        assert_eq!(exec_model(&mut env).unwrap(), Some(Value::Integer(6)));
+
+        // Or a let of the same name
+        let mut env = load_env(
+            parse(
+                "
+model = apply inc
+
+apply fn =
+    let inc = 5 -> fn inc
+
+inc x = x + 1
+                ",
+            )
+            .unwrap(),
+            test_config(),
+        )
+        .unwrap();
+        assert_eq!(exec_model(&mut env).unwrap(), Some(Value::Integer(6)));
+    }
+
+    #[test]
+    fn test_function_composition() {
+        // Builtins composed with user definitions
+        let mut env = load_env(
+            parse(
+                "
+model = map (neg . double . inc) [1, 2]
+
+double x = x * 2
+
+inc x = x + 1
+                ",
+            )
+            .unwrap(),
+            test_config(),
+        )
+        .unwrap();
+        assert_eq!(
+            exec_model(&mut env).unwrap(),
+            Some(Value::List(vec![Value::Integer(-4), Value::Integer(-6)]))
+        );
+
+        // Named form
+        let mut env = load_env(
+            parse(
+                "
+model = 3 |> compose_fn double (\\x -> x + 1)
+
+double x = x * 2
+                ",
+            )
+            .unwrap(),
+            test_config(),
+        )
+        .unwrap();
+        assert_eq!(exec_model(&mut env).unwrap(), Some(Value::Integer(8)));
+
+        // Partially applied transforms
+        let mut env = load_env(
+            parse("start = collect (map (ss 0.5 . tx 2) [SQUARE, CIRCLE])").unwrap(),
+            test_config(),
+        )
+        .unwrap();
+        let res = exec_start(&mut env);
+        assert!(res.is_ok());
+
+        // Functions on the right take all of their arguments before the
+        // result is passed along
+        let mut env = load_env(
+            parse(
+                "
+model = [neg_sum, foldl (neg . plus) 0 [1, 2], 3 |> compose_fn neg plus 4, compose_fn neg plus 5 6]
+
+neg_sum =
+    let negate = neg . plus -> negate 1 2
+
+plus x y = x + y
+                ",
+            )
+            .unwrap(),
+            test_config(),
+        )
+        .unwrap();
+        assert_eq!(
+            exec_model(&mut env).unwrap(),
+            Some(Value::List(vec![
+                Value::Integer(-3),
+                Value::Integer(-1),
+                Value::Integer(-7),
+                Value::Integer(-11)
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_higher_order() {
+        let mut env = load_env(
+            parse(
+                "
+model = [
+    flat_map (\\x -> [x, x * 10]) [1, 2],
+    map length (group_by (\\x -> x / 10) [1, 5, 12, 15, 3]),
+    map (\\row -> foldl (\\acc x -> acc + x * row) 0 [1, 2]) [1, 2]
+]
+                ",
+            )
+            .unwrap(),
+            test_config(),
+        )
+        .unwrap();
+        assert_eq!(
+            exec_model(&mut env).unwrap(),
+            Some(Value::List(vec![
+                Value::List(vec![
+                    Value::Integer(1),
+                    Value::Integer(10),
+                    Value::Integer(2),
+                    Value::Integer(20)
+                ]),
+                Value::List(vec![
+                    Value::Integer(2),
+                    Value::Integer(2),
+                    Value::Integer(1)
+                ]),
+                Value::List(vec![Value::Integer(3), Value::Integer(6)]),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_native_functions() {
+        let readings = Rc::new(RefCell::new(0));
+        let counter = readings.clone();
+        let config = test_config()
+            .function("double", 1, |args| match args {
+                [Value::Integer(n)] => Ok(Value::Integer(n * 2)),
+                _ => Err(Error::InvalidArgument("double".into())),
+            })
+            .function("clampadd", 3, |args| match args {
+                [Value::Integer(x), Value::Integer(y), Value::Integer(max)] => {
+                    Ok(Value::Integer((x + y).min(*max)))
+                }
+                _ => Err(Error::InvalidArgument("clampadd".into())),
+            })
+            .function("sensor", 0, move |_| {
+                *counter.borrow_mut() += 1;
+                Ok(Value::Integer(*counter.borrow()))
+            });
+
+        let program = Program::from_source(
+            "
+reading = sensor
+
+from_one = clampadd 1
+
+model = (
+    double 21,
+    map double [1, 2, 3],
+    map (clampadd 5 6) [10, 20],
+    from_one 2 3,
+    reading + reading
+)
+            ",
+            &config,
+        )
+        .unwrap();
+        assert!(program.check().unwrap().is_empty());
+        let mut env = Env::new(&program, config.clone()).unwrap();
+        assert_eq!(
+            exec_model(&mut env).unwrap(),
+            Some(Value::Tuple(vec![
+                Value::Integer(42),
+                Value::List(vec![
+                    Value::Integer(2),
+                    Value::Integer(4),
+                    Value::Integer(6)
+                ]),
+                Value::List(vec![Value::Integer(10), Value::Integer(11)]),
+                Value::Integer(3),
+                Value::Integer(3),
+            ]))
+        );
+        assert_eq!(*readings.borrow(), 2);
+
+        let program = Program::from_source("model = double \"two\"", &config).unwrap();
+        let mut env = Env::new(&program, config.clone()).unwrap();
+        assert!(matches!(
+            exec_model(&mut env).unwrap_err().inner(),
+            Error::InvalidArgument(name) if name == "double"
+        ));
+
+        // Definitions in the program take precedence over native functions.
+        let program = Program::from_source("double x = x\nmodel = double 21", &config).unwrap();
+        let mut env = Env::new(&program, config).unwrap();
+        assert_eq!(exec_model(&mut env).unwrap(), Some(Value::Integer(21)));
+    }
+
+    #[test]
+    fn test_env_api() {
+        let config = test_config();
+        let program = Program::from_source(
+            "
+start = ss 10 petal
+
+petal = SQUARE
+
+add x y = x + y
+
+grow n = ss n petal
+            ",
+            &config,
+        )
+        .unwrap();
+        let mut env = Env::new(&program, config).unwrap();
+
+        assert_eq!(
+            env.call("add", vec![Value::Integer(1), Value::Integer(2)])
+                .unwrap(),
+            Value::Integer(3)
+        );
+        assert!(matches!(
+            env.call("add", vec![Value::Integer(1)]).unwrap(),
+            Value::Function(name, 1, _) if name == "add"
+        ));
+        assert_eq!(
+            env.eval("map (add 10) [1, 2]").unwrap(),
+            Value::List(vec![Value::Integer(11), Value::Integer(12)])
+        );
+        assert_eq!(
+            env.eval("let x = 3 -> add x (add 1 1)").unwrap(),
+            Value::Integer(5)
+        );
+
+        let shape = match env.eval("grow 4").unwrap() {
+            Value::Shape(shape) => shape,
+            value => panic!("expected a shape, got {:?}", value),
+        };
+        assert!(matches!(
+            &*shape.borrow(),
+            Shape::Basic(BasicShape::Square { .. }, ..)
+        ));
+        let pixmap = crate::renderer::render(shape, 20, 10).unwrap();
+        assert_eq!((pixmap.width(), pixmap.height()), (20, 10));
+
+        let err = env.eval("add 1 missing").unwrap_err();
+        assert!(matches!(err.inner(), Error::UnknownFunction(name) if name == "missing"));
+        assert_eq!(err.definition(), None);
+        assert_eq!(err.span(), Some(Span::new(6, 13)));
+    }
+
+    #[test]
+    fn test_records() {
+        let mut env = load_env(
+            parse(
+                "
+model = map step [{ frame = 0, pos = { x = 1, y = 2 } }, { frame = 1, pos = { x = 0, y = 0 } }]
+
+step state = match state
+    { frame = 0 } -> { state | frame = state.pos.x + state.pos.y }
+    _ -> state
+                ",
+            )
+            .unwrap(),
+            test_config(),
+        )
+        .unwrap();
+        let record = |frame, x, y| {
+            let pos = [
+                ("x".into(), Value::Integer(x)),
+                ("y".into(), Value::Integer(y)),
+            ];
+            Value::Record(
+                [
+                    ("frame".into(), Value::Integer(frame)),
+                    ("pos".into(), Value::Record(pos.into())),
+                ]
+                .into(),
+            )
+        };
+        assert_eq!(
+            exec_model(&mut env).unwrap(),
+            Some(Value::List(vec![record(3, 1, 2), record(1, 0, 0)]))
+        );
+
+        // Records in a list must share the same fields
+        let mut env = load_env(
+            parse("model = [{ frame = 0 }, { time = 0 }]").unwrap(),
+            test_config(),
+        )
+        .unwrap();
+        assert!(exec_model(&mut env).is_err());
+
+        // Updates can't add fields
+        let mut env = load_env(
+            parse("model = { state | time = 0 }\n\nstate = { frame = 0 }").unwrap(),
+            test_config(),
+        )
+        .unwrap();
+        assert!(exec_model(&mut env).is_err());
+    }
+
+    #[test]
+    fn test_destructuring() {
+        let mut env = load_env(
+            parse(
+                "
+model =
+    let (x, y) = (1, 2)
+        [q, ..rest] = [3, 4, 5]
+    -> (x + y + q, for (i, j) in [(0, 1), (2, 3)] -> i * j, rest, swap (8, \"ab\"))
+
+swap p = match p
+    (0, _) -> (\"\", 0)
+    (n, [_, ..cs]) -> (cs, n + 1)
+                ",
+            )
+            .unwrap(),
+            test_config(),
+        )
+        .unwrap();
+        assert_eq!(
+            exec_model(&mut env).unwrap(),
+            Some(Value::Tuple(vec![
+                Value::Integer(6),
+                Value::List(vec![Value::Integer(0), Value::Integer(6)]),
+                Value::List(vec![Value::Integer(4), Value::Integer(5)]),
+                Value::Tuple(vec![Value::String("b".into()), Value::Integer(9)]),
+            ]))
+        );
+
+        // Patterns that can't match report a missing case
+        let mut env = load_env(
+            parse("model = let [x, y] = [1, 2, 3] -> x").unwrap(),
+            test_config(),
+        )
+        .unwrap();
+        let err = exec_model(&mut env).unwrap_err();
+        assert!(matches!(err.inner(), Error::MatchNotFound));
+    }
+
+    #[test]
+    fn test_match_patterns() {
+        let mut env = load_env(
+            parse(
+                "
+model = ([kind 'a', kind '7', kind ' '], for w in [\"hi\", \"yo\"] -> greet w, for n in [0, 1, 5, 10] -> bucket n, for x in [SQUARE, ss 2 CIRCLE, TRIANGLE, SQUARE : CIRCLE] -> name x, cap LINE_CAP_ROUND)
+
+kind c = match c
+    'a'..='z' -> 0
+    '0'..='9' -> 1
+    _ -> 2
+
+greet w = match w
+    \"hi\" -> 1
+    _ -> 0
+
+bucket n = match n
+    0..1 -> 0
+    1..=5 -> 1
+    _ -> 2
+
+name x = match x
+    SQUARE -> 0
+    CIRCLE,TRIANGLE -> 1
+    _ -> 2
+
+cap c = match c
+    LINE_CAP_BUTT -> 0
+    LINE_CAP_ROUND -> 1
+    _ -> 2
+                ",
+            )
+            .unwrap(),
+            test_config(),
+        )
+        .unwrap();
+        let list =
+            |values: &[i32]| Value::List(values.iter().map(|n| Value::Integer(*n)).collect());
+        // Composed shapes aren't any one kind, so they fall through to the
+        // wildcard.
+        assert_eq!(
+            exec_model(&mut env).unwrap(),
+            Some(Value::Tuple(vec![
+                list(&[0, 1, 2]),
+                list(&[1, 0]),
+                list(&[0, 1, 1, 2]),
+                list(&[0, 1, 1, 2]),
+                Value::Integer(1),
+            ]))
+        );
+
+        // Literals of another type can't be compared
+        let mut env = load_env(
+            parse("model = match 5\n    \"a\" -> 1\n    _ -> 0").unwrap(),
+            test_config(),
+        )
+        .unwrap();
+        let err = exec_model(&mut env).unwrap_err();
+        assert!(matches!(err.inner(), Error::InvalidMatch));
+    }
+
     #[test]
     fn test_complex_shapes() {
         // Path creation
@@ -9,18 +9,40 @@ compile_error!("Enable either `std` or `alloc`, but not both!");
 #[cfg(not(any(feature = "std", feature = "alloc")))]
 compile_error!("Either `std` or `alloc` must be enabled!");
 
+mod animation;
+mod check;
 mod colors;
 mod error;
 mod format;
 mod functions;
 mod interpreter;
 mod minify;
+mod module;
 mod out;
 mod parser;
+#[cfg(feature = "pdf")]
+mod pdf;
+mod plotter;
 mod renderer;
 mod shape;
+mod svg;
 
-pub use error::{Error, Result};
+pub use animation::AnimationFormat;
+pub use error::{Error, Result, Span};
 pub use format::format;
+pub use interpreter::{
+    exec_animation, exec_model, exec_start, exec_update, exec_view, Closure, Env, NativeFunction,
+    Value, ValueKind,
+};
 pub use minify::minify;
+pub use module::Program;
 pub use out::*;
+#[cfg(feature = "pdf")]
+pub use pdf::render_pdf;
+pub use plotter::{render_gcode, render_hpgl, PlotConfig};
+pub use renderer::render;
+pub use shape::{
+    BasicShape, Color, ColorChange, Gradient, HslaChange, ImageOp, ImagePath, PathSegment, Pattern,
+    Shape, Style,
+};
+pub use svg::render_svg;
@@ -1,12 +1,21 @@
+#[cfg(feature = "repl")]
+mod repl;
+
 #[cfg(feature = "std")]
 use {
     clap::{Parser, Subcommand},
     sha2::{Digest, Sha256},
     std::path::PathBuf,
     std::time::SystemTime,
-    xylo_lang::{format_file, generate_file, minify_file, Config, Result},
+    xylo_lang::{
+        format_file, generate_file, minify_file, AnimationFormat, Config, PlotConfig, Program,
+        Result,
+    },
 };
 
+#[cfg(feature = "pdf")]
+use xylo_lang::generate_pdf_file;
+
 #[cfg(feature = "std")]
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -46,6 +55,48 @@ enum Commands {
         frames: Option<usize>,
         #[arg(short, long)]
         seed: Option<String>,
+        #[arg(short = 'I', long = "import-path")]
+        import_paths: Vec<PathBuf>,
+        /// Paper size for plotter output: a3, a4, a5, letter or WIDTHxHEIGHT in millimetres.
+        #[arg(long, value_parser = parse_paper)]
+        paper: Option<(f32, f32)>,
+        /// Margin for plotter output in millimetres.
+        #[arg(long)]
+        margin: Option<f32>,
+        /// Animation format: gif, apng, webp or png-sequence. Picked from the destination by default.
+        #[arg(long, value_parser = parse_animation_format)]
+        format: Option<AnimationFormat>,
+        /// Frames per second of an animation.
+        #[arg(long, conflicts_with = "delay", value_parser = parse_fps)]
+        fps: Option<f32>,
+        /// Milliseconds each frame of an animation is shown for.
+        #[arg(long)]
+        delay: Option<u32>,
+        /// How many times an animation plays. Zero plays it forever.
+        #[arg(long)]
+        loops: Option<u16>,
+        /// Play an animation forwards and then backwards.
+        #[arg(long)]
+        ping_pong: bool,
+    },
+    Check {
+        source: PathBuf,
+        #[arg(short = 'I', long = "import-path")]
+        import_paths: Vec<PathBuf>,
+    },
+    #[cfg(feature = "repl")]
+    Repl {
+        source: Option<PathBuf>,
+        #[arg(long)]
+        width: Option<u32>,
+        #[arg(long)]
+        height: Option<u32>,
+        #[arg(long)]
+        max_depth: Option<usize>,
+        #[arg(short, long)]
+        seed: Option<String>,
+        #[arg(short = 'I', long = "import-path")]
+        import_paths: Vec<PathBuf>,
     },
     Minify {
         source: PathBuf,
@@ -58,17 +109,59 @@ enum Commands {
 }
 
 #[cfg(feature = "std")]
-fn main() {
-    match run_cli() {
-        Ok(()) => (),
-        Err(e) => eprintln!("{}", e.to_string()),
+impl Commands {
+    fn source(&self) -> Option<&PathBuf> {
+        match self {
+            #[cfg(feature = "window-std")]
+            Commands::Run { source, .. } => Some(source),
+            #[cfg(feature = "image-std")]
+            Commands::Generate { source, .. } => Some(source),
+            Commands::Check { source, .. } => Some(source),
+            #[cfg(feature = "repl")]
+            Commands::Repl { source, .. } => source.as_ref(),
+            Commands::Minify { source, .. } => Some(source),
+            Commands::Format { source, .. } => Some(source),
+        }
+    }
+
+    fn import_paths(&self) -> Vec<PathBuf> {
+        match self {
+            #[cfg(feature = "image-std")]
+            Commands::Generate { import_paths, .. } => import_paths.clone(),
+            Commands::Check { import_paths, .. } => import_paths.clone(),
+            #[cfg(feature = "repl")]
+            Commands::Repl { import_paths, .. } => import_paths.clone(),
+            _ => Vec::new(),
+        }
     }
 }
 
 #[cfg(feature = "std")]
-fn run_cli() -> Result<()> {
+fn main() {
     let cli = Cli::parse();
+    let source = cli.command.as_ref().and_then(|command| {
+        command
+            .source()
+            .map(|source| (source.clone(), command.import_paths()))
+    });
 
+    match run_cli(cli) {
+        Ok(()) => (),
+        Err(e) => match source {
+            Some((path, import_paths)) => {
+                let config = Config {
+                    import_paths,
+                    ..Config::default()
+                };
+                eprintln!("{}", Program::diagnostic_for_file(path, &config, &e));
+            }
+            None => eprintln!("{}", e),
+        },
+    }
+}
+
+#[cfg(feature = "std")]
+fn run_cli(cli: Cli) -> Result<()> {
     match cli.command {
         #[cfg(feature = "window-std")]
         Some(Commands::Run {
@@ -90,6 +183,14 @@ fn run_cli() -> Result<()> {
             count,
             frames,
             seed,
+            import_paths,
+            paper,
+            margin,
+            format,
+            fps,
+            delay,
+            loops,
+            ping_pong,
         }) => {
             let frames = frames.unwrap_or(1);
             let dest = match dest {
@@ -103,10 +204,13 @@ fn run_cli() -> Result<()> {
                         .split(".")
                         .next()
                         .unwrap();
-                    if frames > 1 {
-                        format!("{}.gif", file_name,).into()
-                    } else {
-                        format!("{}.png", file_name,).into()
+                    match format {
+                        Some(AnimationFormat::Apng) => format!("{}.png", file_name).into(),
+                        Some(AnimationFormat::WebP) => format!("{}.webp", file_name).into(),
+                        Some(AnimationFormat::PngSequence) => file_name.into(),
+                        Some(AnimationFormat::Gif) => format!("{}.gif", file_name).into(),
+                        None if frames > 1 => format!("{}.gif", file_name,).into(),
+                        None => format!("{}.png", file_name,).into(),
                     }
                 }
             };
@@ -116,37 +220,66 @@ fn run_cli() -> Result<()> {
             let max_depth = max_depth.unwrap_or(1500);
             let count = count.unwrap_or(1);
 
-            let seed = match seed {
-                Some(seed) => {
-                    let mut hasher = Sha256::default();
-                    hasher.update(seed.as_bytes());
-                    let mut seed = [0; 32];
-                    hasher.finalize_into((&mut seed).into());
-                    Some(seed)
-                }
-                None => None,
+            let default_plot = PlotConfig::default();
+            let plot = PlotConfig {
+                paper_size: paper.unwrap_or(default_plot.paper_size),
+                margin: margin.unwrap_or(default_plot.margin),
+                ..default_plot
+            };
+
+            let default_config = Config::default();
+            let frame_delay = match (fps, delay) {
+                (Some(fps), _) => (1000.0 / fps).round() as u32,
+                (_, Some(delay)) => delay,
+                _ => default_config.frame_delay,
             };
 
             let config = Config {
                 dimensions: (width, height),
                 max_depth,
-                seed,
+                seed: seed.map(hash_seed),
+                import_paths,
+                plot,
+                animation_format: format,
+                frame_delay,
+                loop_count: loops.unwrap_or(default_config.loop_count),
+                ping_pong,
+                ..default_config
             };
 
+            // Variants of a PDF go on the pages of one document rather than
+            // in separate files.
+            #[cfg(feature = "pdf")]
+            if count > 1
+                && dest
+                    .extension()
+                    .is_some_and(|extension| extension.eq_ignore_ascii_case("pdf"))
+            {
+                let now = SystemTime::now();
+                generate_pdf_file(&source, &dest, frames, count as usize, config)?;
+                println!(
+                    "Output to {:?} in {:?}",
+                    dest,
+                    SystemTime::now().duration_since(now).unwrap()
+                );
+                return Ok(());
+            }
+
             for i in 0..count {
                 let dest = if count == 1 {
                     dest.clone()
                 } else {
-                    dest.parent().unwrap().join(format!(
-                        "{}_{}.{}",
-                        dest.file_stem().unwrap().to_string_lossy(),
-                        i,
-                        dest.extension().unwrap().to_string_lossy()
-                    ))
+                    // PNG sequences are directories, with no extension.
+                    let mut file_name =
+                        format!("{}_{}", dest.file_stem().unwrap().to_string_lossy(), i);
+                    if let Some(extension) = dest.extension() {
+                        file_name = format!("{}.{}", file_name, extension.to_string_lossy());
+                    }
+                    dest.with_file_name(file_name)
                 };
 
                 let now = SystemTime::now();
-                generate_file(&source, &dest, frames, config)?;
+                generate_file(&source, &dest, frames, config.clone())?;
 
                 println!(
                     "Output to {:?} in {:?}",
@@ -155,6 +288,44 @@ fn run_cli() -> Result<()> {
                 );
             }
         }
+        Some(Commands::Check {
+            source,
+            import_paths,
+        }) => {
+            let config = Config {
+                import_paths,
+                ..Config::default()
+            };
+            let program = Program::from_file(&source, &config)?;
+            let errors = program.check()?;
+
+            for error in &errors {
+                eprintln!("{}\n", program.diagnostic(error));
+            }
+            if !errors.is_empty() {
+                eprintln!("Found {} problem(s) in {:?}", errors.len(), source);
+                std::process::exit(1);
+            }
+            println!("No problems found in {:?}", source);
+        }
+        #[cfg(feature = "repl")]
+        Some(Commands::Repl {
+            source,
+            width,
+            height,
+            max_depth,
+            seed,
+            import_paths,
+        }) => {
+            let config = Config {
+                dimensions: (width.unwrap_or(400), height.unwrap_or(400)),
+                max_depth: max_depth.unwrap_or(1500),
+                seed: seed.map(hash_seed),
+                import_paths,
+                ..Config::default()
+            };
+            repl::run(config, source)?;
+        }
         Some(Commands::Minify { source, dest }) => {
             let dest = dest.unwrap_or(source.clone());
             let now = SystemTime::now();
@@ -181,5 +352,47 @@ fn run_cli() -> Result<()> {
     Ok(())
 }
 
+#[cfg(feature = "std")]
+fn hash_seed(seed: String) -> [u8; 32] {
+    let mut hasher = Sha256::default();
+    hasher.update(seed.as_bytes());
+    let mut hash = [0; 32];
+    hasher.finalize_into((&mut hash).into());
+    hash
+}
+
+#[cfg(feature = "std")]
+fn parse_paper(paper: &str) -> std::result::Result<(f32, f32), String> {
+    match paper.to_lowercase().as_str() {
+        "a3" => Ok((297.0, 420.0)),
+        "a4" => Ok((210.0, 297.0)),
+        "a5" => Ok((148.0, 210.0)),
+        "letter" => Ok((215.9, 279.4)),
+        paper => paper
+            .split_once('x')
+            .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+            .ok_or_else(|| "expected a3, a4, a5, letter or WIDTHxHEIGHT".into()),
+    }
+}
+
+#[cfg(feature = "std")]
+fn parse_fps(fps: &str) -> std::result::Result<f32, String> {
+    match fps.parse::<f32>() {
+        Ok(fps) if fps > 0.0 && fps.is_finite() => Ok(fps),
+        _ => Err("expected a number of frames per second above zero".into()),
+    }
+}
+
+#[cfg(feature = "std")]
+fn parse_animation_format(format: &str) -> std::result::Result<AnimationFormat, String> {
+    match format.to_lowercase().as_str() {
+        "gif" => Ok(AnimationFormat::Gif),
+        "apng" => Ok(AnimationFormat::Apng),
+        "webp" => Ok(AnimationFormat::WebP),
+        "png-sequence" => Ok(AnimationFormat::PngSequence),
+        _ => Err("expected gif, apng, webp or png-sequence".into()),
+    }
+}
+
 #[cfg(feature = "alloc")]
 fn main() {}
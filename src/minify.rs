@@ -7,7 +7,58 @@ use alloc::{
 };
 
 use crate::error::Result;
-use crate::parser::{parse, Definition, Literal, Pattern, Token};
+use crate::parser::{parse_module, BinaryOperator, Definition, Import, Literal, Pattern, Token};
+
+fn pattern_to_string(pattern: &Pattern) -> String {
+    let join = |patterns: &[Pattern]| {
+        patterns
+            .iter()
+            .map(pattern_to_string)
+            .collect::<Vec<String>>()
+            .join(",")
+    };
+
+    match pattern {
+        Pattern::Matches(matches) => matches
+            .iter()
+            .map(Literal::to_string)
+            .collect::<Vec<String>>()
+            .join(","),
+        Pattern::Range(start, end, inclusive) => format!(
+            "{}{}{}",
+            start.to_string(),
+            if *inclusive { "..=" } else { ".." },
+            end.to_string()
+        ),
+        Pattern::Wildcard => "_".into(),
+        Pattern::Bind(name) => name.clone(),
+        Pattern::Tuple(patterns) => format!("({})", join(patterns)),
+        Pattern::List(patterns, rest) => {
+            let mut elems = join(patterns);
+            if let Some(rest) = rest {
+                if !patterns.is_empty() {
+                    elems.push(',');
+                }
+                elems.push_str("..");
+                if **rest != Pattern::Wildcard {
+                    elems.push_str(&pattern_to_string(rest));
+                }
+            }
+            format!("[{}]", elems)
+        }
+        Pattern::Record(fields) => {
+            let fields = fields
+                .iter()
+                .map(|(name, pattern)| match pattern {
+                    Pattern::Bind(bound) if bound == name => name.clone(),
+                    _ => format!("{}={}", name, pattern_to_string(pattern)),
+                })
+                .collect::<Vec<String>>()
+                .join(",");
+            format!("{{{}}}", fields)
+        }
+    }
+}
 
 fn block_to_string(block: &[Token]) -> String {
     let mut index = 0;
@@ -32,6 +83,37 @@ fn block_to_string(block: &[Token]) -> String {
                 stack[depth].push(format!("[{}]", elems.join(",")));
                 index += 1;
             }
+            Token::Tuple(size) => {
+                let mut elems = Vec::with_capacity(*size);
+                for _ in 0..*size {
+                    elems.push(stack[depth].pop().unwrap());
+                }
+                elems.reverse();
+
+                stack[depth].push(format!("({})", elems.join(",")));
+                index += 1;
+            }
+            Token::Record(names) => {
+                let mut fields = Vec::with_capacity(names.len());
+                for name in names.iter().rev() {
+                    fields.push(format!("{}={}", name, stack[depth].pop().unwrap()));
+                }
+                fields.reverse();
+
+                stack[depth].push(format!("{{{}}}", fields.join(",")));
+                index += 1;
+            }
+            Token::Update(names) => {
+                let mut fields = Vec::with_capacity(names.len());
+                for name in names.iter().rev() {
+                    fields.push(format!("{}={}", name, stack[depth].pop().unwrap()));
+                }
+                fields.reverse();
+                let record = stack[depth].pop().unwrap();
+
+                stack[depth].push(format!("{{{}|{}}}", record, fields.join(",")));
+                index += 1;
+            }
             Token::UnaryOperator(op) => {
                 let a = stack[depth].pop().unwrap();
                 stack[depth].push(format!("{}({})", op.as_str(), a));
@@ -40,7 +122,14 @@ fn block_to_string(block: &[Token]) -> String {
             Token::BinaryOperator(op) => {
                 let b = stack[depth].pop().unwrap();
                 let a = stack[depth].pop().unwrap();
-                stack[depth].push(format!("({}{}{})", a, op.as_str(), b));
+                match op {
+                    // Without spaces, composing two names reads as a
+                    // qualified name.
+                    BinaryOperator::FunctionComposition => {
+                        stack[depth].push(format!("({} {} {})", a, op.as_str(), b))
+                    }
+                    _ => stack[depth].push(format!("({}{}{})", a, op.as_str(), b)),
+                }
                 index += 1;
             }
             Token::Call(name, argc) => {
@@ -77,6 +166,26 @@ fn block_to_string(block: &[Token]) -> String {
                 lets[depth] = true;
                 index += skip + 1;
             }
+            Token::Destructure(pattern) => {
+                let value = stack[depth].pop().unwrap();
+                if !lets[depth] {
+                    depth += 1;
+                    stack.push(Vec::new());
+                    lets.push(true);
+                }
+
+                stack[depth].push(format!("{}={}", pattern_to_string(pattern), value));
+                lets[depth] = true;
+                index += 1;
+            }
+            Token::Lambda(params, skip) => {
+                stack[depth].push(format!(
+                    "(\\{}->{})",
+                    params.join(" "),
+                    block_to_string(&block[index + 1..index + skip])
+                ));
+                index += skip + 1;
+            }
             Token::Pop => {
                 if lets[depth] {
                     let defs = stack[depth][..stack[depth].len() - 1].join(";");
@@ -121,37 +230,25 @@ fn block_to_string(block: &[Token]) -> String {
                         String::new()
                     };
 
-                    match pattern {
-                        Pattern::Matches(matches) => {
-                            let matches = matches
-                                .iter()
-                                .map(Literal::to_string)
-                                .collect::<Vec<String>>()
-                                .join(",");
-                            pats.push(format!(
-                                "{}{}->{}",
-                                matches,
-                                guard,
-                                block_to_string(&block[index..index + skip])
-                            ));
-                        }
-                        Pattern::Wildcard => {
-                            pats.push(format!(
-                                "_{}->{}",
-                                guard,
-                                block_to_string(&block[index..index + skip])
-                            ));
-                        }
-                    }
+                    pats.push(format!(
+                        "{}{}->{}",
+                        pattern_to_string(pattern),
+                        guard,
+                        block_to_string(&block[index..index + skip])
+                    ));
                     index += skip;
                 }
 
                 stack[depth].push(format!("match {}->{}", a, pats.join(";")));
             }
-            Token::ForStart(var) => {
+            Token::ForStart(pattern) => {
                 let iter = stack[depth].pop().unwrap();
                 depth += 1;
-                stack.push(vec![format!("for {} in {}->", var, iter)]);
+                stack.push(vec![format!(
+                    "for {} in {}->",
+                    pattern_to_string(pattern),
+                    iter
+                )]);
                 lets.push(false);
                 index += 1;
             }
@@ -190,8 +287,33 @@ fn block_to_string(block: &[Token]) -> String {
     stack[0].pop().unwrap()
 }
 
+fn import_to_string(import: &Import) -> String {
+    match import.alias {
+        Some(alias) => format!(
+            "import {} as {}",
+            Literal::String(import.path.clone()).to_string(),
+            alias
+        ),
+        None => format!(
+            "import {}",
+            Literal::String(import.path.clone()).to_string()
+        ),
+    }
+}
+
 fn definition_to_string(definition: &Definition) -> String {
-    if definition.weight == 1.0 {
+    if definition.memo {
+        format!(
+            "{}@memo{}={}",
+            definition.name,
+            if definition.params.is_empty() {
+                String::new()
+            } else {
+                format!(" {}", definition.params.join(" "))
+            },
+            block_to_string(&definition.block)
+        )
+    } else if definition.weight == 1.0 {
         format!(
             "{}{}={}",
             definition.name,
@@ -218,10 +340,17 @@ fn definition_to_string(definition: &Definition) -> String {
 }
 
 pub fn minify(input: &str) -> Result<String> {
-    let tree = parse(input)?;
-    let output = tree
+    let module = parse_module(input)?;
+    let output = module
+        .imports
         .iter()
-        .map(|definition| definition_to_string(definition))
+        .map(|import| import_to_string(import))
+        .chain(
+            module
+                .definitions
+                .iter()
+                .map(|definition| definition_to_string(definition)),
+        )
         .collect::<Vec<String>>()
         .join("\n");
     Ok(output)
@@ -232,6 +361,7 @@ mod tests {
     use super::*;
     use crate::interpreter::{exec_start, load_env};
     use crate::out::Config;
+    use crate::parser::parse;
 
     fn can_execute(output: &str) -> bool {
         let config = Config {
@@ -381,4 +511,89 @@ squares=loop 3->(tx (rand*10) SQUARE)\
         );
         assert!(can_execute(output.as_ref().unwrap()));
     }
+
+    #[test]
+    fn test_imports() {
+        let output = minify(
+            "
+import \"lib/shapes.xylo\" as shapes
+
+start =
+    shapes.flower 3
+            ",
+        );
+        assert!(output.is_ok());
+        assert_eq!(
+            output.as_ref().unwrap(),
+            "\
+import \"lib/shapes.xylo\" as shapes
+start=(shapes.flower 3)\
+            "
+        );
+    }
+
+    #[test]
+    fn test_lambdas() {
+        let output = minify(
+            "
+start =
+    map (\\x y -> x * y) [1, 2]
+            ",
+        );
+        assert!(output.is_ok());
+        assert_eq!(output.as_ref().unwrap(), "start=(map (\\x y->(x*y)) [1,2])");
+    }
+
+    #[test]
+    fn test_function_composition() {
+        let output = minify(
+            "
+start =
+    map (ss 0.5 . r 45) shapes
+            ",
+        );
+        assert!(output.is_ok());
+        assert_eq!(
+            output.as_ref().unwrap(),
+            "start=(map ((ss 0.5) . (r 45)) shapes)"
+        );
+    }
+
+    #[test]
+    fn test_records() {
+        let output = minify(
+            "
+start =
+    match { frame = 0, pos = [1, 2] }
+        { frame = 0, pos } -> { state | frame = 1 }
+        _ -> state
+            ",
+        );
+        assert!(output.is_ok());
+        assert_eq!(
+            output.as_ref().unwrap(),
+            "start=match {frame=0,pos=[1,2]}->{frame=0,pos}->{state|frame=1};_->state"
+        );
+    }
+
+    #[test]
+    fn test_destructuring() {
+        let output = minify(
+            "
+start =
+    let (x, y) = (1, 2)
+        [h, ..t] = [3, 4]
+    -> (x, h)
+
+pick p = match p
+    (0, [_, ..]) -> y
+    { pos = (x, _) } -> x
+            ",
+        );
+        assert!(output.is_ok());
+        assert_eq!(
+            output.as_ref().unwrap(),
+            "start=let (x,y)=(1,2);[h,..t]=[3,4]->(x,h)\npick p=match p->(0,[_,..])->y;{pos=(x,_)}->x"
+        );
+    }
 }
@@ -0,0 +1,724 @@
+#[cfg(feature = "std")]
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+#[cfg(feature = "alloc")]
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+
+use crate::check::check_tree;
+use crate::error::{Error, Result, Span};
+use crate::interpreter::NativeFunction;
+use crate::out::Config;
+#[cfg(feature = "std")]
+use crate::out::Sandbox;
+use crate::parser::{parse_module, strip_comments, Pattern, Token, Tree};
+
+use hashbrown::{HashMap, HashSet};
+#[cfg(feature = "std")]
+use itertools::Itertools;
+
+#[derive(Debug)]
+struct Source {
+    name: String,
+    code: String,
+    stripped: String,
+    offset: usize,
+    #[cfg(feature = "std")]
+    path: Option<PathBuf>,
+    prefix: String,
+    // Every name visible inside the source, whether defined there or imported,
+    // mapped to the name it has in the merged program.
+    names: HashMap<String, String>,
+    // Whether the source's definitions replace earlier ones of the same name,
+    // rather than adding weighted alternatives.
+    replaces: bool,
+    // Parsed once when the source is added, with names already qualified.
+    // They point into `stripped` and `names`, which are never changed after
+    // that and are only freed along with the source, so sources can't be
+    // cloned.
+    definitions: Tree<'static>,
+}
+
+impl Source {
+    fn qualify<'a>(&'a self, name: &'a str) -> &'a str {
+        match self.names.get(name) {
+            Some(qualified) => qualified,
+            None => name,
+        }
+    }
+
+    // Qualifies the calls in a definition's block, leaving alone any names
+    // bound by parameters, `let` or patterns where they're called. Scopes are
+    // tracked the way the interpreter pushes frames: function bodies end
+    // after their length and everything else at a `Pop`.
+    fn qualify_block<'a>(&'a self, params: &[&str], block: &mut [Token<'a>]) {
+        let mut scopes: Vec<(HashSet<String>, bool, Option<usize>)> = vec![(
+            params.iter().map(|param| param.to_string()).collect(),
+            false,
+            None,
+        )];
+        let mut arms: HashMap<usize, HashSet<String>> = HashMap::new();
+
+        // Inserts into the innermost `let` frame, starting one if needed.
+        let bind_let = |scopes: &mut Vec<(HashSet<String>, bool, Option<usize>)>,
+                        names: Vec<String>| match scopes.last_mut() {
+            Some((scope, true, None)) => scope.extend(names),
+            _ => scopes.push((names.into_iter().collect(), true, None)),
+        };
+
+        for (index, token) in block.iter_mut().enumerate() {
+            if let Some(depth) = scopes.iter().position(|(_, _, end)| *end == Some(index)) {
+                scopes.truncate(depth);
+            }
+            if let Some(names) = arms.remove(&index) {
+                scopes.push((names, false, None));
+            }
+
+            match token {
+                Token::Call(name, _) => {
+                    let head = name.split('.').next().unwrap_or(name);
+                    if !scopes.iter().any(|(names, _, _)| names.contains(head)) {
+                        *name = self.qualify(name);
+                    }
+                }
+                Token::Let(name, params, skip) => {
+                    bind_let(&mut scopes, vec![name.to_string()]);
+                    let params = params.iter().map(|param| param.to_string()).collect();
+                    scopes.push((params, false, Some(index + *skip)));
+                }
+                Token::Lambda(params, skip) => {
+                    let params = params.iter().map(|param| param.to_string()).collect();
+                    scopes.push((params, false, Some(index + *skip)));
+                }
+                Token::Destructure(pattern) => {
+                    let mut names = Vec::new();
+                    pattern_names(pattern, &mut names);
+                    bind_let(&mut scopes, names);
+                }
+                Token::Match(patterns) => {
+                    let mut start = index + 1;
+                    for (pattern, _, skip) in patterns.iter() {
+                        if pattern.binds() {
+                            let mut names = Vec::new();
+                            pattern_names(pattern, &mut names);
+                            arms.insert(start, names.into_iter().collect());
+                        }
+                        start += skip;
+                    }
+                }
+                Token::ForStart(pattern) => {
+                    let mut names = Vec::new();
+                    pattern_names(pattern, &mut names);
+                    scopes.push((names.into_iter().collect(), false, None));
+                }
+                Token::Pop
+                    if scopes.len() > 1
+                        && scopes.last().is_some_and(|(_, _, end)| end.is_none()) =>
+                {
+                    scopes.pop();
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+fn pattern_names(pattern: &Pattern, names: &mut Vec<String>) {
+    match pattern {
+        Pattern::Bind(name) => names.push(name.clone()),
+        Pattern::Tuple(patterns) => {
+            for pattern in patterns {
+                pattern_names(pattern, names);
+            }
+        }
+        Pattern::List(patterns, rest) => {
+            for pattern in patterns {
+                pattern_names(pattern, names);
+            }
+            if let Some(rest) = rest {
+                pattern_names(rest, names);
+            }
+        }
+        Pattern::Record(fields) => {
+            for (_, pattern) in fields {
+                pattern_names(pattern, names);
+            }
+        }
+        Pattern::Matches(_) | Pattern::Range(..) | Pattern::Wildcard => (),
+    }
+}
+
+/// A source file along with everything it imports. Imported definitions are
+/// merged into a single tree, prefixed with `alias.` when imported with `as`.
+///
+/// Spans in the tree count from the start of the first file, as if every file
+/// was laid end to end, so `diagnostic` can tell which file an error is in.
+#[derive(Debug, Default)]
+pub struct Program {
+    sources: Vec<Source>,
+    #[cfg(feature = "std")]
+    import_paths: Vec<PathBuf>,
+    #[cfg(feature = "std")]
+    sandbox: Sandbox,
+    natives: Vec<NativeFunction>,
+}
+
+impl Program {
+    fn new(config: &Config) -> Program {
+        Program {
+            sources: Vec::new(),
+            #[cfg(feature = "std")]
+            import_paths: config.import_paths.clone(),
+            #[cfg(feature = "std")]
+            sandbox: config.sandbox.clone(),
+            natives: config.functions.clone(),
+        }
+    }
+
+    /// Loads a program from source code. Imports are resolved against the
+    /// current directory and then the import paths in `config`.
+    pub fn from_source(code: &str, config: &Config) -> Result<Program> {
+        let mut program = Program::new(config);
+        #[cfg(feature = "std")]
+        program.add("<input>".into(), code, None, String::new(), &mut Vec::new())?;
+        #[cfg(feature = "alloc")]
+        program.add("<input>".into(), code, String::new(), &mut Vec::new())?;
+        Ok(program)
+    }
+
+    /// Loads a program from a file. Imports are resolved relative to the
+    /// importing file and then the import paths in `config`.
+    #[cfg(feature = "std")]
+    pub fn from_file<P: AsRef<Path>>(path: P, config: &Config) -> Result<Program> {
+        let mut program = Program::new(config);
+        program.add_file(path.as_ref(), String::new(), &mut Vec::new())?;
+        Ok(program)
+    }
+
+    /// Adds definitions on top of the program, such as ones typed at a prompt.
+    /// They replace any earlier definitions of the same names. The program is
+    /// left as it was if the definitions can't be added.
+    pub fn define(&mut self, code: &str) -> Result<()> {
+        let index = self.sources.len();
+        #[cfg(feature = "std")]
+        let added = self.add("<input>".into(), code, None, String::new(), &mut Vec::new());
+        #[cfg(feature = "alloc")]
+        let added = self.add("<input>".into(), code, String::new(), &mut Vec::new());
+
+        match added.and_then(|_| {
+            self.sources[index].replaces = true;
+            self.tree().map(|_| ())
+        }) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                self.sources.truncate(index);
+                Err(e)
+            }
+        }
+    }
+
+    /// Renders an error raised while loading or running the program at
+    /// `path`, pointing into whichever file the error came from.
+    #[cfg(feature = "std")]
+    pub fn diagnostic_for_file<P: AsRef<Path>>(path: P, config: &Config, error: &Error) -> String {
+        // Loading stops at the same file it did when the error was raised, so
+        // everything the error could point into is available.
+        let mut program = Program::new(config);
+        let _ = program.add_file(path.as_ref(), String::new(), &mut Vec::new());
+        program.diagnostic(error)
+    }
+
+    /// Every definition of the program, merged into one tree. A name defined
+    /// in more than one file is an error, rather than the definitions becoming
+    /// weighted alternatives of each other.
+    pub fn tree(&self) -> Result<Tree<'_>> {
+        let mut tree: Tree = Vec::new();
+        let mut owners: HashMap<&str, usize> = HashMap::new();
+
+        for (index, source) in self.sources.iter().enumerate() {
+            if source.replaces {
+                tree.retain(|definition| {
+                    source
+                        .definitions
+                        .iter()
+                        .all(|other| other.name != definition.name)
+                });
+            }
+
+            for definition in &source.definitions {
+                match owners.insert(definition.name, index) {
+                    Some(owner) if owner != index && !source.replaces => {
+                        return Err(Error::ImportConflict(definition.name.into())
+                            .located(Some(definition.span), Some(definition.name)));
+                    }
+                    _ => (),
+                }
+                tree.push(definition.clone());
+            }
+        }
+
+        Ok(tree)
+    }
+
+    /// Checks the program without running it, returning every problem found.
+    pub fn check(&self) -> Result<Vec<Error>> {
+        Ok(check_tree(&self.tree()?, &self.natives))
+    }
+
+    pub fn diagnostic(&self, error: &Error) -> String {
+        let span = error.span();
+        let source = match span {
+            Some(span) => self
+                .sources
+                .iter()
+                .rev()
+                .find(|source| source.offset <= span.start),
+            None => self.sources.first(),
+        };
+
+        match source {
+            Some(source) => {
+                let span = span
+                    .map(|span| Span::new(span.start - source.offset, span.end - source.offset));
+                error.diagnostic_at(span, &source.name, &source.code)
+            }
+            None => error.to_string(),
+        }
+    }
+
+    #[cfg(feature = "std")]
+    fn add_file(&mut self, path: &Path, prefix: String, stack: &mut Vec<usize>) -> Result<usize> {
+        let canonical = fs::canonicalize(path).map_err(|e| Error::FileError(e))?;
+
+        if let Some(start) = stack
+            .iter()
+            .position(|&i| self.sources[i].path.as_ref() == Some(&canonical))
+        {
+            let cycle = stack[start..]
+                .iter()
+                .chain([&stack[start]])
+                .map(|&i| &self.sources[i].name)
+                .join(" -> ");
+            return Err(Error::ImportCycle(cycle));
+        }
+
+        // A file imported more than once under the same prefix, such as two
+        // libraries sharing a helper file, only gets merged in once.
+        if let Some(index) = self
+            .sources
+            .iter()
+            .position(|source| source.path.as_ref() == Some(&canonical) && source.prefix == prefix)
+        {
+            return Ok(index);
+        }
+
+        let code = fs::read_to_string(&canonical).map_err(|e| Error::FileError(e))?;
+        let name = path.to_string_lossy().into();
+        self.add(name, &code, Some(canonical), prefix, stack)
+    }
+
+    #[cfg(feature = "std")]
+    fn resolve(&self, path: &str, importer: usize) -> Option<PathBuf> {
+        let dir = match &self.sources[importer].path {
+            Some(path) => path.parent().map(Path::to_path_buf).unwrap_or_default(),
+            None => PathBuf::new(),
+        };
+
+        [dir]
+            .iter()
+            .chain(self.import_paths.iter())
+            .map(|dir| dir.join(path))
+            .find(|path| path.is_file())
+    }
+
+    // Imports found in the import paths are trusted, while anything else has
+    // to be allowed by the sandbox.
+    #[cfg(feature = "std")]
+    fn check_import(&self, path: &Path) -> Result<()> {
+        Sandbox::Directories(self.import_paths.clone())
+            .check(path)
+            .or_else(|_| self.sandbox.check(path))
+            .map(|_| ())
+    }
+
+    fn add(
+        &mut self,
+        name: String,
+        code: &str,
+        #[cfg(feature = "std")] path: Option<PathBuf>,
+        prefix: String,
+        stack: &mut Vec<usize>,
+    ) -> Result<usize> {
+        let offset = self
+            .sources
+            .last()
+            .map(|source| source.offset + source.stripped.len() + 1)
+            .unwrap_or(0);
+        let index = self.sources.len();
+        self.sources.push(Source {
+            name,
+            code: code.into(),
+            stripped: strip_comments(code),
+            offset,
+            #[cfg(feature = "std")]
+            path,
+            prefix: prefix.clone(),
+            names: HashMap::new(),
+            replaces: false,
+            definitions: Vec::new(),
+        });
+
+        // SAFETY: The text is kept by the source for as long as its tree, see
+        // `Source::definitions`.
+        let stripped: &'static str =
+            unsafe { &*(self.sources[index].stripped.as_str() as *const str) };
+        let module = parse_module(stripped).map_err(|e| e.offset(offset))?;
+        let mut names = module
+            .definitions
+            .iter()
+            .map(|definition| definition.name.to_string())
+            .collect::<Vec<_>>();
+
+        stack.push(index);
+        for import in &module.imports {
+            let span = Span::new(import.span.start + offset, import.span.end + offset);
+            let path = import.path.clone();
+            let alias = import.alias;
+            let child_prefix = match alias {
+                Some(alias) => format!("{}{}.", prefix, alias),
+                None => prefix.clone(),
+            };
+
+            #[cfg(feature = "std")]
+            let child = match self.resolve(&path, index) {
+                Some(resolved) => self
+                    .check_import(&resolved)
+                    .and_then(|()| self.add_file(&resolved, child_prefix, stack)),
+                None => Err(Error::ImportNotFound(path)),
+            };
+            #[cfg(feature = "alloc")]
+            let child: Result<usize> = {
+                let _ = child_prefix;
+                Err(Error::ImportNotFound(path))
+            };
+            let child = child.map_err(|e| e.located(Some(span), None))?;
+
+            names.extend(self.sources[child].names.keys().map(|name| match alias {
+                Some(alias) => format!("{}.{}", alias, name),
+                None => name.clone(),
+            }));
+        }
+        stack.pop();
+
+        self.sources[index].names = names
+            .into_iter()
+            .map(|name| {
+                let qualified = format!("{}{}", prefix, name);
+                (name, qualified)
+            })
+            .collect();
+
+        let source = &self.sources[index];
+        let offset = |span: Span| Span::new(span.start + offset, span.end + offset);
+        let mut definitions: Tree = module.definitions;
+        for definition in &mut definitions {
+            definition.span = offset(definition.span);
+            for span in &mut definition.spans {
+                *span = offset(*span);
+            }
+
+            if !source.prefix.is_empty() {
+                definition.name = source.qualify(definition.name);
+                source.qualify_block(&definition.params, &mut definition.block);
+            }
+        }
+        // SAFETY: The qualified names are kept by the source for as long as
+        // its tree, see `Source::definitions`.
+        let definitions = unsafe { core::mem::transmute::<Tree<'_>, Tree<'static>>(definitions) };
+        self.sources[index].definitions = definitions;
+
+        Ok(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::{exec_start, load_env, Value};
+    use crate::parser::{Literal, ShapeKind};
+
+    fn config() -> Config {
+        Config {
+            seed: Some([0; 32]),
+            ..Config::default()
+        }
+    }
+
+    #[cfg(feature = "std")]
+    fn write_files(dir: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(dir);
+        let _ = fs::remove_dir_all(&dir);
+        for (name, code) in files {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, code).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn test_define() {
+        let mut program = Program::from_source("start = petal\npetal = SQUARE", &config()).unwrap();
+        program.define("petal = CIRCLE").unwrap();
+        program.define("size = 2").unwrap();
+
+        let tree = program.tree().unwrap();
+        let names: Vec<_> = tree.iter().map(|definition| definition.name).collect();
+        assert_eq!(names, vec!["start", "petal", "size"]);
+        assert!(tree[1]
+            .block
+            .contains(&Token::Literal(Literal::Shape(ShapeKind::Circle))));
+
+        let err = program.define("broken = (").unwrap_err();
+        assert!(matches!(err.inner(), Error::ParseError));
+        assert!(program.diagnostic(&err).contains("<input>:1:"));
+        assert_eq!(program.tree().unwrap().len(), 3);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_import() {
+        let dir = write_files(
+            "xylo_test_import",
+            &[
+                (
+                    "main.xylo",
+                    "import \"lib/shapes.xylo\" as shapes\nimport \"lib/util.xylo\"\n\nstart =\n    shapes.flower (double 2)\n",
+                ),
+                (
+                    "lib/shapes.xylo",
+                    "flower n =\n    collect (for i in 0..n -> petal i)\n\npetal i =\n    r (i * 45) SQUARE\n",
+                ),
+                ("lib/util.xylo", "double x =\n    x * 2\n"),
+            ],
+        );
+
+        let program = Program::from_file(dir.join("main.xylo"), &config()).unwrap();
+        let tree = program.tree().unwrap();
+        let names = tree.iter().map(|d| d.name).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            vec!["start", "shapes.flower", "shapes.petal", "double"]
+        );
+        assert!(tree[1].block.contains(&Token::Call("shapes.petal", 1)));
+
+        let mut env = load_env(tree, config()).unwrap();
+        assert!(exec_start(&mut env).unwrap().is_some());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_import_shadowing() {
+        let dir = write_files(
+            "xylo_test_import_shadowing",
+            &[
+                (
+                    "main.xylo",
+                    "import \"lib.xylo\" as lib\n\nstart =\n    SQUARE\n",
+                ),
+                (
+                    "lib.xylo",
+                    "x = 100\ny = 200\n\nf x =\n    x + 1\n\ng =\n    let y = 3 -> map (\\x -> x * y) [2]\n\nh =\n    match (4, 5) ->\n        (x, _) -> x + y\n",
+                ),
+            ],
+        );
+
+        let program = Program::from_file(dir.join("main.xylo"), &config()).unwrap();
+        let tree = program.tree().unwrap();
+        let f = tree.iter().find(|d| d.name == "lib.f").unwrap();
+        assert!(f.block.contains(&Token::Call("x", 0)));
+
+        let mut env = load_env(tree, config()).unwrap();
+        assert_eq!(env.eval("lib.f 2").unwrap(), Value::Integer(3));
+        assert_eq!(
+            env.eval("lib.g").unwrap(),
+            Value::List(vec![Value::Integer(6)])
+        );
+        assert_eq!(env.eval("lib.h").unwrap(), Value::Integer(204));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_import_search_path() {
+        let dir = write_files(
+            "xylo_test_import_search_path",
+            &[
+                (
+                    "main.xylo",
+                    "import \"grid.xylo\" as grid\n\nstart =\n    grid.cell\n",
+                ),
+                ("lib/grid.xylo", "cell =\n    SQUARE\n"),
+            ],
+        );
+
+        let error = Program::from_file(dir.join("main.xylo"), &config()).unwrap_err();
+        assert!(matches!(error.inner(), Error::ImportNotFound(path) if path == "grid.xylo"));
+        assert_eq!(error.span(), Some(Span::new(0, 26)));
+
+        let config = Config {
+            import_paths: vec![dir.join("lib")],
+            ..config()
+        };
+        let program = Program::from_file(dir.join("main.xylo"), &config).unwrap();
+        assert_eq!(program.tree().unwrap()[1].name, "grid.cell");
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_import_cycle() {
+        let dir = write_files(
+            "xylo_test_import_cycle",
+            &[
+                ("a.xylo", "import \"b.xylo\"\n\nstart =\n    SQUARE\n"),
+                ("b.xylo", "import \"a.xylo\"\n\nhelper =\n    CIRCLE\n"),
+            ],
+        );
+
+        let path = dir.join("a.xylo");
+        let error = Program::from_file(&path, &config()).unwrap_err();
+        match error.inner() {
+            Error::ImportCycle(cycle) => {
+                assert!(cycle.ends_with(&format!("b.xylo -> {}", path.to_string_lossy())))
+            }
+            _ => panic!("expected an import cycle"),
+        }
+
+        // The error points at the import in `b.xylo` that closes the cycle.
+        let diagnostic = Program::diagnostic_for_file(&path, &config(), &error);
+        assert!(diagnostic.contains("b.xylo:1:1"));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_import_shared() {
+        let dir = write_files(
+            "xylo_test_import_shared",
+            &[
+                (
+                    "main.xylo",
+                    "import \"a.xylo\"\nimport \"b.xylo\"\n\nstart =\n    a : b\n",
+                ),
+                ("a.xylo", "import \"common.xylo\"\n\na =\n    common\n"),
+                ("b.xylo", "import \"common.xylo\"\n\nb =\n    common\n"),
+                ("common.xylo", "common =\n    SQUARE\n"),
+            ],
+        );
+
+        let program = Program::from_file(dir.join("main.xylo"), &config()).unwrap();
+        let tree = program.tree().unwrap();
+        assert_eq!(tree.iter().filter(|d| d.name == "common").count(), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_import_conflict() {
+        let dir = write_files(
+            "xylo_test_import_conflict",
+            &[
+                (
+                    "main.xylo",
+                    "import \"lib.xylo\"\n\nstart =\n    petal\n\npetal =\n    SQUARE\n",
+                ),
+                (
+                    "aliased.xylo",
+                    "import \"lib.xylo\" as lib\n\nstart =\n    petal : lib.petal\n\npetal =\n    SQUARE\n",
+                ),
+                ("lib.xylo", "petal =\n    CIRCLE\n"),
+            ],
+        );
+
+        let path = dir.join("main.xylo");
+        let program = Program::from_file(&path, &config()).unwrap();
+        let error = program.tree().unwrap_err();
+        assert!(matches!(error.inner(), Error::ImportConflict(name) if name == "petal"));
+        assert!(program.diagnostic(&error).contains("lib.xylo:1:1"));
+
+        let program = Program::from_file(dir.join("aliased.xylo"), &config()).unwrap();
+        let mut env = load_env(program.tree().unwrap(), config()).unwrap();
+        assert!(exec_start(&mut env).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_import_missing() {
+        let code = "import \"missing.xylo\"\n\nstart =\n    SQUARE\n";
+        let error = Program::from_source(code, &config()).unwrap_err();
+        assert!(matches!(error.inner(), Error::ImportNotFound(_)));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_sandbox() {
+        let dir = write_files(
+            "xylo_test_sandbox",
+            &[
+                (
+                    "main.xylo",
+                    "import \"util.xylo\"\nimport \"shared.xylo\"\n\nstart =\n    cell\n",
+                ),
+                ("util.xylo", "cell =\n    SQUARE\n"),
+                ("image.png", ""),
+            ],
+        );
+        let lib = write_files(
+            "xylo_test_sandbox_lib",
+            &[("shared.xylo", "size =\n    2\n")],
+        );
+        let outside = write_files("xylo_test_sandbox_outside", &[("secret.png", "")]);
+
+        // Imports from the import paths are always allowed
+        let no_files = Config {
+            import_paths: vec![lib.clone()],
+            sandbox: Sandbox::NoFiles,
+            ..config()
+        };
+        let error = Program::from_file(dir.join("main.xylo"), &no_files).unwrap_err();
+        assert!(matches!(error.inner(), Error::FileAccessDenied(_)));
+        assert_eq!(error.span(), Some(Span::new(0, 18)));
+
+        let sandboxed = Config {
+            import_paths: vec![lib],
+            sandbox: Sandbox::Directories(vec![dir.clone()]),
+            ..config()
+        };
+        assert!(Program::from_file(dir.join("main.xylo"), &sandboxed).is_ok());
+
+        // Images and fonts have to be inside the allowed directories
+        let run = |code: String, config: &Config| {
+            let program = Program::from_source(&code, config)?;
+            let mut env = load_env(program.tree()?, config.clone())?;
+            exec_start(&mut env)
+        };
+        let image = dir.join("image.png");
+        let secret = outside.join("secret.png");
+        let escape = dir.join("../xylo_test_sandbox_outside/secret.png");
+        for path in [&image, &secret, &escape] {
+            let code = format!("start =\n    import_image {:?}\n", path);
+            let res = run(code, &sandboxed);
+            assert_eq!(res.is_ok(), path == &image);
+            let code = format!("start =\n    text \"xylo\" {:?} 12\n", path);
+            let res = run(code, &sandboxed);
+            assert_eq!(res.is_ok(), path == &image);
+        }
+
+        let code = format!("start =\n    import_image {:?}\n", image);
+        let error = run(code, &no_files).unwrap_err();
+        assert!(matches!(error.inner(), Error::FileAccessDenied(_)));
+    }
+}
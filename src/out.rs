@@ -1,5 +1,10 @@
 #[cfg(feature = "std")]
-use std::{fs, path::Path, rc::Rc};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::Duration,
+};
 
 #[cfg(feature = "alloc")]
 use alloc::{format, rc::Rc, string::String, vec::Vec};
@@ -7,28 +12,73 @@ use alloc::{format, rc::Rc, string::String, vec::Vec};
 #[cfg(feature = "std")]
 use crate::{format::format, minify::minify};
 
+use crate::animation::AnimationFormat;
 use crate::error::{Error, Result};
-use crate::interpreter::{exec_model, exec_start, exec_update, exec_view, load_env, Value};
-use crate::parser::parse;
+use crate::interpreter::{
+    exec_animation, exec_model, exec_start, exec_update, exec_view, load_env, NativeFunction, Value,
+};
+use crate::module::Program;
+use crate::plotter::{render_gcode, render_hpgl, PlotConfig};
 use crate::renderer::render;
 use crate::shape::Shape;
+use crate::svg::render_svg;
+
+#[cfg(any(feature = "image-std", feature = "image-alloc"))]
+use crate::animation::AnimationWriter;
+
+#[cfg(feature = "image-std")]
+use std::io::BufWriter;
+
+#[cfg(feature = "pdf")]
+use crate::pdf::PdfWriter;
 
 use base64::prelude::*;
 use core::cell::RefCell;
-use itertools::Itertools;
 
 #[cfg(any(feature = "image-std", feature = "image-alloc"))]
-use {
-    gif::{Frame, Repeat},
-    png::ColorType,
-    tiny_skia::Pixmap,
-};
+use {png::ColorType, tiny_skia::Pixmap};
 
-#[derive(Debug, Clone, Copy)]
+/// Settings for parsing, running and rendering programs.
+///
+/// Since 0.2 this is `Clone` but not `Copy`, as it holds the application's
+/// native functions and the import search paths. Clone it to reuse it.
+#[derive(Debug, Clone)]
 pub struct Config {
     pub dimensions: (u32, u32),
     pub max_depth: usize,
+    /// How many times in a row a call in tail position can take the place of
+    /// the one making it.
+    pub max_tail_calls: usize,
     pub seed: Option<[u8; 32]>,
+    /// How many steps a script can take before it's stopped.
+    pub fuel: Option<u64>,
+    /// How long a script can run for before it's stopped.
+    #[cfg(feature = "std")]
+    pub timeout: Option<Duration>,
+    /// The most items a list can hold.
+    pub max_list_length: Option<usize>,
+    /// The most shapes a rendered shape can be made of.
+    pub max_shapes: Option<usize>,
+    /// Directories searched for imports that aren't found next to the file
+    /// importing them.
+    #[cfg(feature = "std")]
+    pub import_paths: Vec<PathBuf>,
+    /// Which files scripts can read for images, fonts and imports.
+    #[cfg(feature = "std")]
+    pub sandbox: Sandbox,
+    /// Functions provided by the application for scripts to call.
+    pub functions: Vec<NativeFunction>,
+    /// Paper and pen settings for G-code and HPGL output.
+    pub plot: PlotConfig,
+    /// What animations are written as. When unset, it's picked from the
+    /// destination's extension, falling back to GIF.
+    pub animation_format: Option<AnimationFormat>,
+    /// How long each frame of an animation is shown for, in milliseconds.
+    pub frame_delay: u32,
+    /// How many times an animation plays. Zero plays it forever.
+    pub loop_count: u16,
+    /// Plays animations forwards and then backwards, so they loop smoothly.
+    pub ping_pong: bool,
 }
 
 impl Default for Config {
@@ -36,68 +86,306 @@ impl Default for Config {
         Config {
             dimensions: (400, 400),
             max_depth: 1500,
+            max_tail_calls: 100_000,
             #[cfg(feature = "std")]
             seed: None,
             #[cfg(feature = "alloc")]
             seed: Some([0; 32]),
+            fuel: None,
+            #[cfg(feature = "std")]
+            timeout: None,
+            max_list_length: None,
+            max_shapes: None,
+            #[cfg(feature = "std")]
+            import_paths: Vec::new(),
+            #[cfg(feature = "std")]
+            sandbox: Sandbox::default(),
+            functions: Vec::new(),
+            plot: PlotConfig::default(),
+            animation_format: None,
+            // The speed browsers play GIFs without a delay at.
+            frame_delay: 100,
+            loop_count: 0,
+            ping_pong: false,
+        }
+    }
+}
+
+impl Config {
+    /// Adds a function that scripts can call by name, taking `arity`
+    /// arguments. It can be partially applied and passed to higher-order
+    /// functions like any builtin.
+    pub fn function<S, F>(mut self, name: S, arity: usize, function: F) -> Config
+    where
+        S: Into<String>,
+        F: Fn(&[Value]) -> Result<Value> + 'static,
+    {
+        self.functions
+            .push(NativeFunction::new(name, arity, function));
+        self
+    }
+}
+
+/// Limits which files a script can read, for rendering pieces from untrusted
+/// sources.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Default)]
+pub enum Sandbox {
+    /// Any file can be read.
+    #[default]
+    Off,
+    /// No files can be read.
+    NoFiles,
+    /// Only files inside these directories can be read.
+    Directories(Vec<PathBuf>),
+}
+
+#[cfg(feature = "std")]
+impl Sandbox {
+    /// Checks that a file can be read, giving the path to read it from. Paths
+    /// are resolved first, so links and `..` can't lead outside the allowed
+    /// directories.
+    pub fn check<P: AsRef<Path>>(&self, path: P) -> Result<PathBuf> {
+        let path = path.as_ref();
+        let denied = || Error::FileAccessDenied(path.to_string_lossy().into());
+        let dirs = match self {
+            Sandbox::Off => return Ok(path.into()),
+            Sandbox::NoFiles => return Err(denied()),
+            Sandbox::Directories(dirs) => dirs,
+        };
+
+        let canonical = fs::canonicalize(path).map_err(|_| denied())?;
+        if dirs
+            .iter()
+            .filter_map(|dir| fs::canonicalize(dir).ok())
+            .any(|dir| canonical.starts_with(dir))
+        {
+            Ok(canonical)
+        } else {
+            Err(denied())
         }
     }
 }
 
 #[cfg(any(feature = "image-std", feature = "image-alloc"))]
-pub fn generate_pixmap<S: AsRef<str>>(input: S, config: Config) -> Result<Pixmap> {
-    let input = input
-        .as_ref()
-        .lines()
-        .map(|line| line.split("#").nth(0).unwrap())
-        .join("\n");
-    let tree = parse(&input)?;
-    let mut env = load_env(tree.clone(), config)?;
-    let shape = exec_start(&mut env)?.unwrap_or(Rc::new(RefCell::new(Shape::empty())));
-    render(shape, config.dimensions.0, config.dimensions.1)
+fn program_shape(program: &Program, config: Config) -> Result<Rc<RefCell<Shape>>> {
+    let mut env = load_env(program.tree()?, config)?;
+    Ok(exec_start(&mut env)?.unwrap_or(Rc::new(RefCell::new(Shape::empty()))))
 }
 
 #[cfg(any(feature = "image-std", feature = "image-alloc"))]
-pub fn generate_frames<S: AsRef<str>>(
-    input: S,
-    frames: usize,
-    config: Config,
-) -> Result<Vec<Pixmap>> {
-    let input = input
-        .as_ref()
-        .lines()
-        .map(|line| line.split("#").nth(0).unwrap())
-        .join("\n");
-    let tree = parse(&input)?;
+fn render_program(program: &Program, config: Config) -> Result<Pixmap> {
+    let (width, height) = config.dimensions;
+    render(program_shape(program, config)?, width, height)
+}
 
-    let mut pixmaps = Vec::with_capacity(frames);
+#[cfg(any(feature = "image-std", feature = "image-alloc"))]
+fn render_program_svg(program: &Program, config: Config) -> Result<String> {
+    let (width, height) = config.dimensions;
+    render_svg(program_shape(program, config)?, width, height)
+}
+
+#[cfg(any(feature = "image-std", feature = "image-alloc"))]
+fn render_program_plot(program: &Program, hpgl: bool, config: Config) -> Result<String> {
+    let (width, height) = config.dimensions;
+    let plot = config.plot.clone();
+    let shape = program_shape(program, config)?;
+    if hpgl {
+        render_hpgl(shape, width, height, &plot)
+    } else {
+        render_gcode(shape, width, height, &plot)
+    }
+}
 
-    let mut env = load_env(tree.clone(), config)?;
+// Runs the program for a number of frames, handing each shape over as soon as
+// it's made.
+#[cfg(any(feature = "image-std", feature = "image-alloc"))]
+fn program_frames<F>(program: &Program, frames: usize, config: Config, mut frame: F) -> Result<()>
+where
+    F: FnMut(Rc<RefCell<Shape>>) -> Result<()>,
+{
+    let mut env = load_env(program.tree()?, config)?;
     let mut model = exec_model(&mut env)?.unwrap_or(Value::Integer(0));
+    let mut count = 0;
 
     if let Some(shape) = exec_start(&mut env)? {
-        pixmaps.push(render(shape, config.dimensions.0, config.dimensions.1)?);
+        frame(shape)?;
+        count += 1;
     }
 
-    while pixmaps.len() < frames {
+    while count < frames {
         let shape =
             exec_view(&mut env, model.clone())?.unwrap_or(Rc::new(RefCell::new(Shape::empty())));
         if let Some(new_model) = exec_update(&mut env, model.clone())? {
             model = new_model;
         }
-        pixmaps.push(render(shape, config.dimensions.0, config.dimensions.1)?);
+        frame(shape)?;
+        count += 1;
     }
 
+    Ok(())
+}
+
+// Lets the program's `animation` definition override how it's played.
+#[cfg(any(feature = "image-std", feature = "image-alloc"))]
+fn animation_config(program: &Program, mut config: Config) -> Result<Config> {
+    let mut env = load_env(program.tree()?, config.clone())?;
+    let fields = match exec_animation(&mut env)? {
+        Some(Value::Record(fields)) => fields,
+        Some(_) => return Err(Error::InvalidAnimation),
+        None => return Ok(config),
+    };
+
+    for (name, value) in fields {
+        let number = match value {
+            Value::Integer(n) => Some(n as f32),
+            Value::Float(n) => Some(n),
+            _ => None,
+        };
+        match (name.as_str(), number, value) {
+            ("fps", Some(fps), _) if fps > 0.0 => {
+                config.frame_delay = (1000.0 / fps).round() as u32;
+            }
+            ("delay", Some(delay), _) if delay >= 0.0 => config.frame_delay = delay.round() as u32,
+            ("loops", _, Value::Integer(loops)) if (0..=u16::MAX as i32).contains(&loops) => {
+                config.loop_count = loops as u16;
+            }
+            ("ping_pong", _, Value::Boolean(ping_pong)) => config.ping_pong = ping_pong,
+            _ => return Err(Error::InvalidAnimation),
+        }
+    }
+    Ok(config)
+}
+
+// How many frames an animation shows. Ping-pong plays the frames back in
+// reverse after the last, leaving out both ends so they aren't shown twice in
+// a row.
+#[cfg(any(feature = "image-std", feature = "image-alloc"))]
+fn played_frames(frames: usize, config: &Config) -> usize {
+    if config.ping_pong && frames > 2 {
+        frames * 2 - 2
+    } else {
+        frames
+    }
+}
+
+// Renders the frames of an animation in the order they're played, handing
+// each over as soon as it's drawn. Ping-pong keeps the shapes to draw again on
+// the way back, which take far less memory than their pixmaps.
+#[cfg(any(feature = "image-std", feature = "image-alloc"))]
+fn render_program_animation<F>(
+    program: &Program,
+    frames: usize,
+    config: Config,
+    mut frame: F,
+) -> Result<()>
+where
+    F: FnMut(Pixmap) -> Result<()>,
+{
+    let (width, height) = config.dimensions;
+    let ping_pong = config.ping_pong;
+    let mut shapes = Vec::new();
+
+    program_frames(program, frames, config, |shape| {
+        if ping_pong {
+            shapes.push(shape.clone());
+        }
+        frame(render(shape, width, height)?)
+    })?;
+
+    if shapes.len() > 2 {
+        for shape in shapes[1..shapes.len() - 1].iter().rev() {
+            frame(render(shape.clone(), width, height)?)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(any(feature = "image-std", feature = "image-alloc"))]
+fn render_program_frames(program: &Program, frames: usize, config: Config) -> Result<Vec<Pixmap>> {
+    let mut pixmaps = Vec::with_capacity(frames);
+    render_program_frames_with(program, frames, config, |pixmap| {
+        pixmaps.push(pixmap);
+        Ok(())
+    })?;
     Ok(pixmaps)
 }
 
+#[cfg(any(feature = "image-std", feature = "image-alloc"))]
+fn render_program_frames_with<F>(
+    program: &Program,
+    frames: usize,
+    config: Config,
+    mut frame: F,
+) -> Result<()>
+where
+    F: FnMut(Pixmap) -> Result<()>,
+{
+    let (width, height) = config.dimensions;
+    program_frames(program, frames, config, |shape| {
+        frame(render(shape, width, height)?)
+    })
+}
+
+// Each run of the program adds its frames as pages, so unseeded programs give
+// a different variant each time.
+#[cfg(feature = "pdf")]
+fn render_program_pdf(
+    program: &Program,
+    frames: usize,
+    variants: usize,
+    config: Config,
+) -> Result<Vec<u8>> {
+    let (width, height) = config.dimensions;
+    let mut writer = PdfWriter::new(width, height);
+    for _ in 0..variants {
+        if frames > 1 {
+            program_frames(program, frames, config.clone(), |shape| {
+                writer.add_page(shape)
+            })?;
+        } else {
+            writer.add_page(program_shape(program, config.clone())?)?;
+        }
+    }
+    Ok(writer.finish())
+}
+
+#[cfg(any(feature = "image-std", feature = "image-alloc"))]
+pub fn generate_pixmap<S: AsRef<str>>(input: S, config: Config) -> Result<Pixmap> {
+    let program = Program::from_source(input.as_ref(), &config)?;
+    render_program(&program, config)
+}
+
+#[cfg(any(feature = "image-std", feature = "image-alloc"))]
+pub fn generate_frames<S: AsRef<str>>(
+    input: S,
+    frames: usize,
+    config: Config,
+) -> Result<Vec<Pixmap>> {
+    let program = Program::from_source(input.as_ref(), &config)?;
+    render_program_frames(&program, frames, config)
+}
+
+/// Renders the program's frames one at a time, handing each to `frame` as
+/// soon as it's drawn rather than keeping them all in memory.
+#[cfg(any(feature = "image-std", feature = "image-alloc"))]
+pub fn generate_frames_with<S, F>(input: S, frames: usize, config: Config, frame: F) -> Result<()>
+where
+    S: AsRef<str>,
+    F: FnMut(Pixmap) -> Result<()>,
+{
+    let program = Program::from_source(input.as_ref(), &config)?;
+    render_program_frames_with(&program, frames, config, frame)
+}
+
 #[cfg(any(feature = "image-std", feature = "image-alloc"))]
 pub fn generate_png_data<S: AsRef<str>>(input: S, config: Config) -> Result<Vec<u8>> {
+    let (width, height) = config.dimensions;
     let pixmap = generate_pixmap(input, config)?;
 
     let mut buf = Vec::new();
     {
-        let mut encoder = png::Encoder::new(&mut buf, config.dimensions.0, config.dimensions.1);
+        let mut encoder = png::Encoder::new(&mut buf, width, height);
         encoder.set_color(ColorType::Rgba);
         let mut writer = encoder.write_header().map_err(|e| Error::PngError(e))?;
         writer
@@ -107,10 +395,69 @@ pub fn generate_png_data<S: AsRef<str>>(input: S, config: Config) -> Result<Vec<
     Ok(buf)
 }
 
+/// Renders the program's picture as an SVG document.
+#[cfg(any(feature = "image-std", feature = "image-alloc"))]
+pub fn generate_svg<S: AsRef<str>>(input: S, config: Config) -> Result<String> {
+    let program = Program::from_source(input.as_ref(), &config)?;
+    render_program_svg(&program, config)
+}
+
+/// Renders the program as G-code for a pen plotter, fitted to the paper in
+/// `config.plot`.
+#[cfg(any(feature = "image-std", feature = "image-alloc"))]
+pub fn generate_gcode<S: AsRef<str>>(input: S, config: Config) -> Result<String> {
+    let program = Program::from_source(input.as_ref(), &config)?;
+    render_program_plot(&program, false, config)
+}
+
+/// Renders the program as HPGL for a pen plotter, fitted to the paper in
+/// `config.plot`.
+#[cfg(any(feature = "image-std", feature = "image-alloc"))]
+pub fn generate_hpgl<S: AsRef<str>>(input: S, config: Config) -> Result<String> {
+    let program = Program::from_source(input.as_ref(), &config)?;
+    render_program_plot(&program, true, config)
+}
+
+/// Renders the program as a PDF document, with a page for each frame.
+#[cfg(feature = "pdf")]
+pub fn generate_pdf<S: AsRef<str>>(input: S, frames: usize, config: Config) -> Result<Vec<u8>> {
+    let program = Program::from_source(input.as_ref(), &config)?;
+    render_program_pdf(&program, frames, 1, config)
+}
+
+/// Renders the program as a base64 `data:` URI. Several frames make an
+/// animated PNG or WebP if `config.animation_format` asks for one, and a GIF
+/// otherwise. A PNG sequence has no single file to encode and is an error.
 #[cfg(any(feature = "image-std", feature = "image-alloc"))]
 pub fn generate_data_uri<S: AsRef<str>>(input: S, frames: u32, config: Config) -> Result<String> {
     if frames > 1 {
-        todo!();
+        let (width, height) = config.dimensions;
+        let (format, mime_type) = match config.animation_format {
+            Some(AnimationFormat::Apng) => (AnimationFormat::Apng, "image/png"),
+            Some(AnimationFormat::WebP) => (AnimationFormat::WebP, "image/webp"),
+            Some(AnimationFormat::PngSequence) => return Err(Error::UnsupportedDataUri),
+            Some(AnimationFormat::Gif) | None => (AnimationFormat::Gif, "image/gif"),
+        };
+        let program = Program::from_source(input.as_ref(), &config)?;
+        let config = animation_config(&program, config)?;
+
+        let mut data = Vec::new();
+        let mut writer = AnimationWriter::new(
+            &mut data,
+            format,
+            width,
+            height,
+            played_frames(frames as usize, &config),
+            config.frame_delay,
+            config.loop_count,
+        )?;
+        render_program_animation(&program, frames as usize, config, |pixmap| {
+            writer.add_frame(&pixmap)
+        })?;
+        writer.finish()?;
+
+        let uri = format!("data:{};base64,{}", mime_type, BASE64_STANDARD.encode(data));
+        Ok(uri)
     } else {
         let data = generate_png_data(input, config)?;
         let uri = format!("data:image/png;base64,{}", BASE64_STANDARD.encode(data));
@@ -120,10 +467,17 @@ pub fn generate_data_uri<S: AsRef<str>>(input: S, frames: u32, config: Config) -
 
 #[cfg(feature = "image-std")]
 pub fn generate_pixmap_from_file<I: AsRef<Path>>(input_path: I, config: Config) -> Result<Pixmap> {
-    let code = fs::read_to_string(input_path).map_err(|e| Error::FileError(e))?;
-    generate_pixmap(code, config)
+    let program = Program::from_file(input_path, &config)?;
+    render_program(&program, config)
 }
 
+/// Renders a program to a file. Destinations ending in `.svg` get an SVG
+/// document of the first frame and those ending in `.pdf` a page for each
+/// frame. `.gcode`, `.nc` and `.ngc` give G-code and `.hpgl` and `.plt` HPGL
+/// for a pen plotter. Otherwise one frame makes a PNG and several an
+/// animation in `config.animation_format`, or the format matching the
+/// extension: `.png` and `.apng` for APNG, `.webp` for WebP and GIF for
+/// anything else.
 #[cfg(feature = "image-std")]
 pub fn generate_file<I: AsRef<Path>, O: AsRef<Path>>(
     input_path: I,
@@ -131,20 +485,66 @@ pub fn generate_file<I: AsRef<Path>, O: AsRef<Path>>(
     frames: usize,
     config: Config,
 ) -> Result<()> {
-    if frames > 1 {
-        let width = config.dimensions.0 as u16;
-        let height = config.dimensions.1 as u16;
-        let code = fs::read_to_string(input_path).map_err(|e| Error::FileError(e))?;
-        let pixmaps = generate_frames(code, frames, config)?;
-
-        let mut buf = fs::File::create(output_path).unwrap();
-        let mut encoder = gif::Encoder::new(&mut buf, width, height, &[]).unwrap();
-        encoder.set_repeat(Repeat::Infinite).unwrap();
-
-        for mut pixmap in pixmaps {
-            let frame = Frame::from_rgba(width, height, pixmap.data_mut());
-            encoder.write_frame(&frame).unwrap();
+    let output_path = output_path.as_ref();
+    let extension = output_path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    let format = config.animation_format.or_else(|| {
+        extension
+            .as_deref()
+            .and_then(AnimationFormat::from_extension)
+    });
+
+    if extension.as_deref() == Some("svg") {
+        let program = Program::from_file(input_path, &config)?;
+        let svg = render_program_svg(&program, config)?;
+        fs::write(output_path, svg).map_err(Error::FileError)?;
+    } else if extension.as_deref() == Some("pdf") {
+        #[cfg(feature = "pdf")]
+        generate_pdf_file(input_path, output_path, frames, 1, config)?;
+        #[cfg(not(feature = "pdf"))]
+        return Err(Error::UnsupportedFormat("pdf".into(), "pdf".into()));
+    } else if let Some(kind @ ("gcode" | "nc" | "ngc" | "hpgl" | "plt")) = extension.as_deref() {
+        let program = Program::from_file(input_path, &config)?;
+        let hpgl = matches!(kind, "hpgl" | "plt");
+        let plot = render_program_plot(&program, hpgl, config)?;
+        fs::write(output_path, plot).map_err(Error::FileError)?;
+    } else if frames > 1 || !matches!(format, None | Some(AnimationFormat::Apng)) {
+        let (width, height) = config.dimensions;
+        let program = Program::from_file(input_path, &config)?;
+        let format = format.unwrap_or(AnimationFormat::Gif);
+
+        #[cfg(not(feature = "webp"))]
+        if format == AnimationFormat::WebP {
+            return Err(Error::UnsupportedFormat("webp".into(), "webp".into()));
+        }
+
+        let config = animation_config(&program, config)?;
+
+        if format == AnimationFormat::PngSequence {
+            fs::create_dir_all(output_path).map_err(Error::FileError)?;
+            let mut count = 0;
+            return render_program_animation(&program, frames, config, |pixmap| {
+                pixmap
+                    .save_png(output_path.join(format!("frame_{:04}.png", count)))
+                    .map_err(Error::PngError)?;
+                count += 1;
+                Ok(())
+            });
         }
+
+        let file = BufWriter::new(fs::File::create(output_path).map_err(Error::FileError)?);
+        let mut writer = AnimationWriter::new(
+            file,
+            format,
+            width,
+            height,
+            played_frames(frames, &config),
+            config.frame_delay,
+            config.loop_count,
+        )?;
+        render_program_animation(&program, frames, config, |pixmap| writer.add_frame(&pixmap))?;
+        writer.finish()?;
     } else {
         let pixmap = generate_pixmap_from_file(input_path, config)?;
         pixmap
@@ -154,6 +554,34 @@ pub fn generate_file<I: AsRef<Path>, O: AsRef<Path>>(
     Ok(())
 }
 
+/// Renders a program to a PDF file, with a page for each frame. The program is
+/// run once for each of the variants, whose pages follow one another.
+#[cfg(feature = "pdf")]
+pub fn generate_pdf_file<I: AsRef<Path>, O: AsRef<Path>>(
+    input_path: I,
+    output_path: O,
+    frames: usize,
+    variants: usize,
+    config: Config,
+) -> Result<()> {
+    let program = Program::from_file(input_path, &config)?;
+    let pdf = render_program_pdf(&program, frames, variants, config)?;
+    fs::write(output_path, pdf).map_err(Error::FileError)?;
+    Ok(())
+}
+
+/// Looks for mistakes that would otherwise only show up partway through
+/// rendering, such as misspelled function names or builtins given too many
+/// arguments. Every problem found is returned rather than just the first.
+pub fn check<S: AsRef<str>>(input: S, config: Config) -> Result<Vec<Error>> {
+    Program::from_source(input.as_ref(), &config)?.check()
+}
+
+#[cfg(feature = "std")]
+pub fn check_file<I: AsRef<Path>>(input_path: I, config: Config) -> Result<Vec<Error>> {
+    Program::from_file(input_path, &config)?.check()
+}
+
 #[cfg(feature = "std")]
 pub fn minify_file<I: AsRef<Path>, O: AsRef<Path>>(input_path: I, output_path: O) -> Result<()> {
     let input = fs::read_to_string(input_path).map_err(|e| Error::FileError(e))?;
@@ -192,6 +620,230 @@ mod tests {
         fs::remove_file("test_generate_file.png").unwrap();
     }
 
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_generate_svg_file() {
+        generate_file(
+            "test.xylo",
+            "test_generate_file.svg",
+            1,
+            Config {
+                seed: Some([0; 32]),
+                ..Config::default()
+            },
+        )
+        .unwrap();
+        let svg = fs::read_to_string("test_generate_file.svg").unwrap();
+        assert!(svg.starts_with("<svg"));
+        assert_eq!(svg.matches("<path").count(), 100);
+        fs::remove_file("test_generate_file.svg").unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "pdf")]
+    fn test_generate_pdf() {
+        let pdf = generate_pdf(
+            "
+model = 0
+
+update m = m + 1
+
+view m = r (m * 10) (ss 100 SQUARE)
+            ",
+            3,
+            Config::default(),
+        )
+        .unwrap();
+        let pages = pdf
+            .windows(b"/Type /Page\n".len())
+            .filter(|window| *window == b"/Type /Page\n")
+            .count();
+        assert_eq!(pages, 3);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_generate_animation_files() {
+        let config = Config {
+            seed: Some([0; 32]),
+            ..Config::default()
+        };
+
+        generate_file("test.xylo", "test_animation.png", 3, config.clone()).unwrap();
+        let apng = fs::read("test_animation.png").unwrap();
+        assert!(apng.windows(4).any(|window| window == b"acTL"));
+        assert_eq!(
+            apng.windows(4).filter(|window| *window == b"fcTL").count(),
+            3
+        );
+        fs::remove_file("test_animation.png").unwrap();
+
+        #[cfg(feature = "webp")]
+        {
+            generate_file("test.xylo", "test_animation.webp", 3, config.clone()).unwrap();
+            let webp = fs::read("test_animation.webp").unwrap();
+            assert_eq!(&webp[8..16], b"WEBPVP8X");
+            assert_eq!(
+                webp.windows(4).filter(|window| *window == b"ANMF").count(),
+                3
+            );
+            fs::remove_file("test_animation.webp").unwrap();
+        }
+
+        generate_file(
+            "test.xylo",
+            "test_animation",
+            3,
+            Config {
+                animation_format: Some(AnimationFormat::PngSequence),
+                ..config
+            },
+        )
+        .unwrap();
+        assert_eq!(fs::read_dir("test_animation").unwrap().count(), 3);
+        assert_eq!(
+            fs::read("test.png").unwrap(),
+            fs::read("test_animation/frame_0000.png").unwrap()
+        );
+        fs::remove_dir_all("test_animation").unwrap();
+    }
+
+    #[test]
+    fn test_animation_config() {
+        let config = Config::default();
+        let program = Program::from_source(
+            "animation = { fps = 25, loops = 2, ping_pong = true }",
+            &config,
+        )
+        .unwrap();
+        let config = animation_config(&program, config).unwrap();
+        assert_eq!(config.frame_delay, 40);
+        assert_eq!(config.loop_count, 2);
+        assert!(config.ping_pong);
+
+        for animation in [
+            "animation = { loops = -1 }",
+            "animation = { fps = 0 }",
+            "animation = { fps = -12.5 }",
+        ] {
+            let program = Program::from_source(animation, &config).unwrap();
+            assert!(matches!(
+                animation_config(&program, config.clone()),
+                Err(Error::InvalidAnimation)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_generate_animated_data_uri() {
+        let input = "
+model = 0
+
+update m = m + 1
+
+view m = r (m * 10) (ss 10 SQUARE)
+        ";
+        let config = Config {
+            dimensions: (40, 40),
+            ..Config::default()
+        };
+
+        let uri = generate_data_uri(input, 3, config.clone()).unwrap();
+        let gif = BASE64_STANDARD
+            .decode(uri.strip_prefix("data:image/gif;base64,").unwrap())
+            .unwrap();
+        assert!(gif.starts_with(b"GIF89a"));
+
+        let uri = generate_data_uri(
+            input,
+            3,
+            Config {
+                animation_format: Some(AnimationFormat::Apng),
+                ..config.clone()
+            },
+        )
+        .unwrap();
+        let apng = BASE64_STANDARD
+            .decode(uri.strip_prefix("data:image/png;base64,").unwrap())
+            .unwrap();
+        assert_eq!(
+            apng.windows(4).filter(|window| *window == b"fcTL").count(),
+            3
+        );
+
+        // Played back and forth, the middle frame is written a second time.
+        let uri = generate_data_uri(
+            input,
+            3,
+            Config {
+                animation_format: Some(AnimationFormat::Apng),
+                ping_pong: true,
+                ..config.clone()
+            },
+        )
+        .unwrap();
+        let apng = BASE64_STANDARD
+            .decode(uri.strip_prefix("data:image/png;base64,").unwrap())
+            .unwrap();
+        assert_eq!(
+            apng.windows(4).filter(|window| *window == b"fcTL").count(),
+            4
+        );
+
+        assert!(matches!(
+            generate_data_uri(
+                input,
+                3,
+                Config {
+                    animation_format: Some(AnimationFormat::PngSequence),
+                    ..config
+                },
+            ),
+            Err(Error::UnsupportedDataUri)
+        ));
+    }
+
+    #[test]
+    fn test_generate_frames_with() {
+        let input = "
+model = 0
+
+update m = m + 1
+
+view m = r (m * 10) (ss 10 SQUARE)
+        ";
+        let config = Config {
+            dimensions: (40, 40),
+            ..Config::default()
+        };
+
+        let mut pixmaps = Vec::new();
+        generate_frames_with(input, 4, config.clone(), |pixmap| {
+            pixmaps.push(pixmap);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(pixmaps, generate_frames(input, 4, config.clone()).unwrap());
+
+        // Played back and forth, the middle two frames are shown again in
+        // reverse.
+        let program = Program::from_source(input, &config).unwrap();
+        let mut played = Vec::new();
+        let config = Config {
+            ping_pong: true,
+            ..config
+        };
+        assert_eq!(played_frames(4, &config), 6);
+        render_program_animation(&program, 4, config, |pixmap| {
+            played.push(pixmap);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(played.len(), 6);
+        assert_eq!(played[4], pixmaps[2]);
+        assert_eq!(played[5], pixmaps[1]);
+    }
+
     #[test]
     fn test_generate_data_uri() {
         let uri = generate_data_uri(
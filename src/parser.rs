@@ -1,5 +1,6 @@
 #[cfg(feature = "alloc")]
 use alloc::{
+    boxed::Box,
     format,
     string::{String, ToString},
     vec,
@@ -14,16 +15,18 @@ use {
 };
 
 use crate::colors::color;
+use crate::error::Span;
 
 use core::str::FromStr;
+use itertools::Itertools;
 use nom::branch::alt;
 use nom::bytes::complete::{tag, take_while_m_n};
 use nom::character::complete::{
     alpha1, alphanumeric1, char, digit1, i32, line_ending, multispace0, multispace1, none_of,
     space0, space1,
 };
-use nom::combinator::{eof, map, map_res, not, opt, peek, recognize, value, verify};
-use nom::error::{Error, ErrorKind};
+use nom::combinator::{cut, eof, map, map_res, not, opt, peek, recognize, value, verify};
+use nom::error::{ErrorKind, FromExternalError, ParseError};
 use nom::multi::{many0, many1, separated_list0, separated_list1};
 use nom::sequence::{delimited, preceded, terminated};
 use nom::{Err, IResult, Parser};
@@ -57,7 +60,7 @@ pub enum Norm {
 }
 
 #[cfg(not(feature = "io"))]
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum SortMode {
     Black(()),
     Brightness(u8),
@@ -65,7 +68,7 @@ pub enum SortMode {
 }
 
 #[cfg(not(feature = "io"))]
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum SortDirection {
     Both,
     Column,
@@ -280,6 +283,7 @@ pub enum BinaryOperator {
     Prepend,
     Append,
     Composition,
+    FunctionComposition,
     Pipe,
 }
 
@@ -300,6 +304,7 @@ impl BinaryOperator {
             Self::BitLeft | Self::BitRight | Self::RangeExclusive | Self::RangeInclusive => 6,
             Self::Concatenation | Self::Prepend | Self::Append => 2,
             Self::Composition => 0,
+            Self::FunctionComposition => 10,
             Self::Pipe => 1,
         }
     }
@@ -331,6 +336,7 @@ impl BinaryOperator {
             Self::Prepend => "+>",
             Self::Append => "<+",
             Self::Composition => ":",
+            Self::FunctionComposition => ".",
             Self::Pipe => "|>",
         }
     }
@@ -339,13 +345,40 @@ impl BinaryOperator {
 #[derive(Debug, Clone, PartialEq)]
 pub enum Pattern {
     Matches(Vec<Literal>),
+    // Start and end of the range, and whether the end is included.
+    Range(Literal, Literal, bool),
     Wildcard,
+    Bind(String),
+    Tuple(Vec<Pattern>),
+    // Elements at the front of a list, and what to do with the rest if
+    // anything is allowed to follow them.
+    List(Vec<Pattern>, Option<Box<Pattern>>),
+    Record(Vec<(String, Pattern)>),
+}
+
+impl Pattern {
+    /// Whether matching brings any names into scope.
+    pub fn binds(&self) -> bool {
+        match self {
+            Pattern::Matches(_) | Pattern::Range(..) | Pattern::Wildcard => false,
+            Pattern::Bind(_) => true,
+            Pattern::Tuple(patterns) => patterns.iter().any(Pattern::binds),
+            Pattern::List(patterns, rest) => {
+                patterns.iter().any(Pattern::binds)
+                    || rest.as_ref().is_some_and(|rest| rest.binds())
+            }
+            Pattern::Record(fields) => fields.iter().any(|(_, pattern)| pattern.binds()),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token<'a> {
     Literal(Literal),
     List(usize),
+    Tuple(usize),
+    Record(Vec<&'a str>),
+    Update(Vec<&'a str>),
     UnaryOperator(UnaryOperator),
     BinaryOperator(BinaryOperator),
     Call(&'a str, usize),
@@ -353,9 +386,11 @@ pub enum Token<'a> {
     Pop,
     Return(Option<usize>),
     Let(&'a str, Vec<&'a str>, usize),
+    Destructure(Pattern),
+    Lambda(Vec<&'a str>, usize),
     If(usize),
     Match(Vec<(Pattern, bool, usize)>),
-    ForStart(&'a str),
+    ForStart(Pattern),
     ForEnd,
     LoopStart,
     LoopEnd,
@@ -365,19 +400,98 @@ pub enum Token<'a> {
 pub struct Definition<'a> {
     pub name: &'a str,
     pub weight: f32,
+    pub memo: bool,
     pub params: Vec<&'a str>,
     pub block: Block<'a>,
+    pub span: Span,
+    pub spans: Vec<Span>,
 }
 
 pub type Block<'a> = Vec<Token<'a>>;
 
 pub type Tree<'a> = Vec<Definition<'a>>;
 
-fn integer(input: &str) -> IResult<&str, Literal> {
+#[derive(Debug, Clone, PartialEq)]
+pub struct Import<'a> {
+    pub path: String,
+    pub alias: Option<&'a str>,
+    pub span: Span,
+}
+
+/// A single source file: the files it imports and the definitions it makes.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Module<'a> {
+    pub imports: Vec<Import<'a>>,
+    pub definitions: Tree<'a>,
+}
+
+type Tokens<'a> = Vec<(Token<'a>, Span)>;
+
+type PResult<'a, O> = IResult<&'a str, O, ParseFailure<'a>>;
+
+/// Parse error that keeps whichever alternative got furthest into the input,
+/// which is almost always the one closest to the actual mistake.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseFailure<'a> {
+    pub input: &'a str,
+    pub kind: ErrorKind,
+}
+
+impl<'a> ParseError<&'a str> for ParseFailure<'a> {
+    fn from_error_kind(input: &'a str, kind: ErrorKind) -> Self {
+        Self { input, kind }
+    }
+
+    fn append(_input: &'a str, _kind: ErrorKind, other: Self) -> Self {
+        other
+    }
+
+    fn or(self, other: Self) -> Self {
+        if other.input.len() <= self.input.len() {
+            other
+        } else {
+            self
+        }
+    }
+}
+
+impl<'a, E> FromExternalError<&'a str, E> for ParseFailure<'a> {
+    fn from_external_error(input: &'a str, kind: ErrorKind, _e: E) -> Self {
+        Self { input, kind }
+    }
+}
+
+// While parsing, spans count bytes from the end of the input since combinators
+// only ever see suffixes of the source. `parse` turns them into offsets from the
+// start once the definition is complete.
+fn span(from: &str, to: &str) -> Span {
+    Span::new(from.len(), to.len())
+}
+
+fn cover(tokens: &[(Token, Span)], span: Span) -> Span {
+    tokens.iter().fold(span, |a, (_, b)| {
+        Span::new(a.start.max(b.start), a.end.min(b.end))
+    })
+}
+
+fn resolve(span: Span, len: usize) -> Span {
+    Span::new(len - span.start, len - span.end)
+}
+
+/// Removes `#` comments, keeping every line in place so that offsets into the
+/// result still map to the same line and column of the original source.
+pub fn strip_comments(input: &str) -> String {
+    input
+        .lines()
+        .map(|line| line.split("#").nth(0).unwrap())
+        .join("\n")
+}
+
+fn integer(input: &str) -> PResult<'_, Literal> {
     map(i32, Literal::Integer).parse(input)
 }
 
-fn float_value(input: &str) -> IResult<&str, f32> {
+fn float_value(input: &str) -> PResult<'_, f32> {
     map_res(
         recognize((
             opt(char('-')),
@@ -409,11 +523,11 @@ fn float_value(input: &str) -> IResult<&str, f32> {
     .parse(input)
 }
 
-fn float(input: &str) -> IResult<&str, Literal> {
+fn float(input: &str) -> PResult<'_, Literal> {
     map(float_value, Literal::Float).parse(input)
 }
 
-fn complex_value(input: &str) -> IResult<&str, Complex<f32>> {
+fn complex_value(input: &str) -> PResult<'_, Complex<f32>> {
     map_res(
         alt((
             recognize((
@@ -436,11 +550,11 @@ fn complex_value(input: &str) -> IResult<&str, Complex<f32>> {
     .parse(input)
 }
 
-fn complex(input: &str) -> IResult<&str, Literal> {
+fn complex(input: &str) -> PResult<'_, Literal> {
     map(complex_value, Literal::Complex).parse(input)
 }
 
-fn boolean(input: &str) -> IResult<&str, Literal> {
+fn boolean(input: &str) -> PResult<'_, Literal> {
     alt((
         value(Literal::Boolean(true), tag("true")),
         value(Literal::Boolean(false), tag("false")),
@@ -463,15 +577,15 @@ fn is_hex_digit(c: char) -> bool {
     c.is_digit(16)
 }
 
-fn hex_primary(input: &str) -> IResult<&str, u8> {
+fn hex_primary(input: &str) -> PResult<'_, u8> {
     map_res(take_while_m_n(2, 2, is_hex_digit), from_hex).parse(input)
 }
 
-fn hex_primary_single(input: &str) -> IResult<&str, u8> {
+fn hex_primary_single(input: &str) -> PResult<'_, u8> {
     map_res(take_while_m_n(1, 1, is_hex_digit), from_hex_single).parse(input)
 }
 
-fn hex(input: &str) -> IResult<&str, Literal> {
+fn hex(input: &str) -> PResult<'_, Literal> {
     let (input, _) = tag("0x")(input)?;
     let (input, (r, g, b)) = alt((
         (hex_primary, hex_primary, hex_primary),
@@ -482,11 +596,11 @@ fn hex(input: &str) -> IResult<&str, Literal> {
     Ok((input, Literal::Hex([r, g, b])))
 }
 
-fn hex_color(input: &str) -> IResult<&str, Literal> {
+fn hex_color(input: &str) -> PResult<'_, Literal> {
     map(color, Literal::Hex).parse(input)
 }
 
-fn character(input: &str) -> IResult<&str, Literal> {
+fn character(input: &str) -> PResult<'_, Literal> {
     map(
         delimited(char('\''), none_of("'\\"), char('\'')),
         Literal::Char,
@@ -494,15 +608,19 @@ fn character(input: &str) -> IResult<&str, Literal> {
     .parse(input)
 }
 
-fn string(input: &str) -> IResult<&str, Literal> {
+fn string_value(input: &str) -> PResult<'_, String> {
     map(
         delimited(char('"'), many0(none_of("\"\\")), char('"')),
-        |chars| Literal::String(chars.into_iter().collect()),
+        |chars| chars.into_iter().collect(),
     )
     .parse(input)
 }
 
-fn shape(input: &str) -> IResult<&str, Literal> {
+fn string(input: &str) -> PResult<'_, Literal> {
+    map(string_value, Literal::String).parse(input)
+}
+
+fn shape(input: &str) -> PResult<'_, Literal> {
     map(
         alt((
             value(
@@ -540,7 +658,7 @@ fn shape(input: &str) -> IResult<&str, Literal> {
     .parse(input)
 }
 
-fn blend_mode(input: &str) -> IResult<&str, Literal> {
+fn blend_mode(input: &str) -> PResult<'_, Literal> {
     map(
         alt((
             alt((
@@ -580,7 +698,7 @@ fn blend_mode(input: &str) -> IResult<&str, Literal> {
     .parse(input)
 }
 
-fn line_cap(input: &str) -> IResult<&str, Literal> {
+fn line_cap(input: &str) -> PResult<'_, Literal> {
     map(
         alt((
             value(LineCap::Butt, tag("LINE_CAP_BUTT")),
@@ -592,7 +710,7 @@ fn line_cap(input: &str) -> IResult<&str, Literal> {
     .parse(input)
 }
 
-fn line_join(input: &str) -> IResult<&str, Literal> {
+fn line_join(input: &str) -> PResult<'_, Literal> {
     map(
         alt((
             value(LineJoin::MiterClip, tag("LINE_JOIN_MITER_CLIP")),
@@ -605,7 +723,7 @@ fn line_join(input: &str) -> IResult<&str, Literal> {
     .parse(input)
 }
 
-fn spread_mode(input: &str) -> IResult<&str, Literal> {
+fn spread_mode(input: &str) -> PResult<'_, Literal> {
     map(
         alt((
             value(SpreadMode::Pad, tag("SPREAD_MODE_PAD")),
@@ -617,7 +735,7 @@ fn spread_mode(input: &str) -> IResult<&str, Literal> {
     .parse(input)
 }
 
-fn filter_quality(input: &str) -> IResult<&str, Literal> {
+fn filter_quality(input: &str) -> PResult<'_, Literal> {
     map(
         alt((
             value(FilterQuality::Nearest, tag("QUALITY_NEAREST")),
@@ -629,7 +747,7 @@ fn filter_quality(input: &str) -> IResult<&str, Literal> {
     .parse(input)
 }
 
-fn filter_type(input: &str) -> IResult<&str, Literal> {
+fn filter_type(input: &str) -> PResult<'_, Literal> {
     map(
         alt((
             value(FilterType::Nearest, tag("FILTER_NEAREST")),
@@ -643,7 +761,7 @@ fn filter_type(input: &str) -> IResult<&str, Literal> {
     .parse(input)
 }
 
-fn threshold_type(input: &str) -> IResult<&str, Literal> {
+fn threshold_type(input: &str) -> PResult<'_, Literal> {
     map(
         alt((
             value(
@@ -663,7 +781,7 @@ fn threshold_type(input: &str) -> IResult<&str, Literal> {
     .parse(input)
 }
 
-fn norm(input: &str) -> IResult<&str, Literal> {
+fn norm(input: &str) -> PResult<'_, Literal> {
     map(
         alt((
             value(Norm::L1, tag("NORM_L1")),
@@ -676,7 +794,7 @@ fn norm(input: &str) -> IResult<&str, Literal> {
 }
 
 #[cfg(feature = "io")]
-fn sort_mode(input: &str) -> IResult<&str, Literal> {
+fn sort_mode(input: &str) -> PResult<'_, Literal> {
     map(
         alt((
             value(SortMode::Black(DEFAULT_BLACK.clone()), tag("SORT_BLACK")),
@@ -692,7 +810,7 @@ fn sort_mode(input: &str) -> IResult<&str, Literal> {
 }
 
 #[cfg(not(feature = "io"))]
-fn sort_mode(input: &str) -> IResult<&str, Literal> {
+fn sort_mode(input: &str) -> PResult<'_, Literal> {
     map(
         alt((
             value(SortMode::Black(()), tag("SORT_BLACK")),
@@ -704,7 +822,7 @@ fn sort_mode(input: &str) -> IResult<&str, Literal> {
     .parse(input)
 }
 
-fn sort_direction(input: &str) -> IResult<&str, Literal> {
+fn sort_direction(input: &str) -> PResult<'_, Literal> {
     map(
         alt((
             value(SortDirection::Both, tag("DIRECTION_BOTH")),
@@ -716,7 +834,7 @@ fn sort_direction(input: &str) -> IResult<&str, Literal> {
     .parse(input)
 }
 
-fn literal(input: &str) -> IResult<&str, Literal> {
+fn literal(input: &str) -> PResult<'_, Literal> {
     alt((
         hex,
         complex,
@@ -741,15 +859,15 @@ fn literal(input: &str) -> IResult<&str, Literal> {
     .parse(input)
 }
 
-fn end(input: &str) -> IResult<&str, &str> {
+fn end(input: &str) -> PResult<'_, &str> {
     preceded(space0, alt((tag(";"), line_ending, eof))).parse(input)
 }
 
-fn unary_operator_tag(input: &str) -> IResult<&str, &str> {
+fn unary_operator_tag(input: &str) -> PResult<'_, &str> {
     alt((tag("!"), tag("~"))).parse(input)
 }
 
-fn unary_operator(input: &str) -> IResult<&str, UnaryOperator> {
+fn unary_operator(input: &str) -> PResult<'_, UnaryOperator> {
     alt((
         value(
             UnaryOperator::Negation,
@@ -761,7 +879,7 @@ fn unary_operator(input: &str) -> IResult<&str, UnaryOperator> {
     .parse(input)
 }
 
-fn binary_operator_tag(input: &str) -> IResult<&str, &str> {
+fn binary_operator_tag(input: &str) -> PResult<'_, &str> {
     alt((
         alt((
             tag("++"),
@@ -790,12 +908,13 @@ fn binary_operator_tag(input: &str) -> IResult<&str, &str> {
         tag("|"),
         tag("..="),
         tag(".."),
+        tag("."),
         tag("^"),
     ))
     .parse(input)
 }
 
-fn binary_operator(input: &str) -> IResult<&str, BinaryOperator> {
+fn binary_operator(input: &str) -> PResult<'_, BinaryOperator> {
     alt((
         alt((
             value(BinaryOperator::Concatenation, tag("++")),
@@ -824,12 +943,13 @@ fn binary_operator(input: &str) -> IResult<&str, BinaryOperator> {
         value(BinaryOperator::BitOr, tag("|")),
         value(BinaryOperator::RangeInclusive, tag("..=")),
         value(BinaryOperator::RangeExclusive, tag("..")),
+        value(BinaryOperator::FunctionComposition, tag(".")),
         value(BinaryOperator::BitXor, tag("^")),
     ))
     .parse(input)
 }
 
-fn identifier(input: &str) -> IResult<&str, &str> {
+fn identifier(input: &str) -> PResult<'_, &str> {
     verify(
         alt((
             tag("π"),
@@ -848,12 +968,20 @@ fn identifier(input: &str) -> IResult<&str, &str> {
     .parse(input)
 }
 
-fn indentation(input: &str, indent: usize) -> IResult<&str, usize> {
+// Names brought in by an aliased import are written `alias.name`.
+fn qualified_identifier(input: &str) -> PResult<'_, &str> {
+    recognize(separated_list1(char('.'), identifier)).parse(input)
+}
+
+fn indentation(input: &str, indent: usize) -> PResult<'_, usize> {
     let (input, line_end) = opt(many1((space0, line_ending))).parse(input)?;
     let (input, spacing) = many0(alt((char(' '), char('\t')))).parse(input)?;
     if line_end.is_some() {
         if spacing.len() < indent as usize {
-            return Err(Err::Error(Error::new(input, ErrorKind::Verify)));
+            return Err(Err::Error(ParseFailure::from_error_kind(
+                input,
+                ErrorKind::Verify,
+            )));
         }
         Ok((input, spacing.len()))
     } else {
@@ -861,7 +989,8 @@ fn indentation(input: &str, indent: usize) -> IResult<&str, usize> {
     }
 }
 
-fn list(input: &str) -> IResult<&str, Block> {
+fn list(input: &str) -> PResult<'_, Tokens<'_>> {
+    let start = input;
     let (input, elems) = delimited(
         (char('['), multispace0),
         separated_list0((multispace0, char(','), multispace0), expr(0, false)),
@@ -874,15 +1003,94 @@ fn list(input: &str) -> IResult<&str, Block> {
     for elem in elems {
         block.extend(elem);
     }
-    block.push(Token::List(argc));
+    block.push((Token::List(argc), span(start, input)));
+
+    Ok((input, block))
+}
+
+fn record_field(input: &str) -> PResult<'_, (&str, Tokens<'_>)> {
+    let (input, name) = identifier(input)?;
+    let (input, _) = (multispace0, char('='), multispace0).parse(input)?;
+    let (input, expr) = expr(0, false)(input)?;
+    Ok((input, (name, expr)))
+}
+
+// A single expression in parentheses is just grouped, while several separated
+// by commas make a tuple.
+fn parenthesized(input: &str) -> PResult<'_, Tokens<'_>> {
+    let start = input;
+    let (input, mut elems) = delimited(
+        (char('('), multispace0),
+        separated_list1((multispace0, char(','), multispace0), |input| {
+            expr_recursive(input, 0, true, 0)
+        }),
+        (multispace0, char(')')),
+    )
+    .parse(input)?;
+
+    if elems.len() == 1 {
+        return Ok((input, elems.pop().unwrap()));
+    }
+
+    let size = elems.len();
+    let mut block = Vec::new();
+    for elem in elems {
+        block.extend(elem);
+    }
+    block.push((Token::Tuple(size), span(start, input)));
 
     Ok((input, block))
 }
 
-fn call(indent: usize, precedence: u8) -> impl FnMut(&str) -> IResult<&str, Block> {
+// Records are written `{ x = 1, y = 2 }`, and updated with `{ record | x = 3 }`.
+fn record(input: &str) -> PResult<'_, Tokens<'_>> {
+    let start = input;
+    let (input, _) = (char('{'), multispace0).parse(input)?;
+    let (input, base) = opt(terminated(
+        |input| {
+            let (rest, name) = qualified_identifier(input)?;
+            Ok((rest, (Token::Call(name, 0), span(input, rest))))
+        },
+        (multispace0, char('|'), multispace0),
+    ))
+    .parse(input)?;
+    let fields_start = input;
+    let (input, fields) =
+        separated_list0((multispace0, char(','), multispace0), record_field).parse(input)?;
+    let (input, _) = (multispace0, char('}')).parse(input)?;
+
+    let names: Vec<&str> = fields.iter().map(|(name, _)| *name).collect();
+    if names
+        .iter()
+        .enumerate()
+        .any(|(i, name)| names[..i].contains(name))
+    {
+        return Err(Err::Failure(ParseFailure {
+            input: fields_start,
+            kind: ErrorKind::Verify,
+        }));
+    }
+
+    let token = match base {
+        Some(_) => Token::Update(names),
+        None => Token::Record(names),
+    };
+    let mut block = Vec::new();
+    block.extend(base);
+    for (_, expr) in fields {
+        block.extend(expr);
+    }
+    block.push((token, span(start, input)));
+
+    Ok((input, block))
+}
+
+fn call(indent: usize, precedence: u8) -> impl FnMut(&str) -> PResult<'_, Tokens<'_>> {
     move |input| {
+        let start = input;
         if precedence < u8::MAX {
-            let (input, name) = identifier(input)?;
+            let (input, name) = qualified_identifier(input)?;
+            let name_span = span(start, input);
             let (input, args) =
                 many0(preceded(space1, expr_with_precedence(indent + 1, u8::MAX))).parse(input)?;
 
@@ -891,41 +1099,66 @@ fn call(indent: usize, precedence: u8) -> impl FnMut(&str) -> IResult<&str, Bloc
             for arg in args {
                 block.extend(arg);
             }
-            block.push(Token::Call(name, argc));
+            let span = cover(&block, name_span);
+            block.push((Token::Call(name, argc), span));
 
             Ok((input, block))
         } else {
-            let (input, name) = identifier(input)?;
+            let (input, name) = qualified_identifier(input)?;
 
             let mut block = Vec::with_capacity(1);
-            block.push(Token::Call(name, 0));
+            block.push((Token::Call(name, 0), span(start, input)));
 
             Ok((input, block))
         }
     }
 }
 
-fn let_definition(indent: usize) -> impl FnMut(&str) -> IResult<&str, Block> {
+fn let_definition(indent: usize) -> impl FnMut(&str) -> PResult<'_, Tokens<'_>> {
     move |input| {
+        let start = input;
         let (input, name) = identifier(input)?;
         let (input, params) = many0(preceded(multispace1, identifier)).parse(input)?;
+        let head = span(start, input);
         let (input, _) = preceded(multispace0, char('=')).parse(input)?;
         let (input, expr) = expr(indent + 1, false)(input)?;
 
+        let span = cover(&expr, head);
         let mut block = Vec::with_capacity(expr.len() + 2);
-        block.push(Token::Let(name, params, expr.len() + 1));
+        block.push((Token::Let(name, params, expr.len() + 1), head));
         block.extend(expr);
-        block.push(Token::Return(None));
+        block.push((Token::Return(None), span));
 
         Ok((input, block))
     }
 }
 
-fn let_statement(indent: usize) -> impl FnMut(&str) -> IResult<&str, Block> {
+// Unlike named definitions, destructuring evaluates its value straight away.
+fn let_destructure(indent: usize) -> impl FnMut(&str) -> PResult<'_, Tokens<'_>> {
     move |input| {
+        let start = input;
+        let (input, pattern) = alt((tuple_pattern, list_pattern, record_pattern)).parse(input)?;
+        let head = span(start, input);
+        let (input, _) = preceded(multispace0, char('=')).parse(input)?;
+        let (input, expr) = expr(indent + 1, false)(input)?;
+
+        let span = cover(&expr, head);
+        let mut block = expr;
+        block.push((Token::Destructure(pattern), span));
+
+        Ok((input, block))
+    }
+}
+
+fn let_statement(indent: usize) -> impl FnMut(&str) -> PResult<'_, Tokens<'_>> {
+    move |input| {
+        let start = input;
         let (input, _) = (tag("let"), space1).parse(input)?;
-        let (input, definitions) =
-            separated_list1((end, multispace0), let_definition(indent + 1)).parse(input)?;
+        let (input, definitions) = separated_list1(
+            (end, multispace0),
+            alt((let_definition(indent + 1), let_destructure(indent + 1))),
+        )
+        .parse(input)?;
         let (input, _) = alt((
             (multispace0, tag("->")),
             (space0, tag(";")),
@@ -939,14 +1172,35 @@ fn let_statement(indent: usize) -> impl FnMut(&str) -> IResult<&str, Block> {
             block.extend(expr);
         }
         block.extend(expr);
-        block.push(Token::Pop);
+        let span = cover(&block, span(start, start));
+        block.push((Token::Pop, span));
 
         Ok((input, block))
     }
 }
 
-fn if_statement(indent: usize) -> impl FnMut(&str) -> IResult<&str, Block> {
+fn lambda(indent: usize) -> impl FnMut(&str) -> PResult<'_, Tokens<'_>> {
     move |input| {
+        let start = input;
+        let (input, _) = char('\\')(input)?;
+        let (input, params) = many1(preceded(space0, identifier)).parse(input)?;
+        let head = span(start, input);
+        let (input, _) = (multispace0, tag("->")).parse(input)?;
+        let (input, expr) = expr(indent + 1, true)(input)?;
+
+        let span = cover(&expr, head);
+        let mut block = Vec::with_capacity(expr.len() + 2);
+        block.push((Token::Lambda(params, expr.len() + 1), span));
+        block.extend(expr);
+        block.push((Token::Return(None), span));
+
+        Ok((input, block))
+    }
+}
+
+fn if_statement(indent: usize) -> impl FnMut(&str) -> PResult<'_, Tokens<'_>> {
+    move |input| {
+        let start = input;
         let (input, _) = tag("if")(input)?;
         let (input, condition) = preceded(space1, expr(indent + 1, true)).parse(input)?;
         let (input, _) =
@@ -962,24 +1216,127 @@ fn if_statement(indent: usize) -> impl FnMut(&str) -> IResult<&str, Block> {
             expr(indent + 1, true)(input)?
         };
 
+        let condition_span = cover(&condition, span(start, start));
+        let span = cover(&else_branch, condition_span);
+
         let mut block =
             Vec::with_capacity(condition.len() + then_branch.len() + else_branch.len() + 2);
         block.extend(condition);
-        block.push(Token::If(then_branch.len() + 1));
+        block.push((Token::If(then_branch.len() + 1), condition_span));
         block.extend(then_branch);
-        block.push(Token::Jump(else_branch.len()));
+        block.push((Token::Jump(else_branch.len()), span));
         block.extend(else_branch);
 
         Ok((input, block))
     }
 }
 
-fn pattern(indent: usize) -> impl FnMut(&str) -> IResult<&str, Pattern> {
+fn wildcard(input: &str) -> PResult<'_, Pattern> {
+    value(
+        Pattern::Wildcard,
+        (tag("_"), not(alt((alphanumeric1, tag("_"))))),
+    )
+    .parse(input)
+}
+
+// Ranges are written like their expressions, `0..10` or `'a'..='z'`.
+fn range_pattern(input: &str) -> PResult<'_, Pattern> {
+    map(
+        (
+            literal,
+            delimited(
+                space0,
+                alt((value(true, tag("..=")), value(false, tag("..")))),
+                space0,
+            ),
+            literal,
+        ),
+        |(start, inclusive, end)| Pattern::Range(start, end, inclusive),
+    )
+    .parse(input)
+}
+
+fn tuple_pattern(input: &str) -> PResult<'_, Pattern> {
+    map(
+        verify(
+            delimited(
+                (char('('), multispace0),
+                separated_list1((multispace0, char(','), multispace0), pattern_value),
+                (multispace0, char(')')),
+            ),
+            |patterns: &Vec<Pattern>| patterns.len() > 1,
+        ),
+        Pattern::Tuple,
+    )
+    .parse(input)
+}
+
+// Lists are matched with `[a, b]`, or `[head, ..tail]` to allow more elements.
+fn list_pattern(input: &str) -> PResult<'_, Pattern> {
+    let (input, _) = (char('['), multispace0).parse(input)?;
+    let (input, patterns) =
+        separated_list0((multispace0, char(','), multispace0), pattern_value).parse(input)?;
+    let (input, rest) = opt(preceded(
+        (multispace0, opt((char(','), multispace0)), tag("..")),
+        map(
+            opt(alt((
+                wildcard,
+                map(identifier, |name| Pattern::Bind(name.into())),
+            ))),
+            |rest| Box::new(rest.unwrap_or(Pattern::Wildcard)),
+        ),
+    ))
+    .parse(input)?;
+    let (input, _) = (multispace0, char(']')).parse(input)?;
+    Ok((input, Pattern::List(patterns, rest)))
+}
+
+// Fields left without a pattern are bound to a name of their own.
+fn record_pattern(input: &str) -> PResult<'_, Pattern> {
+    let field = (
+        identifier,
+        opt(preceded(
+            (multispace0, char('='), multispace0),
+            pattern_value,
+        )),
+    );
+    let (input, fields) = delimited(
+        (char('{'), multispace0),
+        separated_list0((multispace0, char(','), multispace0), field),
+        (multispace0, char('}')),
+    )
+    .parse(input)?;
+
+    let fields = fields
+        .into_iter()
+        .map(|(name, pattern)| {
+            let pattern = pattern.unwrap_or_else(|| Pattern::Bind(name.into()));
+            (name.into(), pattern)
+        })
+        .collect();
+    Ok((input, Pattern::Record(fields)))
+}
+
+fn pattern_value(input: &str) -> PResult<'_, Pattern> {
+    alt((
+        range_pattern,
+        map(literal, |literal| Pattern::Matches(vec![literal])),
+        wildcard,
+        tuple_pattern,
+        list_pattern,
+        record_pattern,
+        map(identifier, |name| Pattern::Bind(name.into())),
+    ))
+    .parse(input)
+}
+
+fn pattern(indent: usize) -> impl FnMut(&str) -> PResult<'_, Pattern> {
     move |input| {
         let (input, _) = indentation(input, indent)?;
         alt((
+            range_pattern,
             map(separated_list1(tag(","), literal), Pattern::Matches),
-            value(Pattern::Wildcard, tag("_")),
+            pattern_value,
         ))
         .parse(input)
     }
@@ -988,11 +1345,11 @@ fn pattern(indent: usize) -> impl FnMut(&str) -> IResult<&str, Pattern> {
 #[derive(Debug)]
 struct PatternBlock<'a> {
     pattern: Pattern,
-    expr: Block<'a>,
-    guard: Option<Block<'a>>,
+    expr: Tokens<'a>,
+    guard: Option<Tokens<'a>>,
 }
 
-fn pattern_block(indent: usize) -> impl FnMut(&str) -> IResult<&str, PatternBlock> {
+fn pattern_block(indent: usize) -> impl FnMut(&str) -> PResult<'_, PatternBlock<'_>> {
     move |input| {
         let (input, pattern) = pattern(indent)(input)?;
 
@@ -1017,18 +1374,22 @@ fn pattern_block(indent: usize) -> impl FnMut(&str) -> IResult<&str, PatternBloc
     }
 }
 
-fn match_statement(indent: usize) -> impl FnMut(&str) -> IResult<&str, Block> {
+fn match_statement(indent: usize) -> impl FnMut(&str) -> PResult<'_, Tokens<'_>> {
     move |input| {
+        let start = input;
         let (input, _) = tag("match")(input)?;
         let (input, condition) = preceded(space1, expr(indent + 1, true)).parse(input)?;
         let (input, _) =
             alt(((multispace0, tag("->")), (space0, peek(line_ending)))).parse(input)?;
         let (input, pattern_blocks) = many1(pattern_block(indent + 1)).parse(input)?;
 
-        let total: usize = pattern_blocks
-            .iter()
-            .map(|pattern_block| pattern_block.expr.len() + 1)
-            .sum();
+        let condition_span = cover(&condition, span(start, start));
+
+        // Arms that bind names run in a frame of their own, popped at the end.
+        let arm_len = |pattern_block: &PatternBlock| {
+            pattern_block.expr.len() + 1 + pattern_block.pattern.binds() as usize
+        };
+        let total: usize = pattern_blocks.iter().map(arm_len).sum();
         let mut skip = 0;
 
         let mut patterns = Vec::with_capacity(pattern_blocks.len());
@@ -1036,14 +1397,16 @@ fn match_statement(indent: usize) -> impl FnMut(&str) -> IResult<&str, Block> {
         let mut flattened_blocks = Vec::new();
 
         for pattern_block in pattern_blocks {
-            skip += pattern_block.expr.len() + 1;
-            patterns.push((
-                pattern_block.pattern,
-                pattern_block.guard.is_some(),
-                pattern_block.expr.len() + 1,
-            ));
+            let len = arm_len(&pattern_block);
+            skip += len;
+            let binds = pattern_block.pattern.binds();
+            patterns.push((pattern_block.pattern, pattern_block.guard.is_some(), len));
+            let span = cover(&pattern_block.expr, condition_span);
             flattened_blocks.extend(pattern_block.expr);
-            flattened_blocks.push(Token::Jump(total - skip));
+            if binds {
+                flattened_blocks.push((Token::Pop, span));
+            }
+            flattened_blocks.push((Token::Jump(total - skip), span));
 
             if let Some(mut guard) = pattern_block.guard {
                 guard.reverse();
@@ -1057,17 +1420,18 @@ fn match_statement(indent: usize) -> impl FnMut(&str) -> IResult<&str, Block> {
             Vec::with_capacity(condition.len() + guard_blocks.len() + flattened_blocks.len() + 1);
         block.extend(guard_blocks);
         block.extend(condition);
-        block.push(Token::Match(patterns));
+        block.push((Token::Match(patterns), condition_span));
         block.extend(flattened_blocks);
 
         Ok((input, block))
     }
 }
 
-fn for_statement(indent: usize) -> impl FnMut(&str) -> IResult<&str, Block> {
+fn for_statement(indent: usize) -> impl FnMut(&str) -> PResult<'_, Tokens<'_>> {
     move |input| {
+        let start = input;
         let (input, _) = tag("for")(input)?;
-        let (input, var) = preceded(space1, identifier).parse(input)?;
+        let (input, pattern) = preceded(space1, pattern_value).parse(input)?;
         let (input, _) = (multispace1, tag("in")).parse(input)?;
         let (input, iter) = delimited(
             space1,
@@ -1077,19 +1441,23 @@ fn for_statement(indent: usize) -> impl FnMut(&str) -> IResult<&str, Block> {
         .parse(input)?;
         let (input, expr) = expr(indent + 1, true)(input)?;
 
+        let head = cover(&iter, span(start, start));
+        let span = cover(&expr, head);
+
         let mut block = Vec::with_capacity(iter.len() + expr.len() + 3);
         block.extend(iter);
-        block.push(Token::ForStart(var));
+        block.push((Token::ForStart(pattern), head));
         block.extend(expr);
-        block.push(Token::Pop);
-        block.push(Token::ForEnd);
+        block.push((Token::Pop, span));
+        block.push((Token::ForEnd, span));
 
         Ok((input, block))
     }
 }
 
-fn loop_statement(indent: usize) -> impl FnMut(&str) -> IResult<&str, Block> {
+fn loop_statement(indent: usize) -> impl FnMut(&str) -> PResult<'_, Tokens<'_>> {
     move |input| {
+        let start = input;
         let (input, _) = tag("loop")(input)?;
         let (input, count) = delimited(
             space1,
@@ -1099,11 +1467,14 @@ fn loop_statement(indent: usize) -> impl FnMut(&str) -> IResult<&str, Block> {
         .parse(input)?;
         let (input, expr) = expr(indent + 1, true)(input)?;
 
+        let head = cover(&count, span(start, start));
+        let span = cover(&expr, head);
+
         let mut block = Vec::with_capacity(count.len() + expr.len() + 2);
         block.extend(count);
-        block.push(Token::LoopStart);
+        block.push((Token::LoopStart, head));
         block.extend(expr);
-        block.push(Token::LoopEnd);
+        block.push((Token::LoopEnd, span));
 
         Ok((input, block))
     }
@@ -1114,30 +1485,33 @@ fn expr_recursive(
     indent: usize,
     consume_semicolon: bool,
     precedence: u8,
-) -> IResult<&str, Block> {
+) -> PResult<'_, Tokens<'_>> {
     let (input, indent) = indentation(input, indent)?;
+    let start = input;
 
     let (input, unary_operator) = opt(unary_operator).parse(input)?;
 
     let (mut input, mut lhs) = alt((
-        map(literal, |literal| vec![Token::Literal(literal)]),
+        |input| {
+            let (rest, literal) = literal(input)?;
+            Ok((rest, vec![(Token::Literal(literal), span(input, rest))]))
+        },
         list,
+        record,
         let_statement(indent),
         if_statement(indent),
         match_statement(indent),
         for_statement(indent),
         loop_statement(indent),
+        lambda(indent),
         call(indent, precedence),
-        delimited(
-            (char('('), multispace0),
-            |input| expr_recursive(input, 0, true, 0),
-            (multispace0, char(')')),
-        ),
+        parenthesized,
     ))
     .parse(input)?;
 
     if let Some(unary_operator) = unary_operator {
-        lhs.push(Token::UnaryOperator(unary_operator));
+        let span = cover(&lhs, span(start, start));
+        lhs.push((Token::UnaryOperator(unary_operator), span));
     }
 
     while let Ok((next_input, op)) = preceded(multispace0, binary_operator).parse(input) {
@@ -1149,7 +1523,8 @@ fn expr_recursive(
         input = next_input;
 
         lhs.extend(rhs);
-        lhs.push(Token::BinaryOperator(op));
+        let span = cover(&lhs, span(start, start));
+        lhs.push((Token::BinaryOperator(op), span));
     }
 
     let (input, _) = if consume_semicolon {
@@ -1161,46 +1536,137 @@ fn expr_recursive(
     Ok((input, lhs))
 }
 
-fn expr(indent: usize, consume_semicolon: bool) -> impl FnMut(&str) -> IResult<&str, Block> {
+fn expr(indent: usize, consume_semicolon: bool) -> impl FnMut(&str) -> PResult<'_, Tokens<'_>> {
     move |input| expr_recursive(input, indent, consume_semicolon, 0)
 }
 
-fn expr_with_precedence(indent: usize, precedence: u8) -> impl FnMut(&str) -> IResult<&str, Block> {
+fn expr_with_precedence(
+    indent: usize,
+    precedence: u8,
+) -> impl FnMut(&str) -> PResult<'_, Tokens<'_>> {
     move |input| expr_recursive(input, indent, true, precedence)
 }
 
-fn block(input: &str, indent: usize) -> IResult<&str, Block> {
+fn block(input: &str, indent: usize) -> PResult<'_, Tokens<'_>> {
     terminated(expr(indent, true), end).parse(input)
 }
 
-fn definition(input: &str) -> IResult<&str, Definition> {
+fn definition(input: &str) -> PResult<'_, Definition<'_>> {
+    let start = input;
     let (input, name) = identifier(input)?;
-    let (input, weight) = opt(preceded(
+    let (input, annotation) = opt(preceded(
         char('@'),
-        alt((float_value, map(i32, |n| n as f32))),
+        alt((
+            map(tag("memo"), |_| (1.0, true)),
+            map(alt((float_value, map(i32, |n| n as f32))), |weight| {
+                (weight, false)
+            }),
+        )),
     ))
     .parse(input)?;
+    let (weight, memo) = annotation.unwrap_or((1.0, false));
     let (input, params) = many0(preceded(multispace1, identifier)).parse(input)?;
+    let head = span(start, input);
     let (input, _) = preceded(multispace0, char('=')).parse(input)?;
     let (input, block) = block(input, 1)?;
+    let (block, spans) = block.into_iter().unzip();
     let definition = Definition {
         name,
-        weight: weight.unwrap_or(1.0),
+        weight,
+        memo,
         params,
         block,
+        span: head,
+        spans,
     };
     Ok((input, definition))
 }
 
-pub fn parse(input: &str) -> crate::Result<Tree> {
-    let (input, tree) = terminated(
-        many0(preceded(many0((space0, line_ending)), definition)),
-        (multispace0, eof),
-    )
-    .parse(input)
-    .map_err(|_| crate::Error::ParseError)?;
-    assert!(input.is_empty());
-    Ok(tree)
+fn import(input: &str) -> PResult<'_, Import<'_>> {
+    let start = input;
+    let (input, _) = (tag("import"), space1, peek(char('"'))).parse(input)?;
+    let (input, (path, alias)) = cut((
+        string_value,
+        opt(preceded((space1, tag("as"), space1), identifier)),
+    ))
+    .parse(input)?;
+    let span = span(start, input);
+    let (input, _) = cut(end).parse(input)?;
+    let import = Import { path, alias, span };
+    Ok((input, import))
+}
+
+fn parse_failure(input: &str, next: &str, e: Err<ParseFailure>) -> crate::Error {
+    match e {
+        Err::Error(e) | Err::Failure(e) => {
+            let offset = input.len() - e.input.len();
+            let name = identifier(next).ok().map(|(_, name)| name);
+            crate::Error::ParseError.located(Some(Span::new(offset, offset + 1)), name)
+        }
+        Err::Incomplete(_) => crate::Error::ParseError,
+    }
+}
+
+pub fn parse_module(input: &str) -> crate::Result<Module<'_>> {
+    let mut module = Module::default();
+    let mut rest = input;
+
+    loop {
+        let (next, _) = many0((space0::<&str, ParseFailure>, line_ending))
+            .parse(rest)
+            .map_err(|_| crate::Error::ParseError)?;
+        if (multispace0::<&str, ParseFailure>, eof).parse(next).is_ok() {
+            break;
+        }
+
+        match import(next) {
+            Ok((next, mut import)) => {
+                import.span = resolve(import.span, input.len());
+                module.imports.push(import);
+                rest = next;
+                continue;
+            }
+            Err(Err::Error(_)) => (),
+            Err(e) => return Err(parse_failure(input, next, e)),
+        }
+
+        match definition(next) {
+            Ok((next, mut definition)) => {
+                definition.span = resolve(definition.span, input.len());
+                for span in &mut definition.spans {
+                    *span = resolve(*span, input.len());
+                }
+                module.definitions.push(definition);
+                rest = next;
+            }
+            Err(e) => return Err(parse_failure(input, next, e)),
+        }
+    }
+
+    Ok(module)
+}
+
+/// Parses only the definitions of a file. Imports are skipped over, since
+/// resolving them is up to `Program`.
+#[cfg(test)]
+pub fn parse(input: &str) -> crate::Result<Tree<'_>> {
+    parse_module(input).map(|module| module.definitions)
+}
+
+/// Parses a single expression on its own, such as one typed at a prompt. Spans
+/// count from the start of the expression.
+pub fn parse_expression(input: &str) -> crate::Result<(Vec<Token<'_>>, Vec<Span>)> {
+    match terminated(expr(0, true), (multispace0, eof)).parse(input) {
+        Ok((_, tokens)) => Ok(tokens
+            .into_iter()
+            .map(|(token, span)| (token, resolve(span, input.len())))
+            .unzip()),
+        Err(Err::Error(e) | Err::Failure(e)) => {
+            let offset = input.len() - e.input.len();
+            Err(crate::Error::ParseError.located(Some(Span::new(offset, offset + 1)), None))
+        }
+        Err(Err::Incomplete(_)) => Err(crate::Error::ParseError),
+    }
 }
 
 #[cfg(test)]
@@ -1211,12 +1677,29 @@ mod tests {
     // Helper function to simplify test assertions
     fn assert_parses<'a, P, O>(mut parser: P, input: &'a str, expected: O)
     where
-        P: FnMut(&'a str) -> IResult<&'a str, O>,
+        P: FnMut(&'a str) -> PResult<'a, O>,
         O: PartialEq + core::fmt::Debug,
     {
         assert_eq!(parser(input), Ok(("", expected)));
     }
 
+    // Same as `assert_parses`, ignoring the spans attached to each token
+    fn assert_tokens<'a, P>(mut parser: P, input: &'a str, expected: Vec<Token<'a>>)
+    where
+        P: FnMut(&'a str) -> PResult<'a, Tokens<'a>>,
+    {
+        let result = parser(input).map(|(input, tokens)| {
+            (
+                input,
+                tokens
+                    .into_iter()
+                    .map(|(token, _)| token)
+                    .collect::<Vec<_>>(),
+            )
+        });
+        assert_eq!(result, Ok(("", expected)));
+    }
+
     #[test]
     fn test_literals() {
         // Numeric literals
@@ -1406,12 +1889,13 @@ mod tests {
         assert_parses(binary_operator, "==", BinaryOperator::EqualTo);
         assert_parses(binary_operator, "..", BinaryOperator::RangeExclusive);
         assert_parses(binary_operator, "|>", BinaryOperator::Pipe);
+        assert_parses(binary_operator, ".", BinaryOperator::FunctionComposition);
     }
 
     #[test]
     fn test_expressions() {
         // Simple expressions
-        assert_parses(
+        assert_tokens(
             expr(0, true),
             "3 + 4 * 2",
             vec![
@@ -1424,7 +1908,7 @@ mod tests {
         );
 
         // Parentheses
-        assert_parses(
+        assert_tokens(
             expr(0, true),
             "(3 + 4) * 2",
             vec![
@@ -1437,7 +1921,7 @@ mod tests {
         );
 
         // Function calls
-        assert_parses(
+        assert_tokens(
             expr(0, true),
             "f x y",
             vec![
@@ -1446,12 +1930,27 @@ mod tests {
                 Token::Call("f", 2),
             ],
         );
+
+        // Function composition
+        assert_tokens(
+            expr(0, true),
+            "map (ss 0.5 . r 45) shapes",
+            vec![
+                Token::Literal(Literal::Float(0.5)),
+                Token::Call("ss", 1),
+                Token::Literal(Literal::Integer(45)),
+                Token::Call("r", 1),
+                Token::BinaryOperator(BinaryOperator::FunctionComposition),
+                Token::Call("shapes", 0),
+                Token::Call("map", 2),
+            ],
+        );
     }
 
     #[test]
     fn test_control_structures() {
         // If-else
-        assert_parses(
+        assert_tokens(
             if_statement(0),
             "if x > 0 -> 1 else -> -1",
             vec![
@@ -1466,7 +1965,7 @@ mod tests {
         );
 
         // Match
-        assert_parses(
+        assert_tokens(
             match_statement(0),
             "match x -> 1 -> 10; 2 -> 20",
             vec![
@@ -1483,7 +1982,7 @@ mod tests {
         );
 
         // Match
-        assert_parses(
+        assert_tokens(
             match_statement(0),
             "match x -> 1 -> 10; 2 if x == 2 -> 20",
             vec![
@@ -1502,15 +2001,35 @@ mod tests {
             ],
         );
 
-        // For loop
+        // Range and literal patterns
+        assert_parses(
+            pattern(0),
+            "0..10",
+            Pattern::Range(Literal::Integer(0), Literal::Integer(10), false),
+        );
+        assert_parses(
+            pattern(0),
+            "'a'..='z'",
+            Pattern::Range(Literal::Char('a'), Literal::Char('z'), true),
+        );
         assert_parses(
+            pattern(0),
+            "SQUARE,CIRCLE",
+            Pattern::Matches(vec![
+                Literal::Shape(ShapeKind::Square),
+                Literal::Shape(ShapeKind::Circle),
+            ]),
+        );
+
+        // For loop
+        assert_tokens(
             for_statement(0),
             "for i in 0..5 -> i * 2",
             vec![
                 Token::Literal(Literal::Integer(0)),
                 Token::Literal(Literal::Integer(5)),
                 Token::BinaryOperator(BinaryOperator::RangeExclusive),
-                Token::ForStart("i"),
+                Token::ForStart(Pattern::Bind("i".into())),
                 Token::Call("i", 0),
                 Token::Literal(Literal::Integer(2)),
                 Token::BinaryOperator(BinaryOperator::Multiplication),
@@ -1520,25 +2039,164 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_lambdas() {
+        assert_tokens(
+            lambda(0),
+            "\\x y -> x * y",
+            vec![
+                Token::Lambda(vec!["x", "y"], 4),
+                Token::Call("x", 0),
+                Token::Call("y", 0),
+                Token::BinaryOperator(BinaryOperator::Multiplication),
+                Token::Return(None),
+            ],
+        );
+
+        // Lambdas can be passed as arguments
+        assert_tokens(
+            expr(0, true),
+            "map (\\x -> x + 1) items",
+            vec![
+                Token::Lambda(vec!["x"], 4),
+                Token::Call("x", 0),
+                Token::Literal(Literal::Integer(1)),
+                Token::BinaryOperator(BinaryOperator::Addition),
+                Token::Return(None),
+                Token::Call("items", 0),
+                Token::Call("map", 2),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_records() {
+        assert_tokens(
+            expr(0, true),
+            "{ x = 1, y = z }",
+            vec![
+                Token::Literal(Literal::Integer(1)),
+                Token::Call("z", 0),
+                Token::Record(vec!["x", "y"]),
+            ],
+        );
+
+        // Functional update and field access
+        assert_tokens(
+            expr(0, true),
+            "{ state | frame = state.frame + 1 }",
+            vec![
+                Token::Call("state", 0),
+                Token::Call("state.frame", 0),
+                Token::Literal(Literal::Integer(1)),
+                Token::BinaryOperator(BinaryOperator::Addition),
+                Token::Update(vec!["frame"]),
+            ],
+        );
+
+        // Record patterns
+        assert_parses(
+            pattern(0),
+            "{ frame = 0, pos }",
+            Pattern::Record(vec![
+                ("frame".into(), Pattern::Matches(vec![Literal::Integer(0)])),
+                ("pos".into(), Pattern::Bind("pos".into())),
+            ]),
+        );
+
+        // Duplicate fields
+        assert!(record("{ x = 1, x = 2 }").is_err());
+    }
+
+    #[test]
+    fn test_tuples() {
+        assert_tokens(
+            expr(0, true),
+            "(x, 2, \"red\")",
+            vec![
+                Token::Call("x", 0),
+                Token::Literal(Literal::Integer(2)),
+                Token::Literal(Literal::String("red".into())),
+                Token::Tuple(3),
+            ],
+        );
+
+        // A single parenthesized expression is still a grouping
+        assert_tokens(expr(0, true), "(x)", vec![Token::Call("x", 0)]);
+
+        // Destructuring patterns
+        assert_parses(
+            pattern(0),
+            "(0, y)",
+            Pattern::Tuple(vec![
+                Pattern::Matches(vec![Literal::Integer(0)]),
+                Pattern::Bind("y".into()),
+            ]),
+        );
+        assert_parses(
+            pattern(0),
+            "[h, ..t]",
+            Pattern::List(
+                vec![Pattern::Bind("h".into())],
+                Some(Box::new(Pattern::Bind("t".into()))),
+            ),
+        );
+        assert_parses(
+            pattern(0),
+            "[_, ..]",
+            Pattern::List(vec![Pattern::Wildcard], Some(Box::new(Pattern::Wildcard))),
+        );
+
+        assert_tokens(
+            expr(0, true),
+            "for (x, y) in points -> x",
+            vec![
+                Token::Call("points", 0),
+                Token::ForStart(Pattern::Tuple(vec![
+                    Pattern::Bind("x".into()),
+                    Pattern::Bind("y".into()),
+                ])),
+                Token::Call("x", 0),
+                Token::Pop,
+                Token::ForEnd,
+            ],
+        );
+
+        assert_tokens(
+            expr(0, true),
+            "let (x, y) = p -> x",
+            vec![
+                Token::Call("p", 0),
+                Token::Destructure(Pattern::Tuple(vec![
+                    Pattern::Bind("x".into()),
+                    Pattern::Bind("y".into()),
+                ])),
+                Token::Call("x", 0),
+                Token::Pop,
+            ],
+        );
+    }
+
     #[test]
     fn test_definitions() {
         // Simple definition
-        assert_parses(
-            definition,
-            "square = SQUARE",
-            Definition {
+        assert_eq!(
+            parse("square = SQUARE").unwrap(),
+            vec![Definition {
                 name: "square",
                 params: vec![],
                 block: vec![Token::Literal(Literal::Shape(ShapeKind::Square))],
                 weight: 1.0,
-            },
+                memo: false,
+                span: Span::new(0, 6),
+                spans: vec![Span::new(9, 15)],
+            }],
         );
 
         // Parameterized definition
-        assert_parses(
-            definition,
-            "repeat@2.5 n shape = loop n -> shape",
-            Definition {
+        assert_eq!(
+            parse("repeat@2.5 n shape = loop n -> shape").unwrap(),
+            vec![Definition {
                 name: "repeat",
                 params: vec!["n", "shape"],
                 block: vec![
@@ -1548,10 +2206,134 @@ mod tests {
                     Token::LoopEnd,
                 ],
                 weight: 2.5,
-            },
+                memo: false,
+                span: Span::new(0, 18),
+                spans: vec![
+                    Span::new(26, 27),
+                    Span::new(21, 27),
+                    Span::new(31, 36),
+                    Span::new(21, 36),
+                ],
+            }],
+        );
+
+        // Memoized definition
+        assert_eq!(
+            parse("fib@memo n = n").unwrap(),
+            vec![Definition {
+                name: "fib",
+                params: vec!["n"],
+                block: vec![Token::Call("n", 0)],
+                weight: 1.0,
+                memo: true,
+                span: Span::new(0, 10),
+                spans: vec![Span::new(13, 14)],
+            }],
         );
     }
 
+    #[test]
+    fn test_spans() {
+        let tree = parse("start =\n    r 45 (ss 2 SQUARE) : CIRCLE\n").unwrap();
+        let spans: Vec<_> = tree[0].block.iter().zip(&tree[0].spans).collect();
+        assert_eq!(
+            spans,
+            vec![
+                (&Token::Literal(Literal::Integer(45)), &Span::new(14, 16)),
+                (&Token::Literal(Literal::Integer(2)), &Span::new(21, 22)),
+                (
+                    &Token::Literal(Literal::Shape(ShapeKind::Square)),
+                    &Span::new(23, 29)
+                ),
+                (&Token::Call("ss", 2), &Span::new(18, 29)),
+                (&Token::Call("r", 2), &Span::new(12, 29)),
+                (
+                    &Token::Literal(Literal::Shape(ShapeKind::Circle)),
+                    &Span::new(33, 39)
+                ),
+                (
+                    &Token::BinaryOperator(BinaryOperator::Composition),
+                    &Span::new(12, 39)
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_error_location() {
+        let err = parse("start = SQUARE\n\nbroken =\n    r 45 (ss 2 SQUARE\n").unwrap_err();
+        assert!(matches!(err.inner(), crate::Error::ParseError));
+        assert_eq!(err.definition(), Some("broken"));
+        // Points at the parenthesis that is never closed
+        assert_eq!(err.span().map(|span| span.start), Some(34));
+    }
+
+    #[test]
+    fn test_parse_expression() {
+        let (tokens, spans) = parse_expression(" ss 2 SQUARE ").unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Literal(Literal::Integer(2)),
+                Token::Literal(Literal::Shape(ShapeKind::Square)),
+                Token::Call("ss", 2),
+            ]
+        );
+        assert_eq!(
+            spans,
+            vec![Span::new(4, 5), Span::new(6, 12), Span::new(1, 12)]
+        );
+
+        let err = parse_expression("1 +").unwrap_err();
+        assert!(matches!(err.inner(), crate::Error::ParseError));
+        assert_eq!(err.definition(), None);
+        assert!(parse_expression("x = 1").is_err());
+    }
+
+    #[test]
+    fn test_imports() {
+        let module = parse_module(
+            "import \"lib/shapes.xylo\" as shapes\nimport \"util.xylo\"\n\nstart =\n    shapes.flower 3\n",
+        )
+        .unwrap();
+        assert_eq!(
+            module.imports,
+            vec![
+                Import {
+                    path: "lib/shapes.xylo".into(),
+                    alias: Some("shapes"),
+                    span: Span::new(0, 34),
+                },
+                Import {
+                    path: "util.xylo".into(),
+                    alias: None,
+                    span: Span::new(35, 53),
+                },
+            ]
+        );
+        assert_eq!(
+            module.definitions[0].block,
+            vec![
+                Token::Literal(Literal::Integer(3)),
+                Token::Call("shapes.flower", 1)
+            ]
+        );
+
+        // Ranges between identifiers are not mistaken for qualified names
+        assert_tokens(
+            expr(0, false),
+            "a..b",
+            vec![
+                Token::Call("a", 0),
+                Token::Call("b", 0),
+                Token::BinaryOperator(BinaryOperator::RangeExclusive),
+            ],
+        );
+
+        let err = parse_module("import \"unterminated\n").unwrap_err();
+        assert!(matches!(err.inner(), crate::Error::ParseError));
+    }
+
     #[test]
     fn test_full_parser() {
         let program = r#"
@@ -1604,7 +2386,7 @@ double x = x * 2
 
     #[test]
     fn test_operator_precedence() {
-        assert_parses(
+        assert_tokens(
             expr(0, true),
             "1 + 2 * 3",
             vec![
@@ -1616,7 +2398,7 @@ double x = x * 2
             ],
         );
 
-        assert_parses(
+        assert_tokens(
             expr(0, true),
             "1 * 2 + 3",
             vec![
@@ -1628,7 +2410,7 @@ double x = x * 2
             ],
         );
 
-        assert_parses(
+        assert_tokens(
             expr(0, true),
             "1 + 2 + 3",
             vec![
@@ -1643,7 +2425,7 @@ double x = x * 2
 
     #[test]
     fn test_list_expressions() {
-        assert_parses(
+        assert_tokens(
             list,
             "[1, 2, 3]",
             vec![
@@ -1654,7 +2436,7 @@ double x = x * 2
             ],
         );
 
-        assert_parses(
+        assert_tokens(
             list,
             "[SQUARE, CIRCLE, TRIANGLE, SQUARE : CIRCLE]",
             vec![
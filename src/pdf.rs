@@ -0,0 +1,730 @@
+use std::rc::Rc;
+
+#[cfg(feature = "io")]
+use {crate::renderer::load_image, tiny_skia::FilterQuality};
+
+#[cfg(not(feature = "io"))]
+use crate::error::Error;
+
+use crate::error::Result;
+use crate::renderer::{flatten_shape, ShapeData};
+use crate::shape::{Color, Gradient, Shape};
+
+use core::cell::RefCell;
+use hashbrown::HashMap;
+use miniz_oxide::deflate::compress_to_vec_zlib;
+use palette::{rgb::Rgba, FromColor, Hsla};
+use pdf_writer::{
+    types::{
+        BlendMode as PdfBlendMode, ColorSpaceOperand, FunctionShadingType, LineCapStyle,
+        LineJoinStyle, MaskType, PaintType, TilingType,
+    },
+    writers, Content, Filter, Finish, Name, Pdf, Rect, Ref,
+};
+use tiny_skia::{BlendMode, FillRule, LineCap, LineJoin, Path, PathSegment, Transform};
+
+// The resources a content stream uses, which are named after their object
+// ids.
+#[derive(Default)]
+struct Resources {
+    states: Vec<Ref>,
+    patterns: Vec<Ref>,
+    x_objects: Vec<Ref>,
+}
+
+impl Resources {
+    fn use_state(&mut self, id: Ref) -> String {
+        if !self.states.contains(&id) {
+            self.states.push(id);
+        }
+        resource_name('G', id)
+    }
+
+    fn use_pattern(&mut self, id: Ref) -> String {
+        if !self.patterns.contains(&id) {
+            self.patterns.push(id);
+        }
+        resource_name('P', id)
+    }
+
+    fn use_x_object(&mut self, id: Ref) -> String {
+        if !self.x_objects.contains(&id) {
+            self.x_objects.push(id);
+        }
+        resource_name('X', id)
+    }
+
+    fn write(&self, mut resources: writers::Resources) {
+        for (prefix, ids) in [
+            ('G', &self.states),
+            ('P', &self.patterns),
+            ('X', &self.x_objects),
+        ] {
+            if ids.is_empty() {
+                continue;
+            }
+            let mut dict = match prefix {
+                'G' => resources.ext_g_states(),
+                'P' => resources.patterns(),
+                _ => resources.x_objects(),
+            };
+            for id in ids {
+                let name = resource_name(prefix, *id);
+                dict.pair(Name(name.as_bytes()), *id);
+            }
+        }
+    }
+}
+
+fn resource_name(prefix: char, id: Ref) -> String {
+    format!("{}{}", prefix, id.get())
+}
+
+pub(crate) struct PdfWriter {
+    pdf: Pdf,
+    next_id: Ref,
+    width: u32,
+    height: u32,
+    page_tree: Ref,
+    pages: Vec<Ref>,
+    // Graphics states are shared between shapes with the same opacity and
+    // blend mode.
+    states: HashMap<(u32, u32, Option<PdfBlendMode>), Ref>,
+}
+
+impl PdfWriter {
+    pub(crate) fn new(width: u32, height: u32) -> Self {
+        let mut next_id = Ref::new(1);
+        let page_tree = next_id.bump();
+        PdfWriter {
+            pdf: Pdf::new(),
+            next_id,
+            width,
+            height,
+            page_tree,
+            pages: Vec::new(),
+            states: HashMap::new(),
+        }
+    }
+
+    // Moves shape coordinates, which are centered, onto the page, which has
+    // its origin in the bottom left. Both have y pointing up.
+    fn page_transform(&self, transform: Transform) -> Transform {
+        transform.post_translate(self.width as f32 / 2.0, self.height as f32 / 2.0)
+    }
+
+    fn page_rect(&self) -> Rect {
+        Rect::new(0.0, 0.0, self.width as f32, self.height as f32)
+    }
+
+    pub(crate) fn add_page(&mut self, shape: Rc<RefCell<Shape>>) -> Result<()> {
+        let data = flatten_shape(shape)?;
+        let mut content = Content::new();
+        let mut resources = Resources::default();
+        self.shapes(&mut content, &mut resources, data)?;
+
+        let page_id = self.next_id.bump();
+        let content_id = self.next_id.bump();
+        let content = compress_to_vec_zlib(&content.finish(), 6);
+        self.pdf
+            .stream(content_id, &content)
+            .filter(Filter::FlateDecode);
+
+        let page_rect = self.page_rect();
+        let mut page = self.pdf.page(page_id);
+        page.parent(self.page_tree)
+            .media_box(page_rect)
+            .contents(content_id);
+        page.group().transparency().color_space().device_rgb();
+        resources.write(page.resources());
+        page.finish();
+
+        self.pages.push(page_id);
+        Ok(())
+    }
+
+    pub(crate) fn finish(mut self) -> Vec<u8> {
+        let catalog_id = self.next_id.bump();
+        self.pdf.catalog(catalog_id).pages(self.page_tree);
+        self.pdf
+            .pages(self.page_tree)
+            .kids(self.pages.iter().copied())
+            .count(self.pages.len() as i32);
+        self.pdf.finish()
+    }
+
+    fn shapes(
+        &mut self,
+        content: &mut Content,
+        resources: &mut Resources,
+        data: Vec<ShapeData>,
+    ) -> Result<()> {
+        for shape_data in data {
+            content.save_state();
+            self.shape(content, resources, shape_data)?;
+            content.restore_state();
+        }
+        Ok(())
+    }
+
+    fn shape(
+        &mut self,
+        content: &mut Content,
+        resources: &mut Resources,
+        shape_data: ShapeData,
+    ) -> Result<()> {
+        match shape_data.clone() {
+            ShapeData::FillPath {
+                path,
+                transform,
+                fill_rule,
+                paint,
+                color,
+                mask,
+                pattern,
+                ..
+            } => {
+                if let Some(data) = mask {
+                    self.mask(content, resources, data)?;
+                }
+                let transform = self.page_transform(transform);
+                let alpha = match pattern {
+                    Some((data, _)) => {
+                        let pattern = self.tiling_pattern(data, transform)?;
+                        let name = resources.use_pattern(pattern);
+                        content.set_fill_color_space(ColorSpaceOperand::Pattern);
+                        content.set_fill_pattern([], Name(name.as_bytes()));
+                        1.0
+                    }
+                    None => self.paint(content, resources, color, transform, false),
+                };
+                self.state(content, resources, alpha, 1.0, paint.blend_mode);
+
+                content.transform(matrix(transform));
+                write_path(content, &path);
+                match fill_rule {
+                    FillRule::Winding => content.fill_nonzero(),
+                    FillRule::EvenOdd => content.fill_even_odd(),
+                };
+            }
+            ShapeData::StrokePath {
+                path,
+                transform,
+                stroke,
+                paint,
+                color,
+                mask,
+                pattern,
+                ..
+            } => {
+                if let Some(data) = mask {
+                    self.mask(content, resources, data)?;
+                }
+                let transform = self.page_transform(transform);
+                let alpha = match pattern {
+                    Some((data, _)) => {
+                        let pattern = self.tiling_pattern(data, transform)?;
+                        let name = resources.use_pattern(pattern);
+                        content.set_stroke_color_space(ColorSpaceOperand::Pattern);
+                        content.set_stroke_pattern([], Name(name.as_bytes()));
+                        1.0
+                    }
+                    None => self.paint(content, resources, color, transform, true),
+                };
+                self.state(content, resources, 1.0, alpha, paint.blend_mode);
+
+                content.set_line_width(stroke.width);
+                content.set_line_cap(match stroke.line_cap {
+                    LineCap::Butt => LineCapStyle::ButtCap,
+                    LineCap::Round => LineCapStyle::RoundCap,
+                    LineCap::Square => LineCapStyle::ProjectingSquareCap,
+                });
+                content.set_line_join(match stroke.line_join {
+                    LineJoin::Miter | LineJoin::MiterClip => LineJoinStyle::MiterJoin,
+                    LineJoin::Round => LineJoinStyle::RoundJoin,
+                    LineJoin::Bevel => LineJoinStyle::BevelJoin,
+                });
+                content.set_miter_limit(stroke.miter_limit);
+
+                // The dashes are cut out of the path, as tiny-skia doesn't
+                // give back the dash array.
+                let path = match &stroke.dash {
+                    Some(dash) => match path.dash(dash, 1.0) {
+                        Some(path) => path,
+                        None => return Ok(()),
+                    },
+                    None => path,
+                };
+
+                content.transform(matrix(transform));
+                write_path(content, &path);
+                content.stroke();
+            }
+            #[cfg(feature = "io")]
+            ShapeData::Image {
+                transform,
+                paint,
+                mask,
+                ..
+            }
+            | ShapeData::Text {
+                transform,
+                paint,
+                mask,
+                ..
+            } => {
+                if let Some(data) = mask {
+                    self.mask(content, resources, data)?;
+                }
+                let image = load_image(&shape_data, self.width, self.height)?;
+                let image_id = self.image(&image, paint.quality != FilterQuality::Nearest);
+                let name = resources.use_x_object(image_id);
+                self.state(
+                    content,
+                    resources,
+                    paint.opacity,
+                    paint.opacity,
+                    paint.blend_mode,
+                );
+
+                // Images are drawn into a unit square with their first row at
+                // the top, which is flipped to match the rasterizer.
+                let width = image.width() as f32;
+                let height = image.height() as f32;
+                content.transform(matrix(self.page_transform(transform)));
+                content.transform([
+                    width,
+                    0.0,
+                    0.0,
+                    -height,
+                    -((image.width() / 2) as f32),
+                    height - (image.height() / 2) as f32,
+                ]);
+                content.x_object(Name(name.as_bytes()));
+            }
+            #[cfg(not(feature = "io"))]
+            ShapeData::Image { .. } | ShapeData::Text { .. } => return Err(Error::NoIO),
+            ShapeData::Fill { color, .. } => {
+                content.set_fill_rgb(color.red(), color.green(), color.blue());
+                self.state(
+                    content,
+                    resources,
+                    color.alpha(),
+                    1.0,
+                    BlendMode::SourceOver,
+                );
+                content.rect(0.0, 0.0, self.width as f32, self.height as f32);
+                content.fill_nonzero();
+            }
+            ShapeData::FillPaint { gradient, .. } => {
+                let transform = self.page_transform(Transform::identity());
+                let alpha = self.paint(
+                    content,
+                    resources,
+                    Color::Gradient(gradient),
+                    transform,
+                    false,
+                );
+                self.state(content, resources, alpha, 1.0, BlendMode::SourceOver);
+                content.rect(0.0, 0.0, self.width as f32, self.height as f32);
+                content.fill_nonzero();
+            }
+        }
+        Ok(())
+    }
+
+    // Sets the fill or stroke color, giving back its opacity.
+    fn paint(
+        &mut self,
+        content: &mut Content,
+        resources: &mut Resources,
+        color: Color,
+        transform: Transform,
+        stroke: bool,
+    ) -> f32 {
+        let (r, g, b, a) = match color {
+            Color::Solid(color) => rgba(color),
+            Color::Gradient(gradient) if gradient.stops.len() == 1 => rgba(gradient.stops[0].1),
+            Color::Gradient(gradient) => match self.shading_pattern(&gradient, transform) {
+                Some((pattern, alpha)) => {
+                    let name = resources.use_pattern(pattern);
+                    if stroke {
+                        content.set_stroke_color_space(ColorSpaceOperand::Pattern);
+                        content.set_stroke_pattern([], Name(name.as_bytes()));
+                    } else {
+                        content.set_fill_color_space(ColorSpaceOperand::Pattern);
+                        content.set_fill_pattern([], Name(name.as_bytes()));
+                    }
+                    return alpha;
+                }
+                None => (1.0, 1.0, 1.0, 1.0),
+            },
+        };
+
+        if stroke {
+            content.set_stroke_rgb(r, g, b);
+        } else {
+            content.set_fill_rgb(r, g, b);
+        }
+        a
+    }
+
+    fn state(
+        &mut self,
+        content: &mut Content,
+        resources: &mut Resources,
+        fill_alpha: f32,
+        stroke_alpha: f32,
+        blend_mode: BlendMode,
+    ) {
+        let blend_mode = pdf_blend_mode(blend_mode);
+        if fill_alpha >= 1.0 && stroke_alpha >= 1.0 && blend_mode.is_none() {
+            return;
+        }
+
+        let key = (fill_alpha.to_bits(), stroke_alpha.to_bits(), blend_mode);
+        let id = match self.states.get(&key) {
+            Some(id) => *id,
+            None => {
+                let id = self.next_id.bump();
+                let mut state = self.pdf.ext_graphics(id);
+                state
+                    .non_stroking_alpha(fill_alpha)
+                    .stroking_alpha(stroke_alpha);
+                if let Some(blend_mode) = blend_mode {
+                    state.blend_mode(blend_mode);
+                }
+                state.finish();
+                self.states.insert(key, id);
+                id
+            }
+        };
+
+        let name = resources.use_state(id);
+        content.set_parameters(Name(name.as_bytes()));
+    }
+
+    // Masks use the luminance of what's drawn in them, the same as in the
+    // rasterizer.
+    fn mask(
+        &mut self,
+        content: &mut Content,
+        resources: &mut Resources,
+        data: Vec<ShapeData>,
+    ) -> Result<()> {
+        let group_id = self.form(data)?;
+        let state_id = self.next_id.bump();
+        self.pdf
+            .ext_graphics(state_id)
+            .soft_mask()
+            .subtype(MaskType::Luminosity)
+            .group(group_id);
+
+        let name = resources.use_state(state_id);
+        content.set_parameters(Name(name.as_bytes()));
+        Ok(())
+    }
+
+    fn form(&mut self, data: Vec<ShapeData>) -> Result<Ref> {
+        let mut content = Content::new();
+        let mut resources = Resources::default();
+        self.shapes(&mut content, &mut resources, data)?;
+
+        let id = self.next_id.bump();
+        let content = compress_to_vec_zlib(&content.finish(), 6);
+        let page_rect = self.page_rect();
+        let mut form = self.pdf.form_xobject(id, &content);
+        form.bbox(page_rect);
+        form.filter(Filter::FlateDecode);
+        form.group().transparency().color_space().device_rgb();
+        resources.write(form.resources());
+        form.finish();
+        Ok(id)
+    }
+
+    // Pattern shapes are drawn over the whole page in the rasterizer, then
+    // moved by the transform of the shape they fill, so the tile is flipped
+    // into that space and back. Every spread mode is drawn as repeating.
+    fn tiling_pattern(&mut self, data: Vec<ShapeData>, transform: Transform) -> Result<Ref> {
+        let mut content = Content::new();
+        let mut resources = Resources::default();
+        self.shapes(&mut content, &mut resources, data)?;
+
+        let flip = Transform::from_row(1.0, 0.0, 0.0, -1.0, 0.0, self.height as f32);
+        let id = self.next_id.bump();
+        let content = compress_to_vec_zlib(&content.finish(), 6);
+        let (page_rect, width, height) = (self.page_rect(), self.width, self.height);
+        let mut pattern = self.pdf.tiling_pattern(id, &content);
+        pattern
+            .paint_type(PaintType::Colored)
+            .tiling_type(TilingType::ConstantSpacing)
+            .bbox(page_rect)
+            .x_step(width as f32)
+            .y_step(height as f32)
+            .matrix(matrix(flip.post_concat(transform)));
+        pattern.filter(Filter::FlateDecode);
+        resources.write(pattern.resources());
+        pattern.finish();
+        Ok(id)
+    }
+
+    // PDF shadings only extend their end colors, so reflected and repeated
+    // gradients are drawn padded. They also can't vary in opacity, so they
+    // take that of their most opaque stop.
+    fn shading_pattern(&mut self, gradient: &Gradient, transform: Transform) -> Option<(Ref, f32)> {
+        if gradient.stops.is_empty()
+            || gradient.radius.is_none() && gradient.start == gradient.end
+            || gradient.radius.is_some() && gradient.radius.unwrap() <= 0.0
+        {
+            return None;
+        }
+
+        let mut stops: Vec<(f32, [f32; 3])> = Vec::with_capacity(gradient.stops.len() + 2);
+        let mut alpha: f32 = 0.0;
+        for (pos, color) in &gradient.stops {
+            let (r, g, b, a) = rgba(*color);
+            let min = stops.last().map(|(pos, _)| *pos).unwrap_or(0.0);
+            stops.push((pos.clamp(min, 1.0), [r, g, b]));
+            alpha = alpha.max(a);
+        }
+        if stops[0].0 > 0.0 {
+            stops.insert(0, (0.0, stops[0].1));
+        }
+        if stops[stops.len() - 1].0 < 1.0 {
+            stops.push((1.0, stops[stops.len() - 1].1));
+        }
+
+        let mut functions = Vec::with_capacity(stops.len() - 1);
+        for pair in stops.windows(2) {
+            let id = self.next_id.bump();
+            self.pdf
+                .exponential_function(id)
+                .domain([0.0, 1.0])
+                .c0(pair[0].1)
+                .c1(pair[1].1)
+                .n(1.0);
+            functions.push(id);
+        }
+        let function = if functions.len() == 1 {
+            functions[0]
+        } else {
+            let id = self.next_id.bump();
+            self.pdf
+                .stitching_function(id)
+                .domain([0.0, 1.0])
+                .functions(functions.iter().copied())
+                .bounds(stops[1..stops.len() - 1].iter().map(|(pos, _)| *pos))
+                .encode(functions.iter().flat_map(|_| [0.0, 1.0]));
+            id
+        };
+
+        let (start_x, start_y) = gradient.start;
+        let (end_x, end_y) = gradient.end;
+        let id = self.next_id.bump();
+        let mut pattern = self.pdf.shading_pattern(id);
+        let mut shading = pattern.function_shading();
+        shading.color_space().device_rgb();
+        shading.function(function).extend([true, true]);
+        match gradient.radius {
+            // tiny-skia's radial gradients go from a point at the start to a
+            // circle around the end.
+            Some(radius) => shading
+                .shading_type(FunctionShadingType::Radial)
+                .coords([start_x, start_y, 0.0, end_x, end_y, radius]),
+            None => shading
+                .shading_type(FunctionShadingType::Axial)
+                .coords([start_x, start_y, end_x, end_y]),
+        };
+        shading.finish();
+        pattern.matrix(matrix(gradient.transform.post_concat(transform)));
+        pattern.finish();
+
+        Some((id, alpha))
+    }
+
+    #[cfg(feature = "io")]
+    fn image(&mut self, image: &tiny_skia::Pixmap, interpolate: bool) -> Ref {
+        let mut rgb = Vec::with_capacity(image.pixels().len() * 3);
+        let mut alpha = Vec::with_capacity(image.pixels().len());
+        for pixel in image.pixels() {
+            let pixel = pixel.demultiply();
+            rgb.extend([pixel.red(), pixel.green(), pixel.blue()]);
+            alpha.push(pixel.alpha());
+        }
+
+        let mask_id = if alpha.iter().any(|a| *a < 255) {
+            let id = self.next_id.bump();
+            let alpha = compress_to_vec_zlib(&alpha, 6);
+            let mut mask = self.pdf.image_xobject(id, &alpha);
+            mask.width(image.width() as i32)
+                .height(image.height() as i32)
+                .bits_per_component(8)
+                .interpolate(interpolate);
+            mask.filter(Filter::FlateDecode);
+            mask.color_space().device_gray();
+            mask.finish();
+            Some(id)
+        } else {
+            None
+        };
+
+        let id = self.next_id.bump();
+        let rgb = compress_to_vec_zlib(&rgb, 6);
+        let mut xobject = self.pdf.image_xobject(id, &rgb);
+        xobject
+            .width(image.width() as i32)
+            .height(image.height() as i32)
+            .bits_per_component(8)
+            .interpolate(interpolate);
+        xobject.filter(Filter::FlateDecode);
+        xobject.color_space().device_rgb();
+        if let Some(mask_id) = mask_id {
+            xobject.s_mask(mask_id);
+        }
+        xobject.finish();
+        id
+    }
+}
+
+fn rgba(color: Hsla<f32>) -> (f32, f32, f32, f32) {
+    let color = Rgba::from_color(color);
+    (
+        color.red.clamp(0.0, 1.0),
+        color.green.clamp(0.0, 1.0),
+        color.blue.clamp(0.0, 1.0),
+        color.alpha.clamp(0.0, 1.0),
+    )
+}
+
+fn matrix(ts: Transform) -> [f32; 6] {
+    [ts.sx, ts.ky, ts.kx, ts.sy, ts.tx, ts.ty]
+}
+
+// PDF has no quadratic curves, so they're raised to cubic ones.
+fn write_path(content: &mut Content, path: &Path) {
+    let mut last = (0.0, 0.0);
+    for segment in path.segments() {
+        match segment {
+            PathSegment::MoveTo(p) => {
+                content.move_to(p.x, p.y);
+                last = (p.x, p.y);
+            }
+            PathSegment::LineTo(p) => {
+                content.line_to(p.x, p.y);
+                last = (p.x, p.y);
+            }
+            PathSegment::QuadTo(p1, p) => {
+                let (x0, y0) = last;
+                content.cubic_to(
+                    x0 + 2.0 / 3.0 * (p1.x - x0),
+                    y0 + 2.0 / 3.0 * (p1.y - y0),
+                    p.x + 2.0 / 3.0 * (p1.x - p.x),
+                    p.y + 2.0 / 3.0 * (p1.y - p.y),
+                    p.x,
+                    p.y,
+                );
+                last = (p.x, p.y);
+            }
+            PathSegment::CubicTo(p1, p2, p) => {
+                content.cubic_to(p1.x, p1.y, p2.x, p2.y, p.x, p.y);
+                last = (p.x, p.y);
+            }
+            PathSegment::Close => {
+                content.close_path();
+            }
+        }
+    }
+}
+
+// The Porter-Duff modes have no PDF equivalent and are drawn normally.
+fn pdf_blend_mode(blend_mode: BlendMode) -> Option<PdfBlendMode> {
+    Some(match blend_mode {
+        BlendMode::Multiply => PdfBlendMode::Multiply,
+        BlendMode::Screen => PdfBlendMode::Screen,
+        BlendMode::Overlay => PdfBlendMode::Overlay,
+        BlendMode::Darken => PdfBlendMode::Darken,
+        BlendMode::Lighten => PdfBlendMode::Lighten,
+        BlendMode::ColorDodge => PdfBlendMode::ColorDodge,
+        BlendMode::ColorBurn => PdfBlendMode::ColorBurn,
+        BlendMode::HardLight => PdfBlendMode::HardLight,
+        BlendMode::SoftLight => PdfBlendMode::SoftLight,
+        BlendMode::Difference => PdfBlendMode::Difference,
+        BlendMode::Exclusion => PdfBlendMode::Exclusion,
+        BlendMode::Hue => PdfBlendMode::Hue,
+        BlendMode::Saturation => PdfBlendMode::Saturation,
+        BlendMode::Color => PdfBlendMode::Color,
+        BlendMode::Luminosity => PdfBlendMode::Luminosity,
+        _ => return None,
+    })
+}
+
+/// Draws each shape on its own page of a PDF document, with pages of the
+/// given size in points. Paths and gradients stay as vectors, while images and
+/// text are embedded as pictures.
+pub fn render_pdf<I>(shapes: I, width: u32, height: u32) -> Result<Vec<u8>>
+where
+    I: IntoIterator<Item = Rc<RefCell<Shape>>>,
+{
+    let mut writer = PdfWriter::new(width, height);
+    for shape in shapes {
+        writer.add_page(shape)?;
+    }
+    Ok(writer.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::shape::{BasicShape, PathSegment, Style};
+
+    fn count(pdf: &[u8], needle: &str) -> usize {
+        pdf.windows(needle.len())
+            .filter(|window| *window == needle.as_bytes())
+            .count()
+    }
+
+    #[test]
+    fn test_render_pdf() {
+        let mut gradient = Gradient::radial(0.0, 0.0, 0.0, 0.0, 10.0);
+        gradient.set_stop_hsl(0.0, 0.0, 1.0, 0.5);
+        gradient.set_stop_hsl(0.5, 120.0, 1.0, 0.5);
+        gradient.set_stop_hsl(1.0, 240.0, 1.0, 0.5);
+
+        let circle = Rc::new(RefCell::new(Shape::Basic(
+            BasicShape::Circle {
+                x: 0.0,
+                y: 0.0,
+                radius: 10.0,
+                transform: Transform::identity(),
+                zindex: None,
+                color: Color::Gradient(gradient),
+                blend_mode: BlendMode::Screen,
+                anti_alias: true,
+                style: Style::Fill(FillRule::Winding),
+            },
+            None,
+            None,
+        )));
+        let mut line = Shape::path(vec![
+            PathSegment::MoveTo(0.0, 0.0),
+            PathSegment::LineTo(10.0, 0.0),
+        ]);
+        line.set_hsla(0.0, 0.0, 1.0, 0.5);
+        line.set_stroke_width(2.0);
+        line.set_mask(Rc::new(RefCell::new(Shape::square())));
+        let line = Rc::new(RefCell::new(line));
+
+        let pdf = render_pdf([circle, line.clone(), line], 100, 50).unwrap();
+        assert!(pdf.starts_with(b"%PDF-"));
+        assert_eq!(count(&pdf, "/Type /Page\n"), 3);
+        assert_eq!(count(&pdf, "/Count 3"), 1);
+        assert_eq!(count(&pdf, "/MediaBox [0 0 100 50]"), 3);
+        assert_eq!(count(&pdf, "/ShadingType 3"), 1);
+        assert_eq!(count(&pdf, "/FunctionType 3"), 1);
+        assert_eq!(count(&pdf, "/BM /Screen"), 1);
+        assert_eq!(count(&pdf, "/S /Luminosity"), 2);
+        // The half transparent stroke shares one graphics state.
+        assert_eq!(count(&pdf, "/CA 0.5"), 1);
+    }
+}
@@ -0,0 +1,500 @@
+#[cfg(feature = "std")]
+use std::rc::Rc;
+
+#[cfg(feature = "alloc")]
+use alloc::{
+    format,
+    rc::Rc,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+
+use crate::error::Result;
+use crate::renderer::{flatten_shape, ShapeData};
+use crate::shape::{Color, Shape};
+
+use core::{cell::RefCell, fmt::Write};
+use palette::{rgb::Rgba, FromColor};
+use tiny_skia::{Path, PathSegment, Point, Transform};
+
+// The longest line a curve is broken into, in millimetres.
+const CURVE_STEP: f32 = 0.5;
+
+// Ends closer than this, in millimetres, are joined without lifting the pen.
+const JOIN_DISTANCE: f32 = 0.01;
+
+// HPGL plotter units per millimetre.
+const HPGL_UNITS: f32 = 40.0;
+
+/// Settings for plotter output. Lengths are in millimetres.
+#[derive(Debug, Clone)]
+pub struct PlotConfig {
+    /// The width and height of the paper.
+    pub paper_size: (f32, f32),
+    /// The space left blank at each edge of the paper. The picture is scaled
+    /// to fit inside it and centered.
+    pub margin: f32,
+    /// How fast the pen moves while drawing, in millimetres per minute. Only
+    /// used for G-code.
+    pub feed_rate: f32,
+    /// The G-code that lifts the pen off the paper.
+    pub pen_up: String,
+    /// The G-code that puts the pen down on the paper.
+    pub pen_down: String,
+}
+
+impl Default for PlotConfig {
+    fn default() -> PlotConfig {
+        PlotConfig {
+            paper_size: (210.0, 297.0),
+            margin: 10.0,
+            feed_rate: 3000.0,
+            pen_up: "G0 Z5".into(),
+            pen_down: "G0 Z0".into(),
+        }
+    }
+}
+
+type Polyline = Vec<(f32, f32)>;
+
+// The lines drawn with one pen, named by its color.
+struct Pen {
+    color: String,
+    lines: Vec<Polyline>,
+}
+
+// Breaks the shape down into the lines each pen draws, in millimetres from
+// the bottom left of the paper. Images, text and background fills can't be
+// drawn with a pen and are left out, and filled shapes are outlined.
+fn plot_pens(
+    shape: Rc<RefCell<Shape>>,
+    width: u32,
+    height: u32,
+    config: &PlotConfig,
+) -> Result<Vec<Pen>> {
+    let (paper_width, paper_height) = config.paper_size;
+    let area_width = (paper_width - config.margin * 2.0).max(0.0);
+    let area_height = (paper_height - config.margin * 2.0).max(0.0);
+    let scale = (area_width / width as f32).min(area_height / height as f32);
+    let paper =
+        Transform::from_scale(scale, scale).post_translate(paper_width / 2.0, paper_height / 2.0);
+    let bounds = (
+        (paper_width - width as f32 * scale) / 2.0,
+        (paper_height - height as f32 * scale) / 2.0,
+        (paper_width + width as f32 * scale) / 2.0,
+        (paper_height + height as f32 * scale) / 2.0,
+    );
+
+    let mut pens: Vec<Pen> = Vec::new();
+    for shape_data in flatten_shape(shape)? {
+        let (path, transform, color) = match shape_data {
+            ShapeData::FillPath {
+                path,
+                transform,
+                color,
+                ..
+            } => (path, transform, color),
+            ShapeData::StrokePath {
+                path,
+                transform,
+                color,
+                stroke,
+                ..
+            } => match &stroke.dash {
+                Some(dash) => match path.dash(dash, 1.0) {
+                    Some(path) => (path, transform, color),
+                    None => continue,
+                },
+                None => (path, transform, color),
+            },
+            _ => continue,
+        };
+
+        let mut lines = Vec::new();
+        for line in flatten_path(&path, transform.post_concat(paper)) {
+            clip_polyline(&line, bounds, &mut lines);
+        }
+        if lines.is_empty() {
+            continue;
+        }
+
+        let color = pen_color(&color);
+        match pens.iter_mut().find(|pen| pen.color == color) {
+            Some(pen) => pen.lines.extend(lines),
+            None => pens.push(Pen { color, lines }),
+        }
+    }
+
+    for pen in &mut pens {
+        pen.lines = order_lines(core::mem::take(&mut pen.lines));
+    }
+    Ok(pens)
+}
+
+// Pens are told apart by color, ignoring transparency. Gradients are drawn
+// with the pen of their first color.
+fn pen_color(color: &Color) -> String {
+    let color = match color {
+        Color::Solid(color) => Rgba::from_color(*color),
+        Color::Gradient(gradient) => match gradient.stops.first() {
+            Some((_, color)) => Rgba::from_color(*color),
+            None => Rgba::new(1.0, 1.0, 1.0, 1.0),
+        },
+    };
+    let channel = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+    format!(
+        "#{:02x}{:02x}{:02x}",
+        channel(color.red),
+        channel(color.green),
+        channel(color.blue)
+    )
+}
+
+// Turns each subpath into a polyline, with the transform applied and curves
+// broken into short lines.
+fn flatten_path(path: &Path, transform: Transform) -> Vec<Polyline> {
+    let map = |p: Point| {
+        let mut p = [p];
+        transform.map_points(&mut p);
+        (p[0].x, p[0].y)
+    };
+
+    let mut lines = Vec::new();
+    let mut line: Polyline = Vec::new();
+    for segment in path.segments() {
+        match segment {
+            PathSegment::MoveTo(p) => {
+                if line.len() > 1 {
+                    lines.push(core::mem::take(&mut line));
+                }
+                line = vec![map(p)];
+            }
+            PathSegment::LineTo(p) => line.push(map(p)),
+            PathSegment::QuadTo(p1, p) => {
+                let p0 = *line.last().unwrap();
+                let (p1, p) = (map(p1), map(p));
+                let steps = curve_steps(&[p0, p1, p]);
+                for i in 1..=steps {
+                    let t = i as f32 / steps as f32;
+                    let u = 1.0 - t;
+                    line.push((
+                        u * u * p0.0 + 2.0 * u * t * p1.0 + t * t * p.0,
+                        u * u * p0.1 + 2.0 * u * t * p1.1 + t * t * p.1,
+                    ));
+                }
+            }
+            PathSegment::CubicTo(p1, p2, p) => {
+                let p0 = *line.last().unwrap();
+                let (p1, p2, p) = (map(p1), map(p2), map(p));
+                let steps = curve_steps(&[p0, p1, p2, p]);
+                for i in 1..=steps {
+                    let t = i as f32 / steps as f32;
+                    let u = 1.0 - t;
+                    line.push((
+                        u * u * u * p0.0
+                            + 3.0 * u * u * t * p1.0
+                            + 3.0 * u * t * t * p2.0
+                            + t * t * t * p.0,
+                        u * u * u * p0.1
+                            + 3.0 * u * u * t * p1.1
+                            + 3.0 * u * t * t * p2.1
+                            + t * t * t * p.1,
+                    ));
+                }
+            }
+            PathSegment::Close => {
+                if let Some(&start) = line.first() {
+                    line.push(start);
+                }
+            }
+        }
+    }
+    if line.len() > 1 {
+        lines.push(line);
+    }
+    lines
+}
+
+// Curves are split into pieces no longer than `CURVE_STEP`, going by the
+// length of their control polygon, which is never shorter than the curve.
+fn curve_steps(points: &[(f32, f32)]) -> usize {
+    let length: f32 = points.windows(2).map(|w| distance(w[0], w[1])).sum();
+    ((length / CURVE_STEP).ceil() as usize).clamp(1, 1000)
+}
+
+fn distance(a: (f32, f32), b: (f32, f32)) -> f32 {
+    distance_squared(a, b).sqrt()
+}
+
+fn distance_squared(a: (f32, f32), b: (f32, f32)) -> f32 {
+    (a.0 - b.0) * (a.0 - b.0) + (a.1 - b.1) * (a.1 - b.1)
+}
+
+// Cuts off the parts of a polyline outside the bounds, so the pen never
+// leaves the paper. A line that goes out and back in is split in two.
+fn clip_polyline(line: &Polyline, bounds: (f32, f32, f32, f32), lines: &mut Vec<Polyline>) {
+    let mut current: Polyline = Vec::new();
+    for w in line.windows(2) {
+        match clip_segment(w[0], w[1], bounds) {
+            Some((a, b)) => {
+                if current
+                    .last()
+                    .is_none_or(|&last| distance_squared(last, a) > 0.0)
+                {
+                    if current.len() > 1 {
+                        lines.push(core::mem::take(&mut current));
+                    }
+                    current = vec![a];
+                }
+                current.push(b);
+            }
+            None => {
+                if current.len() > 1 {
+                    lines.push(core::mem::take(&mut current));
+                }
+                current.clear();
+            }
+        }
+    }
+    if current.len() > 1 {
+        lines.push(current);
+    }
+}
+
+// Liang-Barsky line clipping.
+fn clip_segment(
+    a: (f32, f32),
+    b: (f32, f32),
+    (min_x, min_y, max_x, max_y): (f32, f32, f32, f32),
+) -> Option<((f32, f32), (f32, f32))> {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let mut t0: f32 = 0.0;
+    let mut t1: f32 = 1.0;
+    for (p, q) in [
+        (-dx, a.0 - min_x),
+        (dx, max_x - a.0),
+        (-dy, a.1 - min_y),
+        (dy, max_y - a.1),
+    ] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+        } else {
+            let r = q / p;
+            if p < 0.0 {
+                t0 = t0.max(r);
+            } else {
+                t1 = t1.min(r);
+            }
+        }
+    }
+
+    if t0 > t1 {
+        return None;
+    }
+    Some((
+        (a.0 + t0 * dx, a.1 + t0 * dy),
+        (a.0 + t1 * dx, a.1 + t1 * dy),
+    ))
+}
+
+// Puts the lines in an order that keeps the pen's travel short, always going
+// to the nearest end of the lines left and drawing backwards if that end is
+// the last. Lines that carry on from where the last one stopped are joined to
+// it, so the pen stays down.
+fn order_lines(mut lines: Vec<Polyline>) -> Vec<Polyline> {
+    let mut ordered: Vec<Polyline> = Vec::with_capacity(lines.len());
+    let mut position = (0.0, 0.0);
+
+    while !lines.is_empty() {
+        let mut nearest = (0, false, f32::INFINITY);
+        for (i, line) in lines.iter().enumerate() {
+            let start = distance_squared(position, line[0]);
+            if start < nearest.2 {
+                nearest = (i, false, start);
+            }
+            let end = distance_squared(position, line[line.len() - 1]);
+            if end < nearest.2 {
+                nearest = (i, true, end);
+            }
+        }
+
+        let (i, reverse, _) = nearest;
+        let mut line = lines.swap_remove(i);
+        if reverse {
+            line.reverse();
+        }
+        position = line[line.len() - 1];
+
+        match ordered.last_mut() {
+            Some(last) if distance(last[last.len() - 1], line[0]) < JOIN_DISTANCE => {
+                last.extend(line.into_iter().skip(1));
+            }
+            _ => ordered.push(line),
+        }
+    }
+
+    ordered
+}
+
+/// Draws a shape as G-code for a pen plotter. Each pen color is drawn in
+/// turn, with a pause to change pens between them.
+pub fn render_gcode(
+    shape: Rc<RefCell<Shape>>,
+    width: u32,
+    height: u32,
+    config: &PlotConfig,
+) -> Result<String> {
+    let pens = plot_pens(shape, width, height, config)?;
+
+    let mut gcode = String::new();
+    let _ = writeln!(gcode, "G21\nG90\n{}", config.pen_up);
+    for (i, pen) in pens.iter().enumerate() {
+        if i == 0 {
+            let _ = writeln!(gcode, "; Pen 1 ({})", pen.color);
+        } else {
+            let _ = writeln!(gcode, "G0 X0 Y0\nM0 ; Pen {} ({})", i + 1, pen.color);
+        }
+        for line in &pen.lines {
+            let _ = writeln!(gcode, "G0 X{:.3} Y{:.3}", line[0].0, line[0].1);
+            let _ = writeln!(gcode, "{}", config.pen_down);
+            for (i, (x, y)) in line.iter().enumerate().skip(1) {
+                if i == 1 {
+                    let _ = writeln!(gcode, "G1 X{:.3} Y{:.3} F{}", x, y, config.feed_rate);
+                } else {
+                    let _ = writeln!(gcode, "G1 X{:.3} Y{:.3}", x, y);
+                }
+            }
+            let _ = writeln!(gcode, "{}", config.pen_up);
+        }
+    }
+    gcode.push_str("G0 X0 Y0\nM2\n");
+    Ok(gcode)
+}
+
+/// Draws a shape as HPGL for a pen plotter, with each pen color given its own
+/// pen number.
+pub fn render_hpgl(
+    shape: Rc<RefCell<Shape>>,
+    width: u32,
+    height: u32,
+    config: &PlotConfig,
+) -> Result<String> {
+    let pens = plot_pens(shape, width, height, config)?;
+    let units = |(x, y): (f32, f32)| {
+        (
+            (x * HPGL_UNITS).round() as i32,
+            (y * HPGL_UNITS).round() as i32,
+        )
+    };
+
+    let mut hpgl = "IN;\n".to_string();
+    for (i, pen) in pens.iter().enumerate() {
+        let _ = writeln!(hpgl, "SP{};", i + 1);
+        for line in &pen.lines {
+            let (x, y) = units(line[0]);
+            let _ = write!(hpgl, "PU{},{};PD", x, y);
+            for (i, point) in line.iter().skip(1).enumerate() {
+                let (x, y) = units(*point);
+                if i > 0 {
+                    hpgl.push(',');
+                }
+                let _ = write!(hpgl, "{},{}", x, y);
+            }
+            hpgl.push_str(";\n");
+        }
+    }
+    hpgl.push_str("PU;SP0;\n");
+    Ok(hpgl)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::shape::PathSegment as ShapeSegment;
+
+    fn line(segments: Vec<ShapeSegment>, hue: f32) -> Rc<RefCell<Shape>> {
+        let mut shape = Shape::path(segments);
+        shape.set_hsl(hue, 1.0, 0.5);
+        shape.set_stroke_width(1.0);
+        Rc::new(RefCell::new(shape))
+    }
+
+    #[test]
+    fn test_order_lines() {
+        let lines = vec![
+            vec![(10.0, 0.0), (20.0, 0.0)],
+            vec![(5.0, 0.0), (1.0, 0.0)],
+            vec![(10.0, 0.0), (5.0, 0.0)],
+        ];
+        // The second line is drawn backwards from the origin, then the rest
+        // carry on from its end without lifting the pen.
+        assert_eq!(
+            order_lines(lines),
+            vec![vec![(1.0, 0.0), (5.0, 0.0), (10.0, 0.0), (20.0, 0.0)]]
+        );
+    }
+
+    #[test]
+    fn test_clip_polyline() {
+        let mut lines = Vec::new();
+        clip_polyline(
+            &vec![
+                (-5.0, 5.0),
+                (5.0, 5.0),
+                (5.0, 15.0),
+                (8.0, 15.0),
+                (8.0, 5.0),
+            ],
+            (0.0, 0.0, 10.0, 10.0),
+            &mut lines,
+        );
+        assert_eq!(
+            lines,
+            vec![
+                vec![(0.0, 5.0), (5.0, 5.0), (5.0, 10.0)],
+                vec![(8.0, 10.0), (8.0, 5.0)],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_render_gcode() {
+        let shape = Rc::new(RefCell::new(Shape::collection(vec![
+            line(
+                vec![
+                    ShapeSegment::MoveTo(0.0, 0.0),
+                    ShapeSegment::LineTo(50.0, 0.0),
+                ],
+                0.0,
+            ),
+            line(
+                vec![
+                    ShapeSegment::MoveTo(0.0, 0.0),
+                    ShapeSegment::QuadTo(25.0, 50.0, 50.0, 0.0),
+                ],
+                240.0,
+            ),
+        ])));
+        let config = PlotConfig {
+            paper_size: (120.0, 120.0),
+            margin: 10.0,
+            ..PlotConfig::default()
+        };
+
+        // The 100 pixel picture fills the 100 millimetres inside the margins.
+        let gcode = render_gcode(shape.clone(), 100, 100, &config).unwrap();
+        assert!(gcode.starts_with("G21\nG90\nG0 Z5\n; Pen 1 (#ff0000)\nG0 X60.000 Y60.000\n"));
+        assert!(gcode.contains("G1 X110.000 Y60.000 F3000\n"));
+        assert!(gcode.contains("M0 ; Pen 2 (#0000ff)\n"));
+        assert!(gcode.matches("G1 ").count() > 10);
+        assert!(gcode.ends_with("G0 Z5\nG0 X0 Y0\nM2\n"));
+
+        let hpgl = render_hpgl(shape, 100, 100, &config).unwrap();
+        assert!(hpgl.starts_with("IN;\nSP1;\nPU2400,2400;PD4400,2400;\nSP2;\n"));
+        assert!(hpgl.ends_with("PU;SP0;\n"));
+    }
+}
@@ -36,7 +36,7 @@ use {
     tiny_skia::IntSize,
 };
 
-use crate::error::Result;
+use crate::error::{Error, Result};
 use crate::shape::{
     BasicShape, Color, ColorChange, Gradient, HslaChange, ImageOp, ImagePath, PathSegment, Pattern,
     Shape, Style, IDENTITY, WHITE,
@@ -50,12 +50,13 @@ use tiny_skia::{
 };
 
 #[derive(Debug, Clone)]
-enum ShapeData<'a> {
+pub(crate) enum ShapeData<'a> {
     FillPath {
         path: Path,
         transform: Transform,
         fill_rule: FillRule,
         paint: Paint<'a>,
+        color: Color,
         zindex: f32,
         mask: Option<Vec<ShapeData<'a>>>,
         pattern: Option<(Vec<ShapeData<'a>>, SpreadMode)>,
@@ -65,6 +66,7 @@ enum ShapeData<'a> {
         transform: Transform,
         stroke: Stroke,
         paint: Paint<'a>,
+        color: Color,
         zindex: f32,
         mask: Option<Vec<ShapeData<'a>>>,
         pattern: Option<(Vec<ShapeData<'a>>, SpreadMode)>,
@@ -93,6 +95,7 @@ enum ShapeData<'a> {
     },
     FillPaint {
         paint: Paint<'a>,
+        gradient: Gradient,
         zindex: f32,
     },
 }
@@ -370,7 +373,7 @@ fn convert_shape_rec(
             let blend_mode = overwrite_blend_mode(*blend_mode, blend_mode_overwrite);
             let anti_alias = overwrite_anti_alias(*anti_alias, anti_alias_overwrite);
             let style = overwrite_style(style.clone(), style_overwrite);
-            let paint = match color {
+            let paint = match color.clone() {
                 Color::Solid(color) => solid_paint(Rgba::from_color(color), blend_mode, anti_alias),
                 Color::Gradient(gradient) => gradient_paint(gradient, blend_mode, anti_alias),
             };
@@ -395,6 +398,7 @@ fn convert_shape_rec(
                     transform,
                     fill_rule,
                     paint,
+                    color: color.clone(),
                     zindex,
                     mask,
                     pattern,
@@ -404,6 +408,7 @@ fn convert_shape_rec(
                     transform,
                     stroke,
                     paint,
+                    color,
                     zindex,
                     mask,
                     pattern,
@@ -432,7 +437,7 @@ fn convert_shape_rec(
             let blend_mode = overwrite_blend_mode(*blend_mode, blend_mode_overwrite);
             let anti_alias = overwrite_anti_alias(*anti_alias, anti_alias_overwrite);
             let style = overwrite_style(style.clone(), style_overwrite);
-            let paint = match color {
+            let paint = match color.clone() {
                 Color::Solid(color) => solid_paint(Rgba::from_color(color), blend_mode, anti_alias),
                 Color::Gradient(gradient) => gradient_paint(gradient, blend_mode, anti_alias),
             };
@@ -457,6 +462,7 @@ fn convert_shape_rec(
                     transform,
                     fill_rule,
                     paint,
+                    color: color.clone(),
                     zindex,
                     mask,
                     pattern,
@@ -466,6 +472,7 @@ fn convert_shape_rec(
                     transform,
                     stroke,
                     paint,
+                    color,
                     zindex,
                     mask,
                     pattern,
@@ -498,7 +505,7 @@ fn convert_shape_rec(
             let blend_mode = overwrite_blend_mode(*blend_mode, blend_mode_overwrite);
             let anti_alias = overwrite_anti_alias(*anti_alias, anti_alias_overwrite);
             let style = overwrite_style(style.clone(), style_overwrite);
-            let paint = match color {
+            let paint = match color.clone() {
                 Color::Solid(color) => solid_paint(Rgba::from_color(color), blend_mode, anti_alias),
                 Color::Gradient(gradient) => gradient_paint(gradient, blend_mode, anti_alias),
             };
@@ -523,6 +530,7 @@ fn convert_shape_rec(
                     transform,
                     fill_rule,
                     paint,
+                    color: color.clone(),
                     zindex,
                     mask,
                     pattern,
@@ -532,6 +540,7 @@ fn convert_shape_rec(
                     transform,
                     stroke,
                     paint,
+                    color,
                     zindex,
                     mask,
                     pattern,
@@ -622,8 +631,12 @@ fn convert_shape_rec(
                     ShapeData::Fill { zindex, color }
                 }
                 Color::Gradient(gradient) => {
-                    let paint = gradient_paint(gradient, BlendMode::SourceOver, true);
-                    ShapeData::FillPaint { zindex, paint }
+                    let paint = gradient_paint(gradient.clone(), BlendMode::SourceOver, true);
+                    ShapeData::FillPaint {
+                        zindex,
+                        paint,
+                        gradient,
+                    }
                 }
             });
         }
@@ -660,7 +673,7 @@ fn convert_shape_rec(
                 let blend_mode = overwrite_blend_mode(*blend_mode, blend_mode_overwrite);
                 let anti_alias = overwrite_anti_alias(*anti_alias, anti_alias_overwrite);
                 let style = overwrite_style(style.clone(), style_overwrite);
-                let paint = match color {
+                let paint = match color.clone() {
                     Color::Solid(color) => {
                         solid_paint(Rgba::from_color(color), blend_mode, anti_alias)
                     }
@@ -687,6 +700,7 @@ fn convert_shape_rec(
                         transform,
                         fill_rule,
                         paint,
+                        color: color.clone(),
                         zindex,
                         mask,
                         pattern,
@@ -696,6 +710,7 @@ fn convert_shape_rec(
                         transform,
                         stroke,
                         paint,
+                        color,
                         zindex,
                         mask,
                         pattern,
@@ -865,13 +880,15 @@ fn render_to_pixmap(
             pattern,
             ..
         } => {
-            let mask = mask.map(|data| {
-                let mut pixmap = Pixmap::new(width, height).unwrap();
-                for shape_data in data {
-                    render_to_pixmap(shape_data, &mut pixmap, width, height).unwrap();
-                }
-                Mask::from_pixmap(pixmap.as_ref(), MaskType::Luminance)
-            });
+            let mask = mask
+                .map(|data| {
+                    let mut pixmap = Pixmap::new(width, height).unwrap();
+                    for shape_data in data {
+                        render_to_pixmap(shape_data, &mut pixmap, width, height)?;
+                    }
+                    Ok::<_, Error>(Mask::from_pixmap(pixmap.as_ref(), MaskType::Luminance))
+                })
+                .transpose()?;
 
             match pattern {
                 Some((data, spread_mode)) => {
@@ -910,19 +927,21 @@ fn render_to_pixmap(
             pattern,
             ..
         } => {
-            let mask = mask.map(|data| {
-                let mut pixmap = Pixmap::new(width, height).unwrap();
-                for shape_data in data {
-                    render_to_pixmap(shape_data, &mut pixmap, width, height).unwrap();
-                }
-                Mask::from_pixmap(pixmap.as_ref(), MaskType::Luminance)
-            });
+            let mask = mask
+                .map(|data| {
+                    let mut pixmap = Pixmap::new(width, height).unwrap();
+                    for shape_data in data {
+                        render_to_pixmap(shape_data, &mut pixmap, width, height)?;
+                    }
+                    Ok::<_, Error>(Mask::from_pixmap(pixmap.as_ref(), MaskType::Luminance))
+                })
+                .transpose()?;
 
             match pattern {
                 Some((data, spread_mode)) => {
                     let mut pattern_pixmap = Pixmap::new(width, height).unwrap();
                     for shape_data in data {
-                        render_to_pixmap(shape_data, &mut pattern_pixmap, width, height).unwrap();
+                        render_to_pixmap(shape_data, &mut pattern_pixmap, width, height)?;
                     }
                     let shader = tiny_skia::Pattern::new(
                         pattern_pixmap.as_ref(),
@@ -948,59 +967,30 @@ fn render_to_pixmap(
         }
         #[cfg(all(feature = "std", feature = "io"))]
         ShapeData::Image {
-            ops,
             transform,
             paint,
             mask,
             ..
         }
         | ShapeData::Text {
-            ops,
             transform,
             paint,
             mask,
             ..
         } => {
-            let mut image = match shape_data {
-                ShapeData::Image { path, .. } => match path {
-                    ImagePath::File(path) => {
-                        ImageReader::open(path).unwrap().decode().unwrap().flipv()
-                    }
-                    ImagePath::Shape(shape) => {
-                        let pixmap = render(shape.clone(), width, height).unwrap();
-                        ImageReader::with_format(
-                            Cursor::new(pixmap.encode_png().unwrap()),
-                            ImageFormat::Png,
-                        )
-                        .decode()
-                        .unwrap()
-                    }
-                },
-                ShapeData::Text {
-                    font, text, size, ..
-                } => render_font(font, text, size),
-                _ => unreachable!(),
-            };
-
-            for op in ops {
-                apply_image_op(&mut image, op, width, height);
-            }
-
+            let image = load_image(&shape_data, width, height)?;
             let image_width = image.width();
             let image_height = image.height();
-            let image = Pixmap::from_vec(
-                image.into_bytes(),
-                IntSize::from_wh(image_width, image_height).unwrap(),
-            )
-            .unwrap();
 
-            let mask = mask.map(|data| {
-                let mut pixmap = Pixmap::new(width, height).unwrap();
-                for shape_data in data {
-                    render_to_pixmap(shape_data, &mut pixmap, width, height).unwrap();
-                }
-                Mask::from_pixmap(pixmap.as_ref(), MaskType::Luminance)
-            });
+            let mask = mask
+                .map(|data| {
+                    let mut pixmap = Pixmap::new(width, height).unwrap();
+                    for shape_data in data {
+                        render_to_pixmap(shape_data, &mut pixmap, width, height)?;
+                    }
+                    Ok::<_, Error>(Mask::from_pixmap(pixmap.as_ref(), MaskType::Luminance))
+                })
+                .transpose()?;
 
             let transform = transform
                 .post_scale(1.0, -1.0)
@@ -1015,7 +1005,7 @@ fn render_to_pixmap(
             );
         }
         #[cfg(not(all(feature = "std", feature = "io")))]
-        ShapeData::Image { .. } | ShapeData::Text { .. } => return Err(crate::Error::NoIO),
+        ShapeData::Image { .. } | ShapeData::Text { .. } => return Err(Error::NoIO),
         ShapeData::Fill { color, .. } => {
             pixmap.fill(color);
         }
@@ -1039,10 +1029,58 @@ fn render_to_pixmap(
     Ok(())
 }
 
+/// Loads the picture drawn by an image or text shape, with its image
+/// operations applied.
+#[cfg(all(feature = "std", feature = "io"))]
+pub(crate) fn load_image(shape_data: &ShapeData, width: u32, height: u32) -> Result<Pixmap> {
+    let (mut image, ops) = match shape_data {
+        ShapeData::Image { path, ops, .. } => {
+            let image = match path {
+                ImagePath::File(path) => ImageReader::open(path)
+                    .map_err(|e| Error::ImageError(path.clone(), e.to_string()))?
+                    .decode()
+                    .map_err(|e| Error::ImageError(path.clone(), e.to_string()))?
+                    .flipv(),
+                ImagePath::Shape(shape) => {
+                    let pixmap = render(shape.clone(), width, height)?;
+                    ImageReader::with_format(
+                        Cursor::new(pixmap.encode_png().unwrap()),
+                        ImageFormat::Png,
+                    )
+                    .decode()
+                    .unwrap()
+                }
+            };
+            (image, ops)
+        }
+        ShapeData::Text {
+            font,
+            text,
+            size,
+            ops,
+            ..
+        } => (render_font(font.clone(), text.clone(), *size)?, ops),
+        _ => unreachable!(),
+    };
+
+    for op in ops {
+        apply_image_op(&mut image, op.clone(), width, height)?;
+    }
+
+    let image_width = image.width();
+    let image_height = image.height();
+    Ok(Pixmap::from_vec(
+        image.into_bytes(),
+        IntSize::from_wh(image_width, image_height).unwrap(),
+    )
+    .unwrap())
+}
+
 #[cfg(all(feature = "std", feature = "io"))]
-fn render_font(path: String, text: String, size: f32) -> DynamicImage {
-    let font = fs::read(path).unwrap();
-    let font = Font::from_bytes(font, FontSettings::default()).unwrap();
+fn render_font(path: String, text: String, size: f32) -> Result<DynamicImage> {
+    let font = fs::read(&path).map_err(|e| Error::FontError(path.clone(), e.to_string()))?;
+    let font = Font::from_bytes(font, FontSettings::default())
+        .map_err(|e| Error::FontError(path.clone(), e.into()))?;
 
     let mut bitmaps = Vec::new();
     let mut width = 0;
@@ -1069,11 +1107,11 @@ fn render_font(path: String, text: String, size: f32) -> DynamicImage {
         x_offset += metrics.advance_width as u32;
     }
 
-    image.into()
+    Ok(image.into())
 }
 
 #[cfg(all(feature = "std", feature = "io"))]
-fn apply_image_op(image: &mut DynamicImage, op: ImageOp, width: u32, height: u32) {
+fn apply_image_op(image: &mut DynamicImage, op: ImageOp, width: u32, height: u32) -> Result<()> {
     match op {
         ImageOp::Brighten(value) => *image = image.brighten(value),
         ImageOp::Contrast(c) => *image = image.adjust_contrast(c),
@@ -1103,7 +1141,7 @@ fn apply_image_op(image: &mut DynamicImage, op: ImageOp, width: u32, height: u32
             image::Rgba::from_slice(&end),
         ),
         ImageOp::Overlay(top, x, y) => {
-            let top = render(top, width, height).unwrap();
+            let top = render(top, width, height)?;
             let top =
                 ImageReader::with_format(Cursor::new(top.encode_png().unwrap()), ImageFormat::Png)
                     .decode()
@@ -1111,7 +1149,7 @@ fn apply_image_op(image: &mut DynamicImage, op: ImageOp, width: u32, height: u32
             imageops::overlay(image, &top, x, y);
         }
         ImageOp::Replace(top, x, y) => {
-            let top = render(top, width, height).unwrap();
+            let top = render(top, width, height)?;
             let top =
                 ImageReader::with_format(Cursor::new(top.encode_png().unwrap()), ImageFormat::Png)
                     .decode()
@@ -1126,7 +1164,7 @@ fn apply_image_op(image: &mut DynamicImage, op: ImageOp, width: u32, height: u32
         ImageOp::Rotate270 => *image = image.rotate270(),
         ImageOp::Thumbnail(width, height) => *image = image.thumbnail(width, height),
         ImageOp::Tile(top) => {
-            let top = render(top, width, height).unwrap();
+            let top = render(top, width, height)?;
             let top =
                 ImageReader::with_format(Cursor::new(top.encode_png().unwrap()), ImageFormat::Png)
                     .decode()
@@ -1163,7 +1201,7 @@ fn apply_image_op(image: &mut DynamicImage, op: ImageOp, width: u32, height: u32
                 });
         }
         ImageOp::MatchHistogram(target) => {
-            let target = render(target, width, height).unwrap();
+            let target = render(target, width, height)?;
             let target = ImageReader::with_format(
                 Cursor::new(target.encode_png().unwrap()),
                 ImageFormat::Png,
@@ -1606,13 +1644,22 @@ fn apply_image_op(image: &mut DynamicImage, op: ImageOp, width: u32, height: u32
             *image = img.into_rgba8().into();
         }
     }
+
+    Ok(())
 }
 
-pub fn render(shape: Rc<RefCell<Shape>>, width: u32, height: u32) -> Result<Pixmap> {
+/// Breaks a shape down into the list of things to draw, resolving the
+/// overwrites of composite shapes and ordering it from the lowest z-index up.
+pub(crate) fn flatten_shape<'a>(shape: Rc<RefCell<Shape>>) -> Result<Vec<ShapeData<'a>>> {
     let mut data = Vec::new();
     convert_shape(&mut data, shape, IDENTITY)?;
     data.sort_by(|a, b| a.zindex().partial_cmp(&b.zindex()).unwrap());
+    Ok(data)
+}
 
+/// Draws a shape onto a new pixmap of the given size.
+pub fn render(shape: Rc<RefCell<Shape>>, width: u32, height: u32) -> Result<Pixmap> {
+    let data = flatten_shape(shape)?;
     let mut pixmap = Pixmap::new(width, height).unwrap();
     for shape_data in data {
         render_to_pixmap(shape_data, &mut pixmap, width, height)?;
@@ -1789,6 +1836,65 @@ mod tests {
         // This test just verifies the image path is handled without panic
         let result = render(shape, 100, 100);
         assert!(result.is_ok());
+
+        // Files that can't be loaded are reported rather than panicking
+        let image = |path: &str| {
+            Rc::new(RefCell::new(Shape::Image {
+                path: ImagePath::File(path.into()),
+                ops: vec![],
+                transform: Transform::identity(),
+                zindex: Some(0.0),
+                opacity: 1.0,
+                blend_mode: BlendMode::SourceOver,
+                quality: FilterQuality::Nearest,
+                mask: None,
+            }))
+        };
+        let result = render(image("missing.png"), 100, 100);
+        assert!(matches!(result, Err(Error::ImageError(path, _)) if path == "missing.png"));
+        let result = render(image("test.xylo"), 100, 100);
+        assert!(matches!(result, Err(Error::ImageError(path, _)) if path == "test.xylo"));
+
+        let text = Rc::new(RefCell::new(Shape::text(
+            "test.png".into(),
+            "xylo".into(),
+            12.0,
+        )));
+        let result = render(text, 100, 100);
+        assert!(matches!(result, Err(Error::FontError(path, _)) if path == "test.png"));
+    }
+
+    #[test]
+    #[cfg(all(feature = "std", feature = "io"))]
+    fn test_corrupt_files() {
+        // Files that exist and have the right extension, but can't be decoded
+        let dir = std::env::temp_dir().join("xylo_test_corrupt_files");
+        fs::create_dir_all(&dir).unwrap();
+        let image_path = dir.join("corrupt.png").to_string_lossy().into_owned();
+        let font_path = dir.join("corrupt.ttf").to_string_lossy().into_owned();
+        fs::write(&image_path, b"\x89PNG\r\n\x1a\nnot really a png").unwrap();
+        fs::write(&font_path, b"not really a font").unwrap();
+
+        let image = Rc::new(RefCell::new(Shape::Image {
+            path: ImagePath::File(image_path.clone()),
+            ops: vec![],
+            transform: Transform::identity(),
+            zindex: Some(0.0),
+            opacity: 1.0,
+            blend_mode: BlendMode::SourceOver,
+            quality: FilterQuality::Nearest,
+            mask: None,
+        }));
+        let result = render(image, 100, 100);
+        assert!(matches!(result, Err(Error::ImageError(path, _)) if path == image_path));
+
+        let text = Rc::new(RefCell::new(Shape::text(
+            font_path.clone(),
+            "xylo".into(),
+            12.0,
+        )));
+        let result = render(text, 100, 100);
+        assert!(matches!(result, Err(Error::FontError(path, _)) if path == font_path));
     }
 
     #[test]
@@ -0,0 +1,247 @@
+use rustyline::{error::ReadlineError, DefaultEditor};
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+use tiny_skia::Pixmap;
+use xylo_lang::{render, Config, Env, Error, Program, Result, Shape, Value};
+
+const HELP: &str = "\
+Enter an expression to evaluate it, or a definition to add it to the program.
+Definitions replace any earlier ones of the same name. Lines ending in `=`, an
+operator or an open bracket continue onto the next lines until an empty one.
+
+  :load <file>      Replace the program with the definitions in a file
+  :reload           Load the file again, keeping definitions entered since
+  :render [file]    Write the last shape to a PNG file (default: repl.png)
+  :preview          Draw the last shape in the terminal
+  :help             Show this message
+  :quit             Exit";
+
+// Columns used by `:preview`, with each character drawing two pixels.
+const PREVIEW_WIDTH: u32 = 64;
+
+// What the program is made from. It gets rebuilt from scratch whenever this
+// changes, so the file is read again each time.
+struct Session {
+    config: Config,
+    file: Option<PathBuf>,
+    definitions: Vec<String>,
+}
+
+impl Session {
+    // Builds the program, making sure it can also be run. Errors come back
+    // as diagnostics ready to print.
+    fn program(&self) -> core::result::Result<Program, String> {
+        let mut program = match &self.file {
+            Some(path) => Program::from_file(path, &self.config)
+                .map_err(|e| Program::diagnostic_for_file(path, &self.config, &e))?,
+            None => Program::from_source("", &self.config).map_err(|e| e.to_string())?,
+        };
+
+        for code in &self.definitions {
+            program.define(code).map_err(|e| program.diagnostic(&e))?;
+        }
+
+        if let Err(e) = Env::new(&program, self.config.clone()) {
+            return Err(program.diagnostic(&e));
+        }
+        Ok(program)
+    }
+}
+
+enum Change {
+    Define(String),
+    Load(PathBuf),
+    Reload,
+    Quit,
+}
+
+pub fn run(config: Config, file: Option<PathBuf>) -> Result<()> {
+    let mut editor = DefaultEditor::new().map_err(readline_error)?;
+    let history = std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".xylo_history"));
+    if let Some(history) = &history {
+        let _ = editor.load_history(history);
+    }
+
+    let mut session = Session {
+        config,
+        file,
+        definitions: Vec::new(),
+    };
+    let mut program = match session.program() {
+        Ok(program) => program,
+        Err(e) => {
+            eprintln!("{}", e);
+            session.file = None;
+            session.program().unwrap_or_default()
+        }
+    };
+    let mut shape: Option<Rc<RefCell<Shape>>> = None;
+
+    println!("Xylo {}. Type :help for help.", env!("CARGO_PKG_VERSION"));
+
+    loop {
+        let change = {
+            let mut env = Env::new(&program, session.config.clone())?;
+            loop {
+                let input = match read_input(&mut editor) {
+                    Ok(input) => input,
+                    Err(ReadlineError::Interrupted) => continue,
+                    Err(ReadlineError::Eof) => break Change::Quit,
+                    Err(e) => return Err(readline_error(e)),
+                };
+                let input = input.trim();
+
+                let (command, arg) = match input.strip_prefix(':') {
+                    Some(command) => match command.split_once(char::is_whitespace) {
+                        Some((command, arg)) => (Some(command), arg.trim()),
+                        None => (Some(command), ""),
+                    },
+                    None => (None, input),
+                };
+
+                match command {
+                    None if input.is_empty() => (),
+                    None if is_definition(input, &session.config) => {
+                        break Change::Define(input.into())
+                    }
+                    None => match env.eval(input) {
+                        Ok(Value::Shape(value)) => {
+                            println!("<shape>");
+                            shape = Some(value);
+                        }
+                        Ok(value) => println!("{}", value),
+                        Err(e) => match e.definition() {
+                            // Errors in the expression itself are located
+                            // within it, rather than in the program.
+                            None if e.span().is_some() => {
+                                eprintln!("{}", e.diagnostic("<repl>", input))
+                            }
+                            _ => eprintln!("{}", program.diagnostic(&e)),
+                        },
+                    },
+                    Some("load" | "l") if !arg.is_empty() => break Change::Load(arg.into()),
+                    Some("reload" | "r") => break Change::Reload,
+                    Some("render") => {
+                        let path = if arg.is_empty() { "repl.png" } else { arg };
+                        match draw(&shape, &session.config) {
+                            Some(Ok(pixmap)) => match pixmap.save_png(path) {
+                                Ok(()) => println!("Output to {:?}", path),
+                                Err(e) => eprintln!("error: {}", e),
+                            },
+                            Some(Err(e)) => eprintln!("error: {}", e),
+                            None => eprintln!("error: No shape has been evaluated yet."),
+                        }
+                    }
+                    Some("preview" | "p") => match draw(&shape, &session.config) {
+                        Some(Ok(pixmap)) => print!("{}", preview(&pixmap)),
+                        Some(Err(e)) => eprintln!("error: {}", e),
+                        None => eprintln!("error: No shape has been evaluated yet."),
+                    },
+                    Some("help" | "h" | "?") => println!("{}", HELP),
+                    Some("quit" | "q") => break Change::Quit,
+                    Some(command) => eprintln!("error: Unknown command `:{}`.", command),
+                }
+            }
+        };
+
+        // Changes that fail to load are rolled back, leaving the program as
+        // it was.
+        let previous = (session.file.clone(), session.definitions.clone());
+        match change {
+            Change::Define(code) => session.definitions.push(code),
+            Change::Load(path) => {
+                session.file = Some(path);
+                session.definitions.clear();
+            }
+            Change::Reload => (),
+            Change::Quit => break,
+        }
+
+        match session.program() {
+            Ok(new_program) => program = new_program,
+            Err(e) => {
+                eprintln!("{}", e);
+                (session.file, session.definitions) = previous;
+            }
+        }
+    }
+
+    if let Some(history) = &history {
+        let _ = editor.save_history(history);
+    }
+    Ok(())
+}
+
+fn read_input(editor: &mut DefaultEditor) -> core::result::Result<String, ReadlineError> {
+    let mut input = editor.readline("xylo> ")?;
+    if opens_block(&input) {
+        loop {
+            let line = editor.readline("  ... ")?;
+            if line.trim().is_empty() {
+                break;
+            }
+            input.push('\n');
+            input.push_str(&line);
+        }
+    }
+    let _ = editor.add_history_entry(input.as_str());
+    Ok(input)
+}
+
+// A line that leaves something open carries on over the following lines, up to
+// an empty one, so that indented blocks can be entered.
+fn opens_block(line: &str) -> bool {
+    line.trim_end()
+        .ends_with(|c| "=([{,+-*/%^:|&<>".contains(c))
+}
+
+fn is_definition(input: &str, config: &Config) -> bool {
+    input.starts_with("import ")
+        || Program::from_source(input, config)
+            .and_then(|program| Ok(!program.tree()?.is_empty()))
+            .unwrap_or(false)
+}
+
+fn draw(shape: &Option<Rc<RefCell<Shape>>>, config: &Config) -> Option<Result<Pixmap>> {
+    let (width, height) = config.dimensions;
+    shape
+        .as_ref()
+        .map(|shape| render(shape.clone(), width, height))
+}
+
+// Draws the pixmap with coloured half blocks, each character showing one pixel
+// on top of another. Transparent areas come out black.
+fn preview(pixmap: &Pixmap) -> String {
+    let columns = PREVIEW_WIDTH.min(pixmap.width());
+    let step = pixmap.width() as f32 / columns as f32;
+    let rows = (pixmap.height() as f32 / (step * 2.0)).ceil() as u32;
+
+    let pixel = |x: u32, y: f32| {
+        let x = (x as f32 * step) as u32;
+        let y = (y as u32).min(pixmap.height() - 1);
+        let color = pixmap.pixel(x, y).unwrap();
+        (color.red(), color.green(), color.blue())
+    };
+
+    let mut output = String::new();
+    for row in 0..rows {
+        for column in 0..columns {
+            let (tr, tg, tb) = pixel(column, row as f32 * step * 2.0);
+            let (br, bg, bb) = pixel(column, (row as f32 * 2.0 + 1.0) * step);
+            output.push_str(&format!(
+                "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m▀",
+                tr, tg, tb, br, bg, bb
+            ));
+        }
+        output.push_str("\x1b[0m\n");
+    }
+    output
+}
+
+fn readline_error(e: ReadlineError) -> Error {
+    match e {
+        ReadlineError::Io(e) => Error::FileError(e),
+        e => Error::FileError(std::io::Error::other(e)),
+    }
+}
@@ -454,6 +454,32 @@ pub enum Shape {
 }
 
 impl Shape {
+    /// Counts the shapes this one is made of, stopping once it's past `limit`
+    /// since shapes can be reused many times over.
+    pub fn count(&self, limit: usize) -> usize {
+        match self {
+            Self::Composite { a, b, .. } => {
+                let count = a.borrow().count(limit);
+                if count > limit {
+                    count
+                } else {
+                    count + b.borrow().count(limit - count)
+                }
+            }
+            Self::Collection { shapes, .. } => {
+                let mut count = 0;
+                for shape in shapes {
+                    count += shape.borrow().count(limit - count);
+                    if count > limit {
+                        break;
+                    }
+                }
+                count
+            }
+            _ => 1,
+        }
+    }
+
     pub fn square() -> Self {
         Self::Basic(SQUARE.clone(), None, None)
     }
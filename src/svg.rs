@@ -0,0 +1,539 @@
+#[cfg(feature = "std")]
+use std::rc::Rc;
+
+#[cfg(feature = "alloc")]
+use alloc::{
+    format,
+    rc::Rc,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+#[cfg(all(feature = "std", feature = "io"))]
+use {crate::renderer::load_image, base64::prelude::*, tiny_skia::FilterQuality};
+
+#[cfg(not(all(feature = "std", feature = "io")))]
+use crate::error::Error;
+
+use crate::error::Result;
+use crate::renderer::{flatten_shape, ShapeData};
+use crate::shape::{Color, Gradient, Shape};
+
+use core::{cell::RefCell, fmt::Write};
+use hashbrown::HashMap;
+use palette::{rgb::Rgba, FromColor, Hsla};
+use tiny_skia::{BlendMode, FillRule, LineCap, LineJoin, Path, PathSegment, SpreadMode, Transform};
+
+// Builds up the document. Gradients, masks and patterns go in `<defs>`, with
+// identical ones shared, since masks and patterns set on a collection are
+// copied to every shape inside it.
+struct SvgWriter {
+    width: u32,
+    height: u32,
+    defs: String,
+    ids: HashMap<String, String>,
+}
+
+impl SvgWriter {
+    // Moves shape coordinates, which are centered with y pointing up, into
+    // the document's.
+    fn screen(&self, transform: Transform) -> Transform {
+        transform
+            .post_scale(1.0, -1.0)
+            .post_translate(self.width as f32 / 2.0, self.height as f32 / 2.0)
+    }
+
+    fn define(&mut self, tag: &str, attrs: String, content: String) -> String {
+        let key = format!("{}{}>{}", tag, attrs, content);
+        if let Some(id) = self.ids.get(&key) {
+            return format!("url(#{})", id);
+        }
+
+        let id = format!("{}{}", tag, self.ids.len());
+        let _ = write!(
+            self.defs,
+            "<{} id=\"{}\"{}>{}</{}>",
+            tag, id, attrs, content, tag
+        );
+        self.ids.insert(key, id.clone());
+        format!("url(#{})", id)
+    }
+
+    fn shapes(&mut self, data: Vec<ShapeData>) -> Result<String> {
+        let mut output = String::new();
+        for shape_data in data {
+            output.push_str(&self.shape(shape_data)?);
+        }
+        Ok(output)
+    }
+
+    fn shape(&mut self, shape_data: ShapeData) -> Result<String> {
+        let (element, mask) = match shape_data.clone() {
+            ShapeData::FillPath {
+                path,
+                transform,
+                fill_rule,
+                paint,
+                color,
+                mask,
+                pattern,
+                ..
+            } => {
+                let (fill, opacity) = match pattern {
+                    Some((data, spread_mode)) => (self.pattern(data, spread_mode)?, 1.0),
+                    None => self.paint(color),
+                };
+
+                let mut attrs = format!(" fill=\"{}\"", fill);
+                if opacity < 1.0 {
+                    let _ = write!(attrs, " fill-opacity=\"{}\"", opacity);
+                }
+                if fill_rule == FillRule::EvenOdd {
+                    attrs.push_str(" fill-rule=\"evenodd\"");
+                }
+                if !paint.anti_alias {
+                    attrs.push_str(" shape-rendering=\"crispEdges\"");
+                }
+                attrs.push_str(&blend_style(paint.blend_mode));
+
+                let element = format!(
+                    "<path d=\"{}\"{}{}/>",
+                    path_data(&path),
+                    matrix(self.screen(transform)),
+                    attrs
+                );
+                (element, mask)
+            }
+            ShapeData::StrokePath {
+                path,
+                transform,
+                stroke,
+                paint,
+                color,
+                mask,
+                pattern,
+                ..
+            } => {
+                let (fill, opacity) = match pattern {
+                    Some((data, spread_mode)) => (self.pattern(data, spread_mode)?, 1.0),
+                    None => self.paint(color),
+                };
+
+                let mut attrs = format!(
+                    " fill=\"none\" stroke=\"{}\" stroke-width=\"{}\"",
+                    fill, stroke.width
+                );
+                if opacity < 1.0 {
+                    let _ = write!(attrs, " stroke-opacity=\"{}\"", opacity);
+                }
+                match stroke.line_cap {
+                    LineCap::Butt => (),
+                    LineCap::Round => attrs.push_str(" stroke-linecap=\"round\""),
+                    LineCap::Square => attrs.push_str(" stroke-linecap=\"square\""),
+                }
+                match stroke.line_join {
+                    LineJoin::Miter => (),
+                    LineJoin::MiterClip => attrs.push_str(" stroke-linejoin=\"miter-clip\""),
+                    LineJoin::Round => attrs.push_str(" stroke-linejoin=\"round\""),
+                    LineJoin::Bevel => attrs.push_str(" stroke-linejoin=\"bevel\""),
+                }
+                if stroke.miter_limit != 4.0 {
+                    let _ = write!(attrs, " stroke-miterlimit=\"{}\"", stroke.miter_limit);
+                }
+                if !paint.anti_alias {
+                    attrs.push_str(" shape-rendering=\"crispEdges\"");
+                }
+                attrs.push_str(&blend_style(paint.blend_mode));
+
+                // tiny-skia doesn't give back the dash array, so the dashes
+                // are cut out of the path instead, the same way it does when
+                // rasterizing.
+                let path = match &stroke.dash {
+                    Some(dash) => match path.dash(dash, 1.0) {
+                        Some(path) => path,
+                        None => return Ok(String::new()),
+                    },
+                    None => path,
+                };
+
+                let element = format!(
+                    "<path d=\"{}\"{}{}/>",
+                    path_data(&path),
+                    matrix(self.screen(transform)),
+                    attrs
+                );
+                (element, mask)
+            }
+            #[cfg(all(feature = "std", feature = "io"))]
+            ShapeData::Image {
+                transform,
+                paint,
+                mask,
+                ..
+            }
+            | ShapeData::Text {
+                transform,
+                paint,
+                mask,
+                ..
+            } => {
+                let image = load_image(&shape_data, self.width, self.height)?;
+                let data = image.encode_png().unwrap();
+
+                let mut attrs = String::new();
+                if paint.opacity < 1.0 {
+                    let _ = write!(attrs, " opacity=\"{}\"", paint.opacity);
+                }
+                let mut style = blend_style(paint.blend_mode);
+                if paint.quality == FilterQuality::Nearest {
+                    style = match style.strip_suffix('"') {
+                        Some(style) => format!("{};image-rendering:pixelated\"", style),
+                        None => " style=\"image-rendering:pixelated\"".into(),
+                    };
+                }
+                attrs.push_str(&style);
+
+                let element = format!(
+                    "<image x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" \
+                     preserveAspectRatio=\"none\"{}{} \
+                     xlink:href=\"data:image/png;base64,{}\"/>",
+                    -((image.width() / 2) as i32),
+                    -((image.height() / 2) as i32),
+                    image.width(),
+                    image.height(),
+                    matrix(self.screen(transform)),
+                    attrs,
+                    BASE64_STANDARD.encode(data),
+                );
+                (element, mask)
+            }
+            #[cfg(not(all(feature = "std", feature = "io")))]
+            ShapeData::Image { .. } | ShapeData::Text { .. } => return Err(Error::NoIO),
+            ShapeData::Fill { color, .. } => {
+                let fill = self.paint(Color::Solid(Hsla::from_color(Rgba::new(
+                    color.red(),
+                    color.green(),
+                    color.blue(),
+                    color.alpha(),
+                ))));
+                let element = format!(
+                    "<rect width=\"{}\" height=\"{}\"{}/>",
+                    self.width,
+                    self.height,
+                    fill_attrs(fill)
+                );
+                (element, None)
+            }
+            ShapeData::FillPaint { gradient, .. } => {
+                let fill = self.paint(Color::Gradient(gradient));
+                let element = format!(
+                    "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\"{}{}/>",
+                    -(self.width as f32) / 2.0,
+                    -(self.height as f32) / 2.0,
+                    self.width,
+                    self.height,
+                    matrix(self.screen(Transform::identity())),
+                    fill_attrs(fill)
+                );
+                (element, None)
+            }
+        };
+
+        // The mask is wrapped around the element so that it isn't moved by
+        // the element's own transform, as masks are already in place.
+        match mask {
+            Some(data) => {
+                let content = self.shapes(data)?;
+                let attrs = format!(
+                    " maskUnits=\"userSpaceOnUse\" x=\"0\" y=\"0\" width=\"{}\" height=\"{}\"",
+                    self.width, self.height
+                );
+                let mask = self.define("mask", attrs, content);
+                Ok(format!("<g mask=\"{}\">{}</g>", mask, element))
+            }
+            None => Ok(element),
+        }
+    }
+
+    // Gives the value to paint with, and its opacity.
+    fn paint(&mut self, color: Color) -> (String, f32) {
+        match color {
+            Color::Solid(color) => color_value(color),
+            Color::Gradient(gradient) => match self.gradient(gradient) {
+                Some(url) => (url, 1.0),
+                None => ("#ffffff".into(), 1.0),
+            },
+        }
+    }
+
+    fn gradient(&mut self, gradient: Gradient) -> Option<String> {
+        // Mirrors the gradients tiny-skia can't draw, which fall back to white.
+        if gradient.stops.is_empty()
+            || gradient.radius.is_none() && gradient.start == gradient.end
+            || gradient.radius.is_some() && gradient.radius.unwrap() <= 0.0
+        {
+            return None;
+        }
+
+        let mut content = String::new();
+        for (pos, color) in &gradient.stops {
+            let (color, opacity) = color_value(*color);
+            let _ = write!(
+                content,
+                "<stop offset=\"{}\" stop-color=\"{}\"",
+                pos.clamp(0.0, 1.0),
+                color
+            );
+            if opacity < 1.0 {
+                let _ = write!(content, " stop-opacity=\"{}\"", opacity);
+            }
+            content.push_str("/>");
+        }
+
+        let (start_x, start_y) = gradient.start;
+        let (end_x, end_y) = gradient.end;
+        let mut attrs = " gradientUnits=\"userSpaceOnUse\"".to_string();
+        match gradient.spread_mode {
+            SpreadMode::Pad => (),
+            SpreadMode::Reflect => attrs.push_str(" spreadMethod=\"reflect\""),
+            SpreadMode::Repeat => attrs.push_str(" spreadMethod=\"repeat\""),
+        }
+        if !gradient.transform.is_identity() {
+            let _ = write!(
+                attrs,
+                " gradientTransform=\"{}\"",
+                matrix_value(gradient.transform)
+            );
+        }
+
+        Some(match gradient.radius {
+            // tiny-skia's radial gradients go from a point at the start to a
+            // circle around the end, which is SVG's focal point and circle.
+            Some(radius) => {
+                let _ = write!(
+                    attrs,
+                    " fx=\"{}\" fy=\"{}\" cx=\"{}\" cy=\"{}\" r=\"{}\"",
+                    start_x, start_y, end_x, end_y, radius
+                );
+                self.define("radialGradient", attrs, content)
+            }
+            None => {
+                let _ = write!(
+                    attrs,
+                    " x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\"",
+                    start_x, start_y, end_x, end_y
+                );
+                self.define("linearGradient", attrs, content)
+            }
+        })
+    }
+
+    // Patterns are drawn over the whole document and tiled from there. SVG
+    // only repeats patterns, so the other spread modes are drawn the same way.
+    fn pattern(&mut self, data: Vec<ShapeData>, _spread_mode: SpreadMode) -> Result<String> {
+        let content = self.shapes(data)?;
+        let attrs = format!(
+            " patternUnits=\"userSpaceOnUse\" width=\"{}\" height=\"{}\"",
+            self.width, self.height
+        );
+        Ok(self.define("pattern", attrs, content))
+    }
+}
+
+fn color_value(color: Hsla<f32>) -> (String, f32) {
+    let color = Rgba::from_color(color);
+    let channel = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+    (
+        format!(
+            "#{:02x}{:02x}{:02x}",
+            channel(color.red),
+            channel(color.green),
+            channel(color.blue)
+        ),
+        color.alpha.clamp(0.0, 1.0),
+    )
+}
+
+fn fill_attrs((fill, opacity): (String, f32)) -> String {
+    if opacity < 1.0 {
+        format!(" fill=\"{}\" fill-opacity=\"{}\"", fill, opacity)
+    } else {
+        format!(" fill=\"{}\"", fill)
+    }
+}
+
+fn matrix_value(ts: Transform) -> String {
+    format!(
+        "matrix({} {} {} {} {} {})",
+        ts.sx, ts.ky, ts.kx, ts.sy, ts.tx, ts.ty
+    )
+}
+
+fn matrix(ts: Transform) -> String {
+    format!(" transform=\"{}\"", matrix_value(ts))
+}
+
+fn path_data(path: &Path) -> String {
+    let mut d = String::new();
+    for segment in path.segments() {
+        let _ = match segment {
+            PathSegment::MoveTo(p) => write!(d, "M{} {}", p.x, p.y),
+            PathSegment::LineTo(p) => write!(d, "L{} {}", p.x, p.y),
+            PathSegment::QuadTo(p1, p) => write!(d, "Q{} {} {} {}", p1.x, p1.y, p.x, p.y),
+            PathSegment::CubicTo(p1, p2, p) => {
+                write!(d, "C{} {} {} {} {} {}", p1.x, p1.y, p2.x, p2.y, p.x, p.y)
+            }
+            PathSegment::Close => write!(d, "Z"),
+        };
+    }
+    d
+}
+
+// Blend modes without a CSS equivalent are drawn normally.
+fn blend_style(blend_mode: BlendMode) -> String {
+    let mode = match blend_mode {
+        BlendMode::Plus => "plus-lighter",
+        BlendMode::Multiply => "multiply",
+        BlendMode::Screen => "screen",
+        BlendMode::Overlay => "overlay",
+        BlendMode::Darken => "darken",
+        BlendMode::Lighten => "lighten",
+        BlendMode::ColorDodge => "color-dodge",
+        BlendMode::ColorBurn => "color-burn",
+        BlendMode::HardLight => "hard-light",
+        BlendMode::SoftLight => "soft-light",
+        BlendMode::Difference => "difference",
+        BlendMode::Exclusion => "exclusion",
+        BlendMode::Hue => "hue",
+        BlendMode::Saturation => "saturation",
+        BlendMode::Color => "color",
+        BlendMode::Luminosity => "luminosity",
+        _ => return String::new(),
+    };
+    format!(" style=\"mix-blend-mode:{}\"", mode)
+}
+
+/// Draws a shape as an SVG document of the given size, keeping paths,
+/// gradients and masks as vectors. Images and text are embedded as PNGs.
+pub fn render_svg(shape: Rc<RefCell<Shape>>, width: u32, height: u32) -> Result<String> {
+    let data = flatten_shape(shape)?;
+    let mut writer = SvgWriter {
+        width,
+        height,
+        defs: String::new(),
+        ids: HashMap::new(),
+    };
+    let body = writer.shapes(data)?;
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" \
+         xmlns:xlink=\"http://www.w3.org/1999/xlink\" \
+         width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\">",
+        width, height, width, height
+    );
+    if !writer.defs.is_empty() {
+        let _ = write!(svg, "<defs>{}</defs>", writer.defs);
+    }
+    svg.push_str(&body);
+    svg.push_str("</svg>\n");
+    Ok(svg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "alloc")]
+    use alloc::vec;
+
+    use crate::shape::{BasicShape, PathSegment, Style};
+    use tiny_skia::{Stroke, StrokeDash};
+
+    #[test]
+    fn test_render_svg() {
+        let mut gradient = Gradient::linear(0.0, 0.0, 10.0, 0.0);
+        gradient.set_stop_hsl(0.0, 0.0, 1.0, 0.5);
+        gradient.set_stop_hsl(1.0, 240.0, 1.0, 0.5);
+
+        let circle = Rc::new(RefCell::new(Shape::Basic(
+            BasicShape::Circle {
+                x: 0.0,
+                y: 0.0,
+                radius: 10.0,
+                transform: Transform::from_translate(5.0, 5.0),
+                zindex: Some(1.0),
+                color: Color::Gradient(gradient),
+                blend_mode: BlendMode::Multiply,
+                anti_alias: true,
+                style: Style::Fill(FillRule::EvenOdd),
+            },
+            None,
+            None,
+        )));
+        let mut line = Shape::path(vec![
+            PathSegment::MoveTo(0.0, 0.0),
+            PathSegment::LineTo(10.0, 0.0),
+        ]);
+        line.set_hsla(0.0, 0.0, 1.0, 0.5);
+        line.set_zindex(0.0);
+        line.set_stroke_width(2.0);
+        line.set_mask(Rc::new(RefCell::new(Shape::square())));
+        let shape = Rc::new(RefCell::new(Shape::collection(vec![
+            circle,
+            Rc::new(RefCell::new(line)),
+        ])));
+
+        let svg = render_svg(shape, 100, 50).unwrap();
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\""));
+        assert!(svg.contains("viewBox=\"0 0 100 50\""));
+        assert!(svg.ends_with("</svg>\n"));
+
+        // Lower z-indexes come first.
+        let circle = svg.find("fill-rule=\"evenodd\"").unwrap();
+        let line = svg.find("stroke-width=\"2\"").unwrap();
+        assert!(line < circle);
+
+        assert!(svg.contains("<linearGradient id=\"linearGradient"));
+        assert!(svg.contains("style=\"mix-blend-mode:multiply\""));
+        assert!(svg.contains("matrix(1 0 0 -1 55 20)"));
+        assert!(svg.contains("stroke=\"#ffffff\" stroke-width=\"2\" stroke-opacity=\"0.5\""));
+        assert!(svg.contains("<g mask=\"url(#mask"));
+    }
+
+    #[test]
+    fn test_render_svg_dash() {
+        let mut line = Shape::path(vec![
+            PathSegment::MoveTo(0.0, 0.0),
+            PathSegment::LineTo(10.0, 0.0),
+        ]);
+        line.set_stroke_width(1.0);
+        line.set_dash(StrokeDash::new(vec![2.0, 3.0], 0.0));
+        let svg = render_svg(Rc::new(RefCell::new(line)), 20, 20).unwrap();
+        assert!(svg.contains("d=\"M0 0L2 0M5 0L7 0\""));
+
+        let square = Shape::Basic(
+            BasicShape::Square {
+                x: -1.0,
+                y: -1.0,
+                width: 2.0,
+                height: 2.0,
+                transform: Transform::identity(),
+                zindex: None,
+                color: Color::Solid(Hsla::new(0.0, 0.0, 0.0, 1.0)),
+                blend_mode: BlendMode::SourceOver,
+                anti_alias: false,
+                style: Style::Stroke(Stroke {
+                    width: 0.5,
+                    line_join: LineJoin::Round,
+                    ..Stroke::default()
+                }),
+            },
+            None,
+            None,
+        );
+        let svg = render_svg(Rc::new(RefCell::new(square)), 20, 20).unwrap();
+        assert!(svg.contains("stroke-linejoin=\"round\""));
+        assert!(svg.contains("shape-rendering=\"crispEdges\""));
+        assert!(!svg.contains("mix-blend-mode"));
+    }
+}